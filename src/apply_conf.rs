@@ -1,328 +1,2490 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs;
+use std::io;
 use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context};
-use log::{debug, info, warn};
+use configparser::ini::Ini;
+use log::{debug, error, info, warn};
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
-use nmstate::InterfaceType;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
-use crate::types::Host;
-use crate::{ALL_HOSTS_DIR, HOST_MAPPING_FILE};
+use crate::archive;
+use crate::error::ConfigError;
+use crate::generate_conf::{
+    mapping_file_path, read_mapping, referenced_files, STDIN_CONFIG_DIR, WIREGUARD_SECTION,
+};
+use crate::keyfile;
+use crate::state::{self, ApplyState, STATE_FILE};
+use crate::timing::Timings;
+use crate::types::{
+    validate_unique_mac_addresses, ApplyReport, Host, Interface, InterfaceReport, InterfaceType,
+};
+use crate::{ALL_HOSTS_DIR, HOSTNAME_DISPATCHER_FILE};
 
 /// Destination directory to store the *.nmconnection files for NetworkManager.
-const STATIC_SYSTEM_CONNECTIONS_DIR: &str = "/etc/NetworkManager/system-connections";
+pub const STATIC_SYSTEM_CONNECTIONS_DIR: &str = "/etc/NetworkManager/system-connections";
 const RUNTIME_SYSTEM_CONNECTIONS_DIR: &str = "/var/run/NetworkManager/system-connections";
 /// Configuration directory for NetworkManager options.
-const CONFIG_DIR: &str = "/etc/NetworkManager/conf.d";
+pub const CONFIG_DIR: &str = "/etc/NetworkManager/conf.d";
+/// Destination directory of the certificates and keys referenced by the connection files.
+pub const CERTS_DIR: &str = "/etc/NetworkManager/certs";
 const CONNECTION_FILE_EXT: &str = "nmconnection";
-const HOSTNAME_FILE: &str = "/etc/hostname";
+pub(crate) const HOSTNAME_FILE: &str = "/etc/hostname";
+/// Directory of the scripts executed by NetworkManager on network events.
+pub(crate) const DISPATCHER_DIR: &str = "/etc/NetworkManager/dispatcher.d";
+/// Transient hostname of the running kernel.
+const KERNEL_HOSTNAME_FILE: &str = "/proc/sys/kernel/hostname";
+/// Directory exposing the attributes (e.g. link speed) of the local network devices.
+const SYS_CLASS_NET_DIR: &str = "/sys/class/net";
+/// Directory of the systemd `.link` files pinning the names of the network interfaces.
+pub(crate) const SYSTEMD_NETWORK_DIR: &str = "/etc/systemd/network";
+/// Filename prefix of the `.link` files written by `apply`.
+pub(crate) const LINK_FILE_PREFIX: &str = "10-nmc-";
+/// Commands asking udev to re-process the network devices (applying `.link` files) and waiting for it.
+const UDEV_SETTLE_COMMANDS: &[&[&str]] = &[
+    &[
+        "udevadm",
+        "trigger",
+        "--action=add",
+        "--subsystem-match=net",
+    ],
+    &["udevadm", "settle"],
+];
+/// Serial number of the machine as reported by the firmware.
+const PRODUCT_SERIAL_FILE: &str = "/sys/class/dmi/id/product_serial";
+/// Files describing the running system as read and written by `apply`.
+const SYSTEM_FILES: SystemFiles = SystemFiles {
+    hostname: HOSTNAME_FILE,
+    kernel_hostname: KERNEL_HOSTNAME_FILE,
+    product_serial: PRODUCT_SERIAL_FILE,
+    sys_class_net: SYS_CLASS_NET_DIR,
+};
+/// Commands asking NetworkManager to re-read the connection files from disk, tried in order:
+/// a `ReloadConnections` D-Bus call, falling back to `nmcli`.
+const RELOAD_COMMANDS: &[&[&str]] = &[
+    &[
+        "busctl",
+        "call",
+        "org.freedesktop.NetworkManager",
+        "/org/freedesktop/NetworkManager/Settings",
+        "org.freedesktop.NetworkManager.Settings",
+        "ReloadConnections",
+    ],
+    &["nmcli", "connection", "reload"],
+];
 
-pub(crate) fn apply(source_dir: &str) -> Result<(), anyhow::Error> {
-    let unified_config_path = Path::new(source_dir).join(ALL_HOSTS_DIR);
+/// Delay between unsuccessful runs of the health check.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
-    if unified_config_path.exists() {
-        info!("Applying unified config...");
-        copy_unified_connection_files(unified_config_path, STATIC_SYSTEM_CONNECTIONS_DIR)?;
-    } else {
-        let hosts = parse_hosts(source_dir).context("Parsing config")?;
-        debug!("Loaded hosts config: {hosts:?}");
+/// Optional behaviour of the `apply` command.
+#[non_exhaustive]
+pub struct ApplyOptions {
+    /// Skip applying any configuration instead of failing when none of the hosts match.
+    pub allow_no_match: bool,
+    /// Warn about other hosts sharing MAC addresses with the identified one.
+    pub warn_unmatched: bool,
+    /// Only log the intended changes instead of writing them.
+    pub dry_run: bool,
+    /// Log the duration of the individual phases once done.
+    pub timing: bool,
+    /// Owner (`uid`, `gid`) of the written keyfiles, defaults to the one of the process.
+    pub keyfile_owner: Option<(u32, u32)>,
+    /// Destination directory of the connection files.
+    pub nm_connections_dir: String,
+    /// NetworkManager configuration directory used to disable the default wired connections.
+    pub nm_config_dir: String,
+    /// Destination directory of the certificates and keys referenced by the connection files.
+    pub nm_certs_dir: String,
+    /// Path to store a JSON report of the applied changes at.
+    pub report: Option<PathBuf>,
+    /// Handling of subdirectories within unified configurations.
+    pub unified_layout: UnifiedLayout,
+    /// Only copy the connections with these ids from unified configurations (all if empty).
+    pub select: Vec<String>,
+    /// Only copy the connections of the host interfaces with these logical or detected local names (all if empty).
+    pub only: Vec<String>,
+    /// Expected SHA-256 checksum (hex encoded) of a config dir fetched from an HTTP(S) URL.
+    pub config_sha256: Option<String>,
+    /// Path to store a gzipped tarball of the current NetworkManager configuration and hostname at
+    /// before making any changes.
+    pub backup_archive: Option<PathBuf>,
+    /// Ask NetworkManager to reload the connection files once applied.
+    pub reload: bool,
+    /// Fail if reloading the connection files fails instead of only logging it.
+    pub reload_required: bool,
+    /// Shell command verifying the network once applied (e.g. pinging the gateway), passing on a zero exit code.
+    pub health_check: Option<String>,
+    /// Time the health check is retried for until it passes.
+    pub health_timeout: Duration,
+    /// Restore the previous connection files (and reload them) if the health check does not pass in time.
+    pub health_rollback: bool,
+    /// Source of the local network interfaces.
+    pub nic_provider: NicProvider,
+    /// Live state captured by `nmstatectl show` (e.g. of a remote host) to take the network interfaces from
+    /// instead of detecting the local ones.
+    pub live_state: Option<PathBuf>,
+    /// Check the live state of the local NICs for likely misconfigurations (e.g. mixed bond port speeds).
+    pub probe: bool,
+    /// Log every keyfile line mentioning a renamed interface and whether it gets rewritten.
+    pub explain_renames: bool,
+    /// Select the host by this hostname instead of identifying it by its NICs.
+    pub hostname: Option<String>,
+    /// File recording the hash of the applied config bundle after a successful apply.
+    pub state_file: String,
+    /// Skip applying the config bundle if its hash matches the one recorded in the state file.
+    pub if_changed: bool,
+    /// Handling of preconfigured Ethernet interfaces whose MAC address is not present locally (e.g. replaced NICs).
+    pub on_mac_change: MacChangePolicy,
+    /// Keep the default wired connections of NetworkManager, i.e. neither write `no-auto-default=*`
+    /// nor clean up the runtime connections dir.
+    pub keep_wired_defaults: bool,
+    /// Pin the preconfigured Ethernet interface names via systemd `.link` files and re-detect the local NICs
+    /// once udev renamed them, before storing the connections against the new names.
+    pub persist_names: bool,
+    /// Mask applied to both MAC addresses when matching an Ethernet interface to a local NIC
+    /// without an exact match (e.g. `fd:ff:ff:ff:ff:ff` ignoring the locally administered bit).
+    pub mac_match_mask: Option<[u8; 6]>,
+}
 
-        let network_interfaces = NetworkInterface::show()?;
-        debug!("Retrieved network interfaces: {network_interfaces:?}");
+/// Source of the local network interfaces.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NicProvider {
+    /// The native interface listing, falling back to `ip` if it lacks MAC addresses.
+    #[default]
+    Auto,
+    /// The native interface listing only.
+    Native,
+    /// The output of `ip -j link show`.
+    Ip,
+}
 
-        let host = identify_host(hosts, &network_interfaces)
-            .ok_or_else(|| anyhow!("None of the preconfigured hosts match local NICs"))?;
-        info!("Identified host: {}", host.hostname);
+/// Parse the `--nic-provider` value.
+pub fn parse_nic_provider(value: &str) -> Result<NicProvider, String> {
+    match value {
+        "auto" => Ok(NicProvider::Auto),
+        "native" => Ok(NicProvider::Native),
+        "ip" => Ok(NicProvider::Ip),
+        _ => Err(format!(
+            "invalid NIC provider '{value}', expected 'auto', 'native' or 'ip'"
+        )),
+    }
+}
 
-        fs::write(HOSTNAME_FILE, &host.hostname).context("Setting hostname")?;
-        info!("Set hostname: {}", host.hostname);
+/// Handling of preconfigured Ethernet interfaces whose MAC address is not present locally.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MacChangePolicy {
+    /// Log a warning and apply the config as is.
+    #[default]
+    Warn,
+    /// Abort applying the config.
+    Fail,
+    /// Adopt the MAC address of the local NIC carrying the preconfigured name.
+    Rename,
+}
 
-        let local_interfaces = detect_local_interfaces(&host, network_interfaces);
-        copy_connection_files(
-            host,
-            local_interfaces,
-            source_dir,
-            STATIC_SYSTEM_CONNECTIONS_DIR,
-        )
-        .context("Copying connection files")?;
+/// Parse the `--on-mac-change` value.
+pub fn parse_mac_change_policy(value: &str) -> Result<MacChangePolicy, String> {
+    match value {
+        "warn" => Ok(MacChangePolicy::Warn),
+        "fail" => Ok(MacChangePolicy::Fail),
+        "rename" => Ok(MacChangePolicy::Rename),
+        _ => Err(format!(
+            "invalid MAC change policy '{value}', expected 'fail', 'rename' or 'warn'"
+        )),
     }
+}
 
-    disable_wired_connections(CONFIG_DIR, RUNTIME_SYSTEM_CONNECTIONS_DIR)
-        .context("Disabling wired connections")
+/// Parse a `--mac-match-mask` value given in MAC address notation (e.g. `00:ff:ff:ff:ff:ff`).
+pub fn parse_mac_match_mask(value: &str) -> Result<[u8; 6], String> {
+    parse_mac_octets(value).ok_or_else(|| {
+        format!(
+            "invalid MAC match mask '{value}', expected six hex octets (e.g. 'fd:ff:ff:ff:ff:ff')"
+        )
+    })
 }
 
-fn parse_hosts(source_dir: &str) -> Result<Vec<Host>, anyhow::Error> {
-    let config_file = Path::new(source_dir).join(HOST_MAPPING_FILE);
+/// Handling of subdirectories within unified configurations.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnifiedLayout {
+    /// Skip subdirectories, logging a warning.
+    #[default]
+    Ignore,
+    /// Copy the keyfiles of all subdirectories into the destination dir itself.
+    Flatten,
+    /// Preserve the subdirectories within the destination dir.
+    Nested,
+}
 
-    let file = fs::File::open(config_file)?;
-    let mut hosts: Vec<Host> = serde_yaml::from_reader(file)?;
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        Self {
+            allow_no_match: false,
+            warn_unmatched: false,
+            dry_run: false,
+            timing: false,
+            keyfile_owner: None,
+            nm_connections_dir: STATIC_SYSTEM_CONNECTIONS_DIR.to_owned(),
+            nm_config_dir: CONFIG_DIR.to_owned(),
+            nm_certs_dir: CERTS_DIR.to_owned(),
+            report: None,
+            unified_layout: UnifiedLayout::default(),
+            select: Vec::new(),
+            only: Vec::new(),
+            health_check: None,
+            health_timeout: Duration::from_secs(60),
+            health_rollback: false,
+            config_sha256: None,
+            backup_archive: None,
+            reload: false,
+            reload_required: false,
+            nic_provider: NicProvider::default(),
+            live_state: None,
+            probe: false,
+            explain_renames: false,
+            hostname: None,
+            state_file: STATE_FILE.to_owned(),
+            if_changed: false,
+            keep_wired_defaults: false,
+            on_mac_change: MacChangePolicy::default(),
+            persist_names: false,
+            mac_match_mask: None,
+        }
+    }
+}
 
-    // Ensure lower case formatting.
-    hosts.iter_mut().for_each(|h| {
-        h.interfaces.iter_mut().for_each(|i| match &i.mac_address {
-            None => {}
-            Some(addr) => i.mac_address = Some(addr.to_lowercase()),
-        });
-    });
+/// Parse a keyfile owner given in the `<uid>:<gid>` format.
+pub fn parse_keyfile_owner(value: &str) -> Result<(u32, u32), String> {
+    let (uid, gid) = value
+        .split_once(':')
+        .ok_or_else(|| "expected <uid>:<gid>".to_string())?;
 
-    Ok(hosts)
-}
+    let uid = uid.parse().map_err(|_| format!("invalid uid '{uid}'"))?;
+    let gid = gid.parse().map_err(|_| format!("invalid gid '{gid}'"))?;
 
-/// Identify the preconfigured static host by matching the MAC address of at least one of the local network interfaces.
-fn identify_host(hosts: Vec<Host>, network_interfaces: &[NetworkInterface]) -> Option<Host> {
-    hosts.into_iter().find(|h| {
-        h.interfaces.iter().any(|interface| {
-            network_interfaces
-                .iter()
-                .filter(|nic| nic.mac_addr.is_some())
-                .any(|nic| nic.mac_addr == interface.mac_address)
-        })
-    })
+    Ok((uid, gid))
 }
 
-/// Detect and return the differences between the preconfigured interfaces and their local representations.
+/// Tracks the files modified by `apply` in order to restore their previous state on failure.
 ///
-/// Examples:
-///     Desired Ethernet "eth0" -> Local "ens1f0"
-///     Desired VLAN "eth0.1365" -> Local "ens1f0.1365"
-fn detect_local_interfaces(
-    host: &Host,
-    network_interfaces: Vec<NetworkInterface>,
-) -> HashMap<String, String> {
-    let mut local_interfaces = HashMap::new();
+/// Note that the cleanup of the runtime connections dir is not reverted.
+#[derive(Default)]
+struct Transaction {
+    /// Modified paths along with their previous contents (`None` if the file did not exist).
+    changes: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
 
-    host.interfaces
-        .iter()
-        .filter(|interface| interface.interface_type == InterfaceType::Ethernet.to_string())
-        .for_each(|interface| {
-            let detected_interface = network_interfaces.iter().find(|nic| {
-                nic.mac_addr == interface.mac_address
-                    && !host.interfaces.iter().any(|i| i.logical_name == nic.name)
-            });
-            match detected_interface {
-                None => {}
-                Some(detected) => {
-                    local_interfaces.insert(interface.logical_name.clone(), detected.name.clone());
-                }
+impl Transaction {
+    /// Record the current state of the file at `path` before it gets modified.
+    fn record(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        if self.changes.iter().any(|(p, _)| p == path) {
+            return Ok(());
+        }
+
+        let previous = match fs::read(path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context(format!("Backing up {path:?}")),
+        };
+
+        self.changes.push((path.to_path_buf(), previous));
+        Ok(())
+    }
+
+    /// Restore all recorded files in reverse order of modification.
+    fn rollback(&mut self) {
+        for (path, previous) in std::mem::take(&mut self.changes).into_iter().rev() {
+            let result = match previous {
+                Some(contents) => fs::write(&path, contents),
+                None => fs::remove_file(&path),
             };
-        });
 
-    // Look for non-Ethernet interfaces containing references to Ethernet ones differing from their preconfigured names.
-    local_interfaces.clone().iter().for_each(|(key, value)| {
-        host.interfaces
-            .iter()
-            .filter(|interface| {
-                interface.logical_name.contains(key) && !interface.logical_name.eq(key)
-            })
-            .for_each(|interface| {
-                let name = &interface.logical_name;
-                local_interfaces.insert(name.clone(), name.replace(key, value));
-            })
-    });
+            match result {
+                Ok(..) => info!("Restored {path:?}"),
+                Err(err) => error!("Restoring {path:?} failed: {err}"),
+            }
+        }
+    }
+}
 
-    local_interfaces
+/// Files describing the running system, used to identify the host and to set its hostname.
+struct SystemFiles<'a> {
+    hostname: &'a str,
+    kernel_hostname: &'a str,
+    product_serial: &'a str,
+    sys_class_net: &'a str,
 }
 
-/// Copy all *.nmconnection files from the preconfigured host dir to the
-/// appropriate NetworkManager dir (default `/etc/NetworkManager/system-connections`).
-fn copy_unified_connection_files(
-    source_dir: PathBuf,
-    destination_dir: &str,
-) -> Result<(), anyhow::Error> {
-    fs::create_dir_all(destination_dir).context("Creating destination dir")?;
+/// Apply the network configurations, restoring the previous state of all modified files on failure.
+///
+/// The `source_dir` may also be a gzipped tarball (`-` reading it from stdin),
+/// or an HTTP(S) URL pointing to one with the `remote` feature.
+pub(crate) fn apply(source_dir: &str, options: &ApplyOptions) -> Result<(), ConfigError> {
+    apply_on(source_dir, options, &SYSTEM_FILES)
+}
 
-    for entry in fs::read_dir(source_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+fn apply_on(
+    source_dir: &str,
+    options: &ApplyOptions,
+    system: &SystemFiles,
+) -> Result<(), ConfigError> {
+    #[cfg(feature = "remote")]
+    let fetched = if crate::remote::is_remote(source_dir) {
+        Some(crate::remote::fetch_config(
+            source_dir,
+            options.config_sha256.as_deref(),
+        )?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "remote"))]
+    if source_dir.starts_with("http://") || source_dir.starts_with("https://") {
+        return Err(anyhow!("Applying config from a URL requires the 'remote' feature").into());
+    }
+    #[cfg(feature = "remote")]
+    let source_dir = match &fetched {
+        Some(dir) => dir
+            .path()
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid temporary config dir"))?,
+        None => source_dir,
+    };
 
-        if entry.metadata()?.is_dir()
-            || path
-                .extension()
-                .and_then(OsStr::to_str)
-                .unwrap_or_default()
-                .ne(CONNECTION_FILE_EXT)
-        {
-            warn!("Ignoring unexpected entry: {path:?}");
-            continue;
+    let extracted = if source_dir == STDIN_CONFIG_DIR {
+        Some(archive::extract(io::stdin()).context("Reading config archive from stdin")?)
+    } else if archive::is_config_archive(source_dir) {
+        Some(archive::extract_config_archive(Path::new(source_dir))?)
+    } else {
+        None
+    };
+    let source_dir = match &extracted {
+        Some(dir) => dir
+            .path()
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid temporary config dir"))?,
+        None => source_dir,
+    };
+
+    let config_hash = state::config_hash(Path::new(source_dir)).context("Hashing config")?;
+    if options.if_changed {
+        match state::read_state(Path::new(&options.state_file)) {
+            Ok(Some(state)) if state.config_hash == config_hash => {
+                info!("Config unchanged since the last apply, skipping");
+                return Ok(());
+            }
+            Ok(..) => {}
+            Err(err) => warn!("{err:#}"),
         }
+    }
 
-        info!("Copying file... {path:?}");
+    if let Some(path) = &options.backup_archive {
+        if options.dry_run {
+            info!("[dry-run] Would store backup archive: {path:?}");
+        } else {
+            store_backup_archive(path, options, system.hostname)?;
+        }
+    }
 
-        let contents = fs::read_to_string(&path).context("Reading file")?;
+    let mut transaction = Transaction::default();
+    let mut timings = Timings::default();
 
-        let filename = path
-            .file_stem()
-            .and_then(OsStr::to_str)
-            .ok_or_else(|| anyhow!("Invalid file path"))?;
+    let mut report = ApplyReport::default();
 
-        store_connection_file(filename, contents, destination_dir).context("Storing file")?;
+    let result = apply_config(
+        source_dir,
+        options,
+        system,
+        &mut transaction,
+        &mut timings,
+        &mut report,
+    );
+    if result.is_err() {
+        warn!("Rolling back applied changes...");
+        transaction.rollback();
     }
 
-    Ok(())
-}
-
-/// Copy all *.nmconnection files from the preconfigured host dir to the
-/// appropriate NetworkManager dir (default `/etc/NetworkManager/system-connections`)
-/// applying interface naming adjustments if necessary.
-fn copy_connection_files(
-    host: Host,
-    local_interfaces: HashMap<String, String>,
-    source_dir: &str,
-    destination_dir: &str,
-) -> Result<(), anyhow::Error> {
-    fs::create_dir_all(destination_dir).context("Creating destination dir")?;
+    if options.timing {
+        info!("{}", timings.summary());
+    }
 
-    let host_config_dir = Path::new(source_dir).join(&host.hostname);
-    let host_config_dir = host_config_dir
-        .to_str()
-        .ok_or_else(|| anyhow!("Determining host config path"))?;
+    let applied = result?;
 
-    for interface in host.interfaces {
-        info!("Processing interface '{}'...", &interface.logical_name);
-        let connections = &interface.connection_ids;
+    if options.reload {
+        if options.dry_run {
+            info!("[dry-run] Would reload NetworkManager connections");
+        } else {
+            match reload_connections(RELOAD_COMMANDS) {
+                Ok(..) => info!("Reloaded NetworkManager connections"),
+                Err(err) if options.reload_required => return Err(err.into()),
+                Err(err) => warn!("{err:#}"),
+            }
+        }
+    }
 
-        if connections.is_empty() {
-            return Err(anyhow!(
-                "Missing connection ids for {}",
-                &interface.logical_name
-            ));
+    if let Some(command) = &options.health_check {
+        if options.dry_run {
+            info!("[dry-run] Would run health check: {command}");
+        } else {
+            verify_health(command, options, &mut transaction)?;
         }
+    }
 
-        for connection in connections {
-            info!("Processing connection '{}'...", connection);
-            let mut filename = connection.clone();
+    if let Some(path) = &options.report {
+        let contents = serde_json::to_string_pretty(&report).context("Serializing report")?;
+        fs::write(path, contents).context(format!("Writing report to {path:?}"))?;
+    }
 
-            let filepath = keyfile_path(host_config_dir, &filename)
-                .ok_or_else(|| anyhow!("Determining source keyfile path"))?;
+    if applied && !options.dry_run {
+        let state = ApplyState::new(report.hostname, config_hash);
+        state::write_state(Path::new(&options.state_file), &state)?;
+    }
+
+    Ok(())
+}
 
-            let mut contents = fs::read_to_string(filepath).context("Reading file")?;
+/// Run the given reload commands until one of them succeeds.
+fn reload_connections(commands: &[&[&str]]) -> anyhow::Result<()> {
+    let mut failures = Vec::new();
 
-            // Update the name and all references of the host NIC in the settings file if there is a difference from the static config.
-            match local_interfaces.get(&interface.logical_name) {
-                None => {}
-                Some(local_name) => {
-                    info!(
-                        "Using interface name '{}' instead of the preconfigured '{}'",
-                        local_name, interface.logical_name
-                    );
+    for command in commands {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty reload command"))?;
 
-                    contents = contents.replace(&interface.logical_name, local_name);
-                    filename = filename.replace(&interface.logical_name, local_name);
-                }
+        match std::process::Command::new(program).args(args).output() {
+            Ok(output) if output.status.success() => {
+                debug!("Reloaded connections via {program}");
+                return Ok(());
             }
-
-            store_connection_file(&filename, contents, destination_dir).context("Storing file")?;
+            Ok(output) => failures.push(format!(
+                "{program}: {} ({})",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+            Err(err) => failures.push(format!("{program}: {err}")),
         }
     }
 
-    Ok(())
+    Err(anyhow!(
+        "Reloading NetworkManager connections failed: {}",
+        failures.join("; ")
+    ))
 }
 
-fn store_connection_file(
-    filename: &str,
-    contents: String,
-    destination_dir: &str,
+/// Run the health `command` until it passes or `options.health_timeout` elapses,
+/// rolling back the applied changes on timeout if requested.
+fn verify_health(
+    command: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
 ) -> Result<(), anyhow::Error> {
-    let destination = keyfile_path(destination_dir, filename)
-        .ok_or_else(|| anyhow!("Determining destination keyfile path"))?;
+    info!("Running health check: {command}");
 
-    fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .mode(0o600)
-        .open(destination)
-        .context("Creating file")?
-        .write_all(contents.as_bytes())
-        .context("Writing file")
-}
+    let Err(err) = run_health_check(command, options.health_timeout, HEALTH_CHECK_INTERVAL) else {
+        info!("Health check passed");
+        return Ok(());
+    };
 
-fn keyfile_path(dir: &str, filename: &str) -> Option<PathBuf> {
-    if dir.is_empty() || filename.is_empty() {
-        return None;
+    if options.health_rollback {
+        warn!("Health check failed, rolling back applied changes...");
+        transaction.rollback();
+        if options.reload {
+            reload_connections(RELOAD_COMMANDS).context("Reloading restored connections")?;
+        }
     }
 
-    let mut destination = Path::new(dir).join(filename).into_os_string();
+    Err(err)
+}
 
-    // Manually append the extension since Path::with_extension() would overwrite a portion of the
-    // filename (i.e. interface name) in the cases where the interface name contains one or more dots
-    destination.push(".");
-    destination.push(CONNECTION_FILE_EXT);
+/// Run the shell `command` every `interval` until it exits successfully, failing once `timeout` elapses.
+fn run_health_check(
+    command: &str,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<(), anyhow::Error> {
+    let deadline = Instant::now() + timeout;
 
-    Some(destination.into())
+    loop {
+        let status = Command::new("sh")
+            .args(["-c", command])
+            .status()
+            .context("Running health check")?;
+        if status.success() {
+            return Ok(());
+        }
+        debug!("Health check failed: {status}");
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(anyhow!(
+                "Health check '{command}' did not pass within {timeout:?}"
+            ));
+        }
+        std::thread::sleep(interval.min(deadline - now));
+    }
 }
 
-fn disable_wired_connections(config_dir: &str, conn_dir: &str) -> Result<(), anyhow::Error> {
-    let _ = fs::remove_dir_all(conn_dir);
-    fs::create_dir_all(conn_dir).context(format!("Recreating {} directory", conn_dir))?;
+/// Snapshot the NetworkManager connections and configuration dirs as well as the hostname file.
+fn store_backup_archive(
+    path: &Path,
+    options: &ApplyOptions,
+    hostname_file: &str,
+) -> Result<(), anyhow::Error> {
+    info!("Storing backup archive {path:?}...");
 
-    fs::create_dir_all(config_dir).context(format!("Creating {} directory", config_dir))?;
+    archive::create_archive(
+        path,
+        &[
+            Path::new(&options.nm_connections_dir),
+            Path::new(&options.nm_config_dir),
+            Path::new(hostname_file),
+        ],
+    )
+    .context("Storing backup archive")
+}
 
-    let config_path = Path::new(config_dir).join("no-auto-default.conf");
-    let config_contents = "[main]\nno-auto-default=*\n";
+/// Apply the config, returning whether any of it was applied (i.e. `false` if none of the hosts match).
+fn apply_config(
+    source_dir: &str,
+    options: &ApplyOptions,
+    system: &SystemFiles,
+    transaction: &mut Transaction,
+    timings: &mut Timings,
+    report: &mut ApplyReport,
+) -> Result<bool, anyhow::Error> {
+    let unified_config_path = Path::new(source_dir).join(ALL_HOSTS_DIR);
+    let unified = unified_config_path.exists();
 
-    fs::OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(config_path)
-        .context("Creating config file")?
-        .write_all(config_contents.as_bytes())
-        .context("Writing config file")
-}
+    // Unified configs may come with per-host overrides taking precedence on the hosts they were generated for.
+    let host = if unified && !mapping_file_path(Path::new(source_dir)).exists() {
+        None
+    } else {
+        let hosts = timings
+            .measure("parse", || parse_hosts(source_dir))
+            .context("Parsing config")?;
+        debug!("Loaded hosts config: {hosts:?}");
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-    use std::path::{Path, PathBuf};
-    use std::{fs, io};
+        let network_interfaces =
+            timings.measure("detect", || detect_network_interfaces(options))?;
+        debug!("Retrieved network interfaces: {network_interfaces:?}");
 
-    use network_interface::NetworkInterface;
+        // MAC set hashes only cover the physical NICs, so that virtual interfaces do not affect the match.
+        let physical_macs = if hosts.iter().any(|h| h.mac_set_hash.is_some()) {
+            timings.measure("detect physical", || {
+                detect_physical_macs(options, Path::new(system.sys_class_net))
+            })?
+        } else {
+            Vec::new()
+        };
 
-    use crate::apply_conf::{
-        copy_connection_files, copy_unified_connection_files, detect_local_interfaces,
-        disable_wired_connections, identify_host, keyfile_path, parse_hosts,
+        let all_hosts = options.warn_unmatched.then(|| hosts.clone());
+
+        let host = timings.measure("identify", || -> Result<_, anyhow::Error> {
+            if let Some(hostname) = &options.hostname {
+                // Hosts without an override fall back to the unified config.
+                if unified {
+                    return Ok(identify_host_by_hostname(hosts, hostname));
+                }
+                return select_host(hosts, hostname).map(Some);
+            }
+
+            let serial = product_serial(system.product_serial);
+            if let Some(host) = identify_host_by_serial(&hosts, serial.as_deref())? {
+                return Ok(Some(host));
+            }
+
+            // Hosts bound to a different serial are ruled out regardless of their MAC addresses.
+            let hosts: Vec<Host> = hosts
+                .into_iter()
+                .filter(|h| serial.is_none() || h.serial.is_none())
+                .collect();
+
+            let host = identify_host(hosts.clone(), &network_interfaces, &physical_macs)?.or_else(|| {
+                let hostname = current_hostname(system)?;
+                info!("None of the preconfigured hosts match local NICs, looking up hostname '{hostname}'...");
+                identify_host_by_hostname(hosts, &hostname)
+            });
+            Ok(host)
+        })?;
+
+        if let (Some(host), Some(all_hosts)) = (&host, &all_hosts) {
+            warn_unmatched_hosts(all_hosts, host);
+        }
+
+        match host {
+            Some(host) => Some((host, network_interfaces)),
+            None if unified => None,
+            None if options.allow_no_match => {
+                warn!("None of the preconfigured hosts match local NICs, skipping config");
+                return Ok(false);
+            }
+            None => return Err(ConfigError::NoMatchingHost.into()),
+        }
     };
-    use crate::types::{Host, Interface};
 
-    #[test]
-    fn disable_wired_conn() {
-        assert!(disable_wired_connections("config", "connections").is_ok());
+    match host {
+        None => {
+            if !options.only.is_empty() {
+                warn!("Restricting interfaces only applies to host configs, ignoring it");
+            }
+            info!("Applying unified config...");
+            let counts = timings.measure("store", || {
+                copy_unified_connection_files(
+                    unified_config_path,
+                    &options.nm_connections_dir,
+                    options,
+                    transaction,
+                )
+            })?;
+            info!(
+                "Connection files: {} written, {} unchanged",
+                counts.written, counts.unchanged
+            );
+        }
+        Some((mut host, network_interfaces)) => {
+            if !options.select.is_empty() {
+                warn!("Selecting connections only applies to unified configs, ignoring it");
+            }
+            info!("Identified host: {}", host.hostname);
+            validate_unique_mac_addresses(&host.interfaces)?;
+            handle_mac_changes(&mut host, &network_interfaces, options.on_mac_change)?;
+            report.hostname = Some(host.hostname.trim().to_owned());
 
-        assert!(Path::new("config").exists());
-        assert!(Path::new("connections").exists());
+            if options.dry_run {
+                info!("[dry-run] Would set hostname: {}", host.hostname.trim());
+            } else {
+                set_hostname(system.hostname, &host.hostname, transaction)
+                    .context("Setting hostname")?;
+                info!("Set hostname: {}", host.hostname.trim());
+            }
 
-        let config_contents = fs::read_to_string("config/no-auto-default.conf").unwrap();
-        assert_eq!(config_contents, "[main]\nno-auto-default=*\n");
+            let local_interfaces = if options.persist_names {
+                timings.measure("rename", || {
+                    detect_local_interfaces_after_renames(
+                        &host,
+                        || {
+                            store_link_files(&host, SYSTEMD_NETWORK_DIR, options, transaction)?;
+                            if options.dry_run {
+                                info!("[dry-run] Would wait for udev to rename the interfaces");
+                                return Ok(());
+                            }
+                            settle_udev(UDEV_SETTLE_COMMANDS)
+                        },
+                        || detect_network_interfaces(options),
+                        options.mac_match_mask,
+                    )
+                })?
+            } else {
+                detect_local_interfaces(&host, network_interfaces, options.mac_match_mask)
+            };
 
-        // cleanup
-        assert!(fs::remove_dir_all("config").is_ok());
-        assert!(fs::remove_dir_all("connections").is_ok());
+            if options.explain_renames {
+                let host_config_dir = Path::new(source_dir).join(&host.hostname);
+                for line in explain_renames(&host, &host_config_dir, &local_interfaces)
+                    .context("Explaining interface renames")?
+                {
+                    info!("{line}");
+                }
+            }
+
+            if options.probe {
+                let host_config_dir = Path::new(source_dir).join(&host.hostname);
+                let warnings =
+                    check_bond_port_speeds(&host_config_dir, &local_interfaces, link_speed)
+                        .context("Probing bond port speeds")?;
+                for warning in warnings {
+                    warn!("{warning}");
+                }
+            }
+            let (interfaces, counts, renames) = timings
+                .measure("store", || {
+                    copy_connection_files(
+                        host,
+                        local_interfaces,
+                        source_dir,
+                        &options.nm_connections_dir,
+                        options,
+                        transaction,
+                    )
+                })
+                .context("Copying connection files")?;
+            report.interfaces = interfaces;
+
+            for line in assignment_summary(&report.interfaces) {
+                info!("{line}");
+            }
+            info!(
+                "Connection files: {} written, {} unchanged",
+                counts.written, counts.unchanged
+            );
+            info!("{}", rename_summary(&renames));
+        }
     }
 
-    #[test]
-    fn identify_host_successfully() {
-        let hosts = vec![
+    let dispatcher_script = Path::new(source_dir).join(HOSTNAME_DISPATCHER_FILE);
+    if dispatcher_script.exists() {
+        install_hostname_dispatcher(&dispatcher_script, DISPATCHER_DIR, options, transaction)
+            .context("Installing hostname dispatcher script")?;
+    }
+
+    if options.keep_wired_defaults {
+        info!("Keeping the default wired connections");
+        return Ok(true);
+    }
+
+    if options.dry_run {
+        info!(
+            "[dry-run] Would disable wired connections via {} and clean up {RUNTIME_SYSTEM_CONNECTIONS_DIR}",
+            options.nm_config_dir
+        );
+        return Ok(true);
+    }
+
+    timings
+        .measure("disable wired connections", || {
+            disable_wired_connections(
+                &options.nm_config_dir,
+                RUNTIME_SYSTEM_CONNECTIONS_DIR,
+                transaction,
+            )
+        })
+        .context("Disabling wired connections")?;
+
+    Ok(true)
+}
+
+fn parse_hosts(source_dir: &str) -> Result<Vec<Host>, anyhow::Error> {
+    let mut hosts = read_mapping(&mapping_file_path(Path::new(source_dir)))?;
+
+    // Ensure the formatting used by the local network interfaces.
+    hosts.iter_mut().for_each(|h| {
+        h.interfaces.iter_mut().for_each(|i| {
+            if let Some(addr) = &i.mac_address {
+                i.mac_address = Some(normalize_mac_address(addr));
+            }
+            i.member_mac_addresses
+                .iter_mut()
+                .for_each(|addr| *addr = normalize_mac_address(addr));
+        });
+    });
+
+    Ok(hosts)
+}
+
+/// Format the MAC address in lower case with colon separators (e.g. `00-11-22-AA-BB-CC` -> `00:11:22:aa:bb:cc`).
+fn normalize_mac_address(addr: &str) -> String {
+    addr.trim().replace('-', ":").to_lowercase()
+}
+
+/// Parse a MAC address in colon (or dash) separated notation into its octets.
+fn parse_mac_octets(addr: &str) -> Option<[u8; 6]> {
+    let octets: Vec<u8> = normalize_mac_address(addr)
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<_>>()?;
+
+    octets.try_into().ok()
+}
+
+/// Compare two MAC addresses only on the bits set in `mask`.
+fn mac_matches_masked(a: &str, b: &str, mask: [u8; 6]) -> bool {
+    match (parse_mac_octets(a), parse_mac_octets(b)) {
+        (Some(a), Some(b)) => (0..6).all(|i| a[i] & mask[i] == b[i] & mask[i]),
+        _ => false,
+    }
+}
+
+/// Identify the preconfigured static host by matching the MAC addresses of the local network interfaces.
+/// Interfaces marked with `exclude_from_match` are not taken into account,
+/// bonds also match via the MAC address of any of their ports.
+///
+/// The host with the most matching interfaces is selected. Fails if several distinct hosts tie for the most matches.
+/// Hosts specifying a `mac_set_hash` are matched against the hash of the `physical_macs` instead.
+fn identify_host(
+    hosts: Vec<Host>,
+    network_interfaces: &[NetworkInterface],
+    physical_macs: &[String],
+) -> Result<Option<Host>, anyhow::Error> {
+    let physical_macs: Vec<&str> = physical_macs.iter().map(String::as_str).collect();
+
+    // Matching MAC set hashes take precedence over the number of matching interfaces.
+    let mut candidates: Vec<((bool, usize), Host)> = hosts
+        .into_iter()
+        .map(|h| {
+            let hash_matches = h
+                .mac_set_hash
+                .as_deref()
+                .is_some_and(|hash| mac_set_hash_matches(hash, &physical_macs));
+            (
+                (
+                    hash_matches,
+                    count_matching_interfaces(&h, network_interfaces),
+                ),
+                h,
+            )
+        })
+        .filter(|((hash_matches, matches), _)| *hash_matches || *matches > 0)
+        .collect();
+
+    let Some(best_score) = candidates.iter().map(|(score, _)| *score).max() else {
+        return Ok(None);
+    };
+    candidates.retain(|(score, _)| *score == best_score);
+
+    let mut hostnames: Vec<&str> = candidates
+        .iter()
+        .map(|(_, h)| h.hostname.as_str())
+        .collect();
+    hostnames.sort();
+    hostnames.dedup();
+
+    if hostnames.len() > 1 {
+        let hostnames = hostnames.into_iter().map(str::to_owned).collect();
+        return Err(ConfigError::AmbiguousHost(hostnames).into());
+    }
+
+    Ok(candidates.into_iter().next().map(|(_, h)| h))
+}
+
+/// Compute the hash identifying a set of MAC addresses in the `<salt>:<hex digest>` format.
+///
+/// The digest is the SHA-256 of the salt followed by the sorted, deduplicated and comma separated
+/// MAC addresses in lower case colon separated format. All-zero addresses (e.g. loopback) are ignored.
+pub(crate) fn mac_set_hash(salt: &str, macs: &[&str]) -> String {
+    let mut macs: Vec<String> = macs
+        .iter()
+        .map(|mac| normalize_mac_address(mac))
+        .filter(|mac| mac.chars().any(|c| c != '0' && c != ':'))
+        .collect();
+    macs.sort();
+    macs.dedup();
+
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(macs.join(","))
+        .finalize();
+    let digest: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    format!("{salt}:{digest}")
+}
+
+fn mac_set_hash_matches(hash: &str, macs: &[&str]) -> bool {
+    hash.rsplit_once(':')
+        .is_some_and(|(salt, _)| mac_set_hash(salt, macs).eq_ignore_ascii_case(hash))
+}
+
+/// Determine the permanent MAC addresses of the physical NICs, either of the captured live state (if given)
+/// or of the devices listed in `sys_class_net`.
+fn detect_physical_macs(
+    options: &ApplyOptions,
+    sys_class_net: &Path,
+) -> Result<Vec<String>, anyhow::Error> {
+    if let Some(path) = &options.live_state {
+        let contents = fs::read_to_string(path).context(format!("Reading {path:?}"))?;
+        return live_state_physical_macs(&contents);
+    }
+
+    physical_nic_macs(sys_class_net, crate::ethtool::permanent_mac)
+        .context(format!("Reading {sys_class_net:?}"))
+}
+
+/// Collect the MAC addresses of the NICs backed by a device (i.e. excluding bonds, bridges, veth pairs etc.),
+/// preferring their permanent ones over the current ones possibly inherited from a bond.
+fn physical_nic_macs(
+    sys_class_net: &Path,
+    permanent_mac: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut macs = Vec::new();
+
+    for entry in fs::read_dir(sys_class_net)? {
+        let path = entry?.path();
+        if !path.join("device").exists() {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+            continue;
+        };
+
+        let mac = match permanent_mac(name) {
+            Some(mac) => mac,
+            None => fs::read_to_string(path.join("address"))
+                .context(format!("Reading MAC address of {name}"))?
+                .trim()
+                .to_owned(),
+        };
+        macs.push(mac);
+    }
+
+    Ok(macs)
+}
+
+/// Determine the network interfaces of the host, i.e. the local NICs including their permanent MAC addresses
+/// or the ones listed in the captured live state (if given).
+fn detect_network_interfaces(
+    options: &ApplyOptions,
+) -> Result<Vec<NetworkInterface>, anyhow::Error> {
+    if let Some(path) = &options.live_state {
+        info!("Using the network interfaces of the captured live state {path:?}");
+        let contents = fs::read_to_string(path).context(format!("Reading {path:?}"))?;
+        return parse_live_state(&contents);
+    }
+
+    list_network_interfaces(options.nic_provider)
+        .map(|nics| with_permanent_macs(nics, crate::ethtool::permanent_mac))
+}
+
+/// Interface entry of a captured `nmstatectl show` output.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LiveStateInterface {
+    name: String,
+    #[serde(rename = "type")]
+    interface_type: Option<String>,
+    mac_address: Option<String>,
+    permanent_mac_address: Option<String>,
+}
+
+/// Live state as captured by `nmstatectl show`, all other settings are ignored.
+#[derive(Deserialize)]
+struct LiveState {
+    #[serde(default)]
+    interfaces: Vec<LiveStateInterface>,
+}
+
+/// Parse the NICs of a live state captured by `nmstatectl show`.
+///
+/// Similar to the local detection, permanent MAC addresses differing from the current ones
+/// (e.g. of bond ports) are added as separate entries.
+fn parse_live_state(contents: &str) -> Result<Vec<NetworkInterface>, anyhow::Error> {
+    let state: LiveState = serde_yaml::from_str(contents).context("Parsing live state")?;
+
+    let mut network_interfaces = Vec::new();
+    for (index, interface) in state.interfaces.into_iter().enumerate() {
+        let mac_addr = interface.mac_address.map(|mac| mac.to_lowercase());
+        let permanent_mac = interface
+            .permanent_mac_address
+            .map(|mac| mac.to_lowercase())
+            .filter(|mac| Some(mac) != mac_addr.as_ref());
+
+        network_interfaces.push(NetworkInterface {
+            name: interface.name.clone(),
+            mac_addr,
+            addr: vec![],
+            index: index as u32 + 1,
+        });
+        if let Some(mac) = permanent_mac {
+            network_interfaces.push(NetworkInterface {
+                name: interface.name,
+                mac_addr: Some(mac),
+                addr: vec![],
+                index: index as u32 + 1,
+            });
+        }
+    }
+
+    Ok(network_interfaces)
+}
+
+/// Collect the MAC addresses of the Ethernet interfaces of a captured live state,
+/// preferring their permanent ones over the current ones.
+fn live_state_physical_macs(contents: &str) -> Result<Vec<String>, anyhow::Error> {
+    let state: LiveState = serde_yaml::from_str(contents).context("Parsing live state")?;
+
+    Ok(state
+        .interfaces
+        .into_iter()
+        .filter(|interface| interface.interface_type.as_deref() == Some("ethernet"))
+        .filter_map(|interface| interface.permanent_mac_address.or(interface.mac_address))
+        .collect())
+}
+
+/// Retrieve the local network interfaces from the given provider.
+fn list_network_interfaces(provider: NicProvider) -> Result<Vec<NetworkInterface>, anyhow::Error> {
+    match provider {
+        NicProvider::Native => Ok(NetworkInterface::show()?),
+        NicProvider::Ip => ip_link_interfaces(),
+        NicProvider::Auto => {
+            let network_interfaces = NetworkInterface::show()?;
+            if !network_interfaces
+                .iter()
+                .any(|nic| nic.mac_addr.is_none() && nic.name != "lo")
+            {
+                return Ok(network_interfaces);
+            }
+
+            info!("Some network interfaces lack MAC addresses, falling back to 'ip link'...");
+            match ip_link_interfaces() {
+                Ok(interfaces) => Ok(interfaces),
+                Err(err) => {
+                    warn!("{err:#}");
+                    Ok(network_interfaces)
+                }
+            }
+        }
+    }
+}
+
+/// Interface entry of the `ip -j link show` output.
+#[derive(Deserialize)]
+struct IpLink {
+    ifindex: u32,
+    ifname: String,
+    address: Option<String>,
+}
+
+fn ip_link_interfaces() -> Result<Vec<NetworkInterface>, anyhow::Error> {
+    let output = Command::new("ip")
+        .args(["-j", "link", "show"])
+        .output()
+        .context("Running 'ip link show'")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Running 'ip link show' failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    parse_ip_link(&output.stdout)
+}
+
+/// Parse the JSON output of `ip -j link show`.
+fn parse_ip_link(output: &[u8]) -> Result<Vec<NetworkInterface>, anyhow::Error> {
+    let links: Vec<IpLink> =
+        serde_json::from_slice(output).context("Parsing 'ip link show' output")?;
+
+    Ok(links
+        .into_iter()
+        .map(|link| NetworkInterface {
+            name: link.ifname,
+            mac_addr: link.address.map(|address| address.to_lowercase()),
+            addr: vec![],
+            index: link.ifindex,
+        })
+        .collect())
+}
+
+/// Add an entry carrying the permanent MAC address for every NIC whose current one differs from it,
+/// as bonds, bridges and MACVLANs override the MAC addresses of their ports.
+fn with_permanent_macs(
+    mut network_interfaces: Vec<NetworkInterface>,
+    permanent_mac: impl Fn(&str) -> Option<String>,
+) -> Vec<NetworkInterface> {
+    let mut names: Vec<String> = network_interfaces
+        .iter()
+        .map(|nic| nic.name.clone())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let Some(mac) = permanent_mac(&name) else {
+            continue;
+        };
+        let Some(nic) = network_interfaces.iter().find(|nic| nic.name == name) else {
+            continue;
+        };
+        if nic.mac_addr.as_deref() == Some(mac.as_str()) {
+            continue;
+        }
+
+        debug!("Interface {name} has permanent MAC address {mac}");
+        network_interfaces.push(NetworkInterface {
+            name: nic.name.clone(),
+            mac_addr: Some(mac),
+            addr: vec![],
+            index: nic.index,
+        });
+    }
+
+    network_interfaces
+}
+
+/// Count the interfaces of the host whose MAC address matches one of the local network interfaces.
+fn count_matching_interfaces(host: &Host, network_interfaces: &[NetworkInterface]) -> usize {
+    let macs: Vec<&String> = network_interfaces
+        .iter()
+        .filter_map(|nic| nic.mac_addr.as_ref())
+        .collect();
+
+    count_interfaces_matching_macs(host, &macs)
+}
+
+fn count_interfaces_matching_macs(host: &Host, macs: &[&String]) -> usize {
+    host.interfaces
+        .iter()
+        .filter(|i| !i.exclude_from_match)
+        .filter(|interface| {
+            macs.iter().any(|mac| {
+                interface.mac_address.as_ref() == Some(mac)
+                    || interface.member_mac_addresses.contains(mac)
+            })
+        })
+        .count()
+}
+
+/// Return the hostnames of the other hosts whose interfaces share MAC addresses with the identified `host`
+/// along with the number of such interfaces, hinting at stale or copy-pasted mapping entries.
+fn unmatched_hosts_sharing_macs(hosts: &[Host], host: &Host) -> Vec<(String, usize)> {
+    let macs: Vec<&String> = host
+        .interfaces
+        .iter()
+        .flat_map(|i| i.mac_address.iter().chain(&i.member_mac_addresses))
+        .collect();
+
+    hosts
+        .iter()
+        .filter(|h| h.hostname != host.hostname)
+        .map(|h| (h.hostname.clone(), count_interfaces_matching_macs(h, &macs)))
+        .filter(|(_, matches)| *matches > 0)
+        .collect()
+}
+
+fn warn_unmatched_hosts(hosts: &[Host], host: &Host) {
+    for (hostname, matches) in unmatched_hosts_sharing_macs(hosts, host) {
+        warn!(
+            "Host '{hostname}' shares MAC addresses of {matches} interface(s) with the identified host '{}', \
+             its mapping is likely misconfigured",
+            host.hostname
+        );
+    }
+}
+
+/// Write the hostname in the conventional format of a single newline terminated line.
+fn set_hostname(
+    path: &str,
+    hostname: &str,
+    transaction: &mut Transaction,
+) -> Result<(), anyhow::Error> {
+    transaction.record(Path::new(path))?;
+    fs::write(path, format!("{}\n", hostname.trim()))?;
+    Ok(())
+}
+
+/// Identify the preconfigured static host by its DMI product serial.
+fn identify_host_by_serial(
+    hosts: &[Host],
+    serial: Option<&str>,
+) -> Result<Option<Host>, anyhow::Error> {
+    let Some(serial) = serial else {
+        return Ok(None);
+    };
+
+    let mut candidates = hosts
+        .iter()
+        .filter(|h| h.serial.as_deref().is_some_and(|s| s.trim() == serial));
+
+    match (candidates.next(), candidates.next()) {
+        (Some(host), None) => Ok(Some(host.clone())),
+        (Some(host), Some(other)) => Err(anyhow!(
+            "Ambiguous host match: {} and {} share the serial '{serial}'",
+            host.hostname,
+            other.hostname
+        )),
+        (None, _) => Ok(None),
+    }
+}
+
+/// Read the DMI product serial, ignoring missing (e.g. virtual machines) or placeholder values.
+fn product_serial(path: &str) -> Option<String> {
+    let serial = fs::read_to_string(path).ok()?.trim().to_owned();
+
+    if serial.is_empty() || serial.eq_ignore_ascii_case("Not Specified") {
+        return None;
+    }
+
+    Some(serial)
+}
+
+/// Detect bonds whose ports report differing link speeds, commonly caused by cabling or port selection mistakes.
+///
+/// Ports are read from the connection files in `host_config_dir` and resolved to their local names,
+/// ports without a known speed (e.g. links which are down) are ignored.
+fn check_bond_port_speeds(
+    host_config_dir: &Path,
+    local_interfaces: &HashMap<String, String>,
+    speed: impl Fn(&str) -> Option<u32>,
+) -> Result<Vec<String>, anyhow::Error> {
+    // bond -> [(local port name, speed in Mb/s)]
+    let mut bonds: HashMap<String, Vec<(String, u32)>> = HashMap::new();
+
+    for entry in fs::read_dir(host_config_dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(CONNECTION_FILE_EXT)) {
+            continue;
+        }
+
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(fs::read_to_string(&path)?)
+            .map_err(|e| anyhow!(e))
+            .context(format!("Parsing {path:?}"))?;
+
+        let is_bond_port = ["port-type", "slave-type"]
+            .iter()
+            .any(|key| c.get("connection", key).as_deref() == Some("bond"));
+        let controller = c
+            .get("connection", "controller")
+            .or_else(|| c.get("connection", "master"));
+        let (true, Some(controller), Some(name)) = (
+            is_bond_port,
+            controller,
+            c.get("connection", "interface-name"),
+        ) else {
+            continue;
+        };
+
+        let local_name = local_interfaces.get(&name).cloned().unwrap_or(name);
+        if let Some(speed) = speed(&local_name) {
+            bonds
+                .entry(controller)
+                .or_default()
+                .push((local_name, speed));
+        }
+    }
+
+    let mut warnings: Vec<String> = bonds
+        .into_iter()
+        .filter(|(_, ports)| ports.iter().any(|(_, speed)| *speed != ports[0].1))
+        .map(|(bond, mut ports)| {
+            ports.sort();
+            let ports = ports
+                .iter()
+                .map(|(name, speed)| format!("{name} ({speed} Mb/s)"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("Ports of bond {bond} have differing link speeds: {ports}")
+        })
+        .collect();
+    warnings.sort();
+
+    Ok(warnings)
+}
+
+/// Read the current link speed (in Mb/s) of the given local interface.
+fn link_speed(name: &str) -> Option<u32> {
+    let path = Path::new(SYS_CLASS_NET_DIR).join(name).join("speed");
+
+    // Links which are down report a speed of -1 or fail to be read altogether.
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Select the preconfigured static host explicitly requested by its hostname.
+fn select_host(hosts: Vec<Host>, hostname: &str) -> Result<Host, anyhow::Error> {
+    let mut known: Vec<String> = hosts.iter().map(|h| h.hostname.clone()).collect();
+    known.sort();
+
+    identify_host_by_hostname(hosts, hostname).ok_or_else(|| {
+        anyhow!(
+            "Host '{hostname}' not found in config, known hosts: {}",
+            known.join(", ")
+        )
+    })
+}
+
+/// Identify the preconfigured static host by its hostname.
+fn identify_host_by_hostname(hosts: Vec<Host>, hostname: &str) -> Option<Host> {
+    hosts.into_iter().find(|h| h.hostname == hostname)
+}
+
+/// Read the current hostname from the hostname file (`/etc/hostname`) falling back to the one of the running kernel.
+fn current_hostname(system: &SystemFiles) -> Option<String> {
+    [system.hostname, system.kernel_hostname]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|hostname| hostname.trim().to_owned())
+        .find(|hostname| !hostname.is_empty())
+}
+
+/// Handle the Ethernet interfaces of the identified host whose MAC address is not present locally
+/// (e.g. after replacing a NIC since the config was generated) according to the `policy`.
+///
+/// Renaming maps such an interface to the local NIC carrying its preconfigured name and adopts its MAC address.
+/// The mapping file itself is left untouched.
+fn handle_mac_changes(
+    host: &mut Host,
+    network_interfaces: &[NetworkInterface],
+    policy: MacChangePolicy,
+) -> Result<(), anyhow::Error> {
+    let is_local = |mac: &str| {
+        network_interfaces
+            .iter()
+            .any(|nic| nic.mac_addr.as_deref() == Some(mac))
+    };
+
+    let changed: Vec<usize> = host
+        .interfaces
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| i.interface_type == InterfaceType::Ethernet)
+        .filter(|(_, i)| i.mac_address.as_deref().is_some_and(|mac| !is_local(mac)))
+        .map(|(index, _)| index)
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let description = changed
+        .iter()
+        .map(|&index| {
+            let interface = &host.interfaces[index];
+            format!(
+                "{} ({})",
+                interface.logical_name,
+                interface.mac_address.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message =
+        format!("Detected interfaces whose MAC address is not present locally: {description}");
+
+    match policy {
+        MacChangePolicy::Warn => {
+            warn!("{message}");
+            Ok(())
+        }
+        MacChangePolicy::Fail => Err(anyhow!(message)),
+        MacChangePolicy::Rename => {
+            for index in changed {
+                let name = host.interfaces[index].logical_name.clone();
+                let mac_address = network_interfaces
+                    .iter()
+                    .filter(|nic| nic.name == name)
+                    .find_map(|nic| nic.mac_addr.clone())
+                    .filter(|mac| {
+                        !host
+                            .interfaces
+                            .iter()
+                            .any(|i| i.mac_address.as_deref() == Some(mac))
+                    })
+                    .ok_or_else(|| {
+                        anyhow!("{message}, no local NIC named {name} can replace it")
+                    })?;
+
+                info!(
+                    "Interface {name} changed its MAC address from {} to {mac_address}",
+                    host.interfaces[index]
+                        .mac_address
+                        .as_deref()
+                        .unwrap_or_default()
+                );
+                host.interfaces[index].mac_address = Some(mac_address);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Keep a keyfile bound to its device by MAC address in line with the MAC address of the mapping,
+/// e.g. after adopting the MAC address of a replaced NIC.
+fn update_bound_mac_address(contents: &str, mac_address: &str) -> Result<String, anyhow::Error> {
+    let mut c = Ini::new_cs();
+    c.set_comment_symbols(&['#']);
+    c.read(contents.to_owned()).map_err(|e| anyhow!(e))?;
+
+    match c.get("ethernet", "mac-address") {
+        Some(bound) if !bound.eq_ignore_ascii_case(mac_address) => Ok(keyfile::set(
+            contents,
+            "ethernet",
+            "mac-address",
+            mac_address,
+        )),
+        _ => Ok(contents.to_owned()),
+    }
+}
+
+/// Detect and return the differences between the preconfigured interfaces and their local representations.
+///
+/// Examples:
+///     Desired Ethernet "eth0" -> Local "ens1f0"
+///     Desired VLAN "eth0.1365" -> Local "ens1f0.1365"
+fn detect_local_interfaces(
+    host: &Host,
+    network_interfaces: Vec<NetworkInterface>,
+    mac_match_mask: Option<[u8; 6]>,
+) -> HashMap<String, String> {
+    let mut local_interfaces = HashMap::new();
+
+    host.interfaces
+        .iter()
+        .filter(|interface| interface.interface_type == InterfaceType::Ethernet)
+        // Interfaces without MAC address (e.g. of hosts matched by their MAC set hash) keep their names.
+        .filter(|interface| interface.mac_address.is_some())
+        .for_each(|interface| {
+            let mut candidates: Vec<&NetworkInterface> = network_interfaces
+                .iter()
+                .filter(|nic| nic.mac_addr == interface.mac_address)
+                .collect();
+
+            // Fall back to the masked comparison, e.g. for NICs flipping the locally administered bit.
+            if let (true, Some(mask), Some(mac)) = (
+                candidates.is_empty(),
+                mac_match_mask,
+                &interface.mac_address,
+            ) {
+                candidates = network_interfaces
+                    .iter()
+                    .filter(|nic| {
+                        nic.mac_addr
+                            .as_deref()
+                            .is_some_and(|local| mac_matches_masked(mac, local, mask))
+                    })
+                    .collect();
+                candidates.iter().for_each(|nic| {
+                    debug!(
+                        "Matched interface {} ({mac}) to {} under MAC mask",
+                        interface.logical_name, nic.name
+                    )
+                });
+            }
+
+            // The NIC already carries its preconfigured name (e.g. on a re-run after a reboot).
+            if candidates
+                .iter()
+                .any(|nic| nic.name == interface.logical_name)
+            {
+                return;
+            }
+
+            let detected_interface = candidates.iter().find(|nic| {
+                !host.interfaces.iter().any(|i| i.logical_name == nic.name)
+                    // Skip virtual interfaces (e.g. VLANs created by a previous run) inheriting the MAC address.
+                    && !candidates
+                        .iter()
+                        .any(|other| nic.name.starts_with(&format!("{}.", other.name)))
+            });
+            match detected_interface {
+                None => {}
+                Some(detected) => {
+                    local_interfaces.insert(interface.logical_name.clone(), detected.name.clone());
+                }
+            };
+        });
+
+    // Look for non-Ethernet interfaces containing references to Ethernet ones differing from their preconfigured names.
+    local_interfaces.clone().iter().for_each(|(key, value)| {
+        host.interfaces
+            .iter()
+            .filter(|interface| !interface.logical_name.eq(key))
+            .for_each(|interface| {
+                let name = &interface.logical_name;
+                if let Some(local_name) = replace_interface_reference(name, key, value) {
+                    local_interfaces.insert(name.clone(), local_name);
+                }
+            })
+    });
+
+    local_interfaces
+}
+
+/// Two-phase detection of the local interfaces: `rename` pins the preconfigured names (phase one)
+/// and the NICs are only listed afterwards (phase two) so that the connections target the new names.
+///
+/// Interfaces which still differ from their preconfigured names are logged and handled as usual.
+fn detect_local_interfaces_after_renames(
+    host: &Host,
+    rename: impl FnOnce() -> Result<(), anyhow::Error>,
+    list_interfaces: impl FnOnce() -> Result<Vec<NetworkInterface>, anyhow::Error>,
+    mac_match_mask: Option<[u8; 6]>,
+) -> Result<HashMap<String, String>, anyhow::Error> {
+    rename().context("Renaming interfaces")?;
+
+    let network_interfaces = list_interfaces().context("Re-reading network interfaces")?;
+    debug!("Retrieved renamed network interfaces: {network_interfaces:?}");
+
+    let local_interfaces = detect_local_interfaces(host, network_interfaces, mac_match_mask);
+    let mut pending: Vec<_> = local_interfaces
+        .iter()
+        .filter(|(name, _)| {
+            host.interfaces
+                .iter()
+                .any(|i| &i.logical_name == *name && i.interface_type == InterfaceType::Ethernet)
+        })
+        .map(|(name, local)| format!("{local} -> {name}"))
+        .collect();
+    if !pending.is_empty() {
+        pending.sort();
+        warn!("Interfaces not renamed yet: {}", pending.join(", "));
+    }
+
+    Ok(local_interfaces)
+}
+
+/// Write a systemd `.link` file per Ethernet interface of the host, matching it by its MAC address
+/// and assigning its preconfigured name.
+fn store_link_files(
+    host: &Host,
+    destination_dir: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut paths = Vec::new();
+
+    for interface in host
+        .interfaces
+        .iter()
+        .filter(|i| i.interface_type == InterfaceType::Ethernet)
+    {
+        let Some(mac_address) = &interface.mac_address else {
+            continue;
+        };
+        let name = &interface.logical_name;
+        if name.is_empty() || name.contains(['/', '\0']) || name.contains("..") {
+            return Err(anyhow!("Invalid interface name '{name}'"));
+        }
+
+        let destination = Path::new(destination_dir).join(format!("{LINK_FILE_PREFIX}{name}.link"));
+        paths.push(destination.clone());
+
+        if options.dry_run {
+            info!("[dry-run] Would write link file: {destination:?}");
+            continue;
+        }
+
+        fs::create_dir_all(destination_dir)
+            .context(format!("Creating {destination_dir} directory"))?;
+        transaction.record(&destination)?;
+
+        let contents = format!("[Match]\nMACAddress={mac_address}\n\n[Link]\nName={name}\n");
+        fs::write(&destination, contents).context(format!("Writing link file {destination:?}"))?;
+        info!("Stored link file: {destination:?}");
+    }
+
+    Ok(paths)
+}
+
+/// Run the given udev commands in order, failing on the first unsuccessful one.
+fn settle_udev(commands: &[&[&str]]) -> Result<(), anyhow::Error> {
+    for command in commands {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty udev command"))?;
+
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .context(format!("Running '{}'", command.join(" ")))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Running '{}' failed: {}",
+                command.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the references to the `old` interface in the `name` of another interface.
+///
+/// Only whole name components are taken into account, e.g. `eth1` is referenced
+/// by `eth1.100` but neither by `eth10` nor by `veth1`.
+fn replace_interface_reference(name: &str, old: &str, new: &str) -> Option<String> {
+    replace_name_component(name, old, new, |c| c == '.')
+}
+
+/// Replace the references to the `old` interface in a connection id (or keyfile name).
+///
+/// Unlike interface names ids commonly carry arbitrary suffixes, e.g. `eth1` is referenced by `eth1-port`.
+fn replace_connection_id_reference(id: &str, old: &str, new: &str) -> Option<String> {
+    replace_name_component(id, old, new, |c| !c.is_alphanumeric())
+}
+
+fn replace_name_component(
+    name: &str,
+    old: &str,
+    new: &str,
+    is_trailing_boundary: impl Fn(char) -> bool,
+) -> Option<String> {
+    let mut replaced = String::with_capacity(name.len());
+    let mut last = 0;
+
+    for (start, _) in name.match_indices(old) {
+        let end = start + old.len();
+        let preceded_by_boundary = name[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        let followed_by_boundary = name[end..].chars().next().is_none_or(&is_trailing_boundary);
+
+        if start >= last && preceded_by_boundary && followed_by_boundary {
+            replaced.push_str(&name[last..start]);
+            replaced.push_str(new);
+            last = end;
+        }
+    }
+
+    if last == 0 {
+        return None;
+    }
+
+    replaced.push_str(&name[last..]);
+    Some(replaced)
+}
+
+/// Copy all *.nmconnection files from the preconfigured host dir to the
+/// appropriate NetworkManager dir (default `/etc/NetworkManager/system-connections`).
+///
+/// Subdirectories are handled according to the `unified_layout` option.
+fn copy_unified_connection_files(
+    source_dir: PathBuf,
+    destination_dir: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<StoreCounts, anyhow::Error> {
+    let keyfiles = unified_keyfiles(&source_dir, options)?;
+    validate_unique_mac_bindings(&source_dir, &keyfiles)?;
+
+    if !options.dry_run {
+        fs::create_dir_all(destination_dir).context("Creating destination dir")?;
+    }
+
+    let mut copied = HashSet::new();
+    let mut counts = StoreCounts::default();
+    copy_unified_dir(
+        &source_dir,
+        destination_dir,
+        options,
+        transaction,
+        &mut copied,
+        &mut counts,
+    )?;
+
+    let missing: Vec<&str> = options
+        .select
+        .iter()
+        .filter(|id| {
+            !copied
+                .iter()
+                .any(|path| path.file_name() == Some(OsStr::new(id.as_str())))
+        })
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Selected connections not found in unified config: {}",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(counts)
+}
+
+/// List the keyfiles of the unified config which are copied according to the `unified_layout` and `select` options.
+fn unified_keyfiles(
+    source_dir: &Path,
+    options: &ApplyOptions,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut keyfiles = Vec::new();
+
+    for entry in fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.metadata()?.is_dir() {
+            if options.unified_layout != UnifiedLayout::Ignore {
+                keyfiles.extend(unified_keyfiles(&path, options)?);
+            }
+            continue;
+        }
+
+        let selected = options.select.is_empty()
+            || path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .is_some_and(|stem| options.select.iter().any(|id| id == stem));
+        if selected && path.extension().and_then(OsStr::to_str) == Some(CONNECTION_FILE_EXT) {
+            keyfiles.push(path);
+        }
+    }
+
+    keyfiles.sort();
+    Ok(keyfiles)
+}
+
+/// Fail if several keyfiles bind their connection to the same MAC address,
+/// as only one of them could be activated on the device.
+fn validate_unique_mac_bindings(
+    source_dir: &Path,
+    keyfiles: &[PathBuf],
+) -> Result<(), anyhow::Error> {
+    let mut bindings: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for path in keyfiles {
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.load(path)
+            .map_err(|e| anyhow!(e))
+            .context(format!("Reading {path:?}"))?;
+
+        let bound = ["ethernet", "802-3-ethernet"]
+            .into_iter()
+            .find_map(|section| c.get(section, "mac-address"));
+        if let Some(mac_address) = bound {
+            let filename = path.strip_prefix(source_dir).unwrap_or(path);
+            bindings
+                .entry(normalize_mac_address(&mac_address))
+                .or_default()
+                .push(filename.display().to_string());
+        }
+    }
+
+    let duplicates: Vec<String> = bindings
+        .into_iter()
+        .filter(|(_, filenames)| filenames.len() > 1)
+        .map(|(mac_address, filenames)| format!("{mac_address} ({})", filenames.join(", ")))
+        .collect();
+    if !duplicates.is_empty() {
+        return Err(anyhow!(
+            "Connection files in unified config bind the same MAC address: {}",
+            duplicates.join("; ")
+        ));
+    }
+
+    Ok(())
+}
+
+fn copy_unified_dir(
+    source_dir: &Path,
+    destination_dir: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+    copied: &mut HashSet<PathBuf>,
+    counts: &mut StoreCounts,
+) -> Result<(), anyhow::Error> {
+    let mut entries = fs::read_dir(source_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+
+        if entry.metadata()?.is_dir() {
+            match options.unified_layout {
+                UnifiedLayout::Ignore => warn!("Ignoring unexpected entry: {path:?}"),
+                UnifiedLayout::Flatten => {
+                    copy_unified_dir(&path, destination_dir, options, transaction, copied, counts)?
+                }
+                UnifiedLayout::Nested => {
+                    let nested_dir = Path::new(destination_dir).join(entry.file_name());
+                    let nested_dir = nested_dir
+                        .to_str()
+                        .ok_or_else(|| anyhow!("Invalid dir path: {nested_dir:?}"))?;
+                    copy_unified_dir(&path, nested_dir, options, transaction, copied, counts)?
+                }
+            }
+            continue;
+        }
+
+        if path
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .ne(CONNECTION_FILE_EXT)
+        {
+            warn!("Ignoring unexpected entry: {path:?}");
+            continue;
+        }
+
+        let filename = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .ok_or_else(|| anyhow!("Invalid file path"))?;
+
+        if !options.select.is_empty() && !options.select.iter().any(|id| id == filename) {
+            debug!("Skipping connection not selected: {path:?}");
+            continue;
+        }
+
+        info!("Copying file... {path:?}");
+
+        let contents = fs::read_to_string(&path).context("Reading file")?;
+        let modified = entry.metadata()?.modified().ok();
+
+        // Flattening may map keyfiles of different subdirectories to the same destination.
+        if !copied.insert(Path::new(destination_dir).join(filename)) {
+            return Err(anyhow!(
+                "Conflicting connection file {path:?} in unified config"
+            ));
+        }
+
+        if !options.dry_run {
+            fs::create_dir_all(destination_dir).context("Creating destination dir")?;
+        }
+
+        let (_, written) = store_connection_file(
+            filename,
+            contents,
+            destination_dir,
+            modified,
+            options,
+            transaction,
+        )
+        .context("Storing file")?;
+        counts.count(written);
+    }
+
+    Ok(())
+}
+
+/// Describe every keyfile line mentioning a renamed interface and whether it is rewritten by [`copy_connection_files`].
+///
+/// Lines where the old name only occurs within a longer token (e.g. a UUID) are flagged as substring matches.
+fn explain_renames(
+    host: &Host,
+    host_config_dir: &Path,
+    local_interfaces: &HashMap<String, String>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut renames: Vec<(&String, &String)> = local_interfaces.iter().collect();
+    renames.sort();
+
+    let mut keyfiles = Vec::new();
+    for interface in host.interfaces.iter().filter(|i| !i.unmanaged) {
+        for connection in &interface.connection_ids {
+            let filename = format!("{connection}.{CONNECTION_FILE_EXT}");
+            let contents = fs::read_to_string(host_config_dir.join(&filename))
+                .context(format!("Reading {filename}"))?;
+            let rewritten = match local_interfaces.get(&interface.logical_name) {
+                Some(local_name) => rename_interface_references(
+                    &contents,
+                    &interface.logical_name,
+                    local_name,
+                    local_interfaces,
+                )?,
+                None => contents.clone(),
+            };
+            keyfiles.push((filename, contents, rewritten));
+        }
+    }
+
+    let mut explanation = Vec::new();
+    for (old, new) in renames {
+        explanation.push(format!("Rename {old} -> {new}:"));
+
+        for (filename, contents, rewritten) in &keyfiles {
+            for (index, (line, rewritten_line)) in
+                contents.lines().zip(rewritten.lines()).enumerate()
+            {
+                if !line.contains(old.as_str()) {
+                    continue;
+                }
+
+                let action = if line == rewritten_line {
+                    "kept"
+                } else {
+                    "rewritten"
+                };
+                let substring =
+                    if replace_name_component(line, old, new, |c| !c.is_alphanumeric()).is_none() {
+                        ", substring match"
+                    } else {
+                        ""
+                    };
+                explanation.push(format!(
+                    "  {filename}:{}: {line} ({action}{substring})",
+                    index + 1
+                ));
+            }
+        }
+    }
+
+    Ok(explanation)
+}
+
+/// Preconfigured interface names mapped to the local names they were renamed to.
+type InterfaceRenames = HashMap<String, String>;
+
+/// Copy all *.nmconnection files from the preconfigured host dir to the
+/// appropriate NetworkManager dir (default `/etc/NetworkManager/system-connections`)
+/// applying interface naming adjustments if necessary.
+///
+/// Returns a report per copied interface along with the number of written and unchanged files
+/// and the interface renames that were applied.
+fn copy_connection_files(
+    host: Host,
+    local_interfaces: HashMap<String, String>,
+    source_dir: &str,
+    destination_dir: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<(Vec<InterfaceReport>, StoreCounts, InterfaceRenames), anyhow::Error> {
+    // Interfaces are requested by either their preconfigured or their detected local name.
+    let is_named = |interface: &Interface, name: &String| {
+        name == &interface.logical_name
+            || local_interfaces.get(&interface.logical_name) == Some(name)
+    };
+
+    let missing: Vec<&str> = options
+        .only
+        .iter()
+        .filter(|name| !host.interfaces.iter().any(|i| is_named(i, name)))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Requested interfaces not found on host {}: {}",
+            host.hostname,
+            missing.join(", ")
+        ));
+    }
+
+    if !options.dry_run {
+        fs::create_dir_all(destination_dir).context("Creating destination dir")?;
+    }
+
+    let mut reports = Vec::new();
+    let mut counts = StoreCounts::default();
+    let mut renames = HashMap::new();
+
+    let host_config_dir = Path::new(source_dir).join(&host.hostname);
+    let host_config_dir = host_config_dir
+        .to_str()
+        .ok_or_else(|| anyhow!("Determining host config path"))?;
+
+    for interface in host.interfaces {
+        if !options.only.is_empty() && !options.only.iter().any(|name| is_named(&interface, name)) {
+            debug!(
+                "Skipping interface '{}' not requested",
+                &interface.logical_name
+            );
+            continue;
+        }
+
+        if interface.unmanaged {
+            info!(
+                "Skipping interface '{}' not managed by NetworkManager",
+                &interface.logical_name
+            );
+            continue;
+        }
+
+        info!("Processing interface '{}'...", &interface.logical_name);
+        let connections = &interface.connection_ids;
+
+        if connections.is_empty() {
+            return Err(anyhow!(
+                "Missing connection ids for {}",
+                &interface.logical_name
+            ));
+        }
+
+        let mut sources = Vec::new();
+        let mut destinations = Vec::new();
+
+        for connection in connections {
+            info!("Processing connection '{}'...", connection);
+            sources.push(format!(
+                "{}/{connection}.{CONNECTION_FILE_EXT}",
+                host.hostname
+            ));
+            let mut filename = connection.clone();
+
+            let filepath = keyfile_path(host_config_dir, &filename)
+                .ok_or_else(|| anyhow!("Determining source keyfile path"))?;
+
+            let mut contents = fs::read_to_string(&filepath).context("Reading file")?;
+            let modified = fs::metadata(&filepath)?.modified().ok();
+
+            if let Some(keyfile_type) = keyfile_type_mismatch(&interface.interface_type, &contents)?
+            {
+                warn!(
+                    "Connection file {filepath:?} is of type '{keyfile_type}' while the mapping of '{}' specifies '{}'",
+                    interface.logical_name, interface.interface_type
+                );
+            }
+
+            if let (InterfaceType::Ethernet, Some(mac_address)) =
+                (&interface.interface_type, &interface.mac_address)
+            {
+                contents = update_bound_mac_address(&contents, mac_address)?;
+            }
+
+            contents =
+                copy_referenced_files(&contents, Path::new(host_config_dir), options, transaction)
+                    .context(format!("Copying files referenced by {filepath:?}"))?;
+
+            // Update the name and all references of the host NIC in the settings file if there is a difference from the static config.
+            match local_interfaces.get(&interface.logical_name) {
+                None => {}
+                Some(local_name) => {
+                    info!(
+                        "Using interface name '{}' instead of the preconfigured '{}'",
+                        local_name, interface.logical_name
+                    );
+
+                    contents = rename_interface_references(
+                        &contents,
+                        &interface.logical_name,
+                        local_name,
+                        &local_interfaces,
+                    )?;
+                    if let Some(renamed) = replace_connection_id_reference(
+                        &filename,
+                        &interface.logical_name,
+                        local_name,
+                    ) {
+                        filename = renamed;
+                    }
+                }
+            }
+
+            let (destination, written) = store_connection_file(
+                &filename,
+                contents,
+                destination_dir,
+                modified,
+                options,
+                transaction,
+            )
+            .context("Storing file")?;
+            counts.count(written);
+            destinations.push(destination);
+        }
+
+        let local_name = local_interfaces.get(&interface.logical_name);
+        if let Some(local_name) = local_name {
+            renames.insert(interface.logical_name.clone(), local_name.clone());
+        }
+        reports.push(InterfaceReport {
+            local_name: local_name.unwrap_or(&interface.logical_name).clone(),
+            renamed: local_name.is_some(),
+            logical_name: interface.logical_name,
+            source_keyfiles: sources,
+            destination_keyfiles: destinations,
+        });
+    }
+
+    Ok((reports, counts, renames))
+}
+
+/// Describe the final assignment of every interface to its local name and source keyfiles, one line each.
+fn assignment_summary(reports: &[InterfaceReport]) -> Vec<String> {
+    let mut lines = vec!["Interface assignment summary:".to_string()];
+    lines.extend(reports.iter().map(|report| format!("  {report}")));
+    lines
+}
+
+/// Summarize the `logical -> local` interface renames ordered by the preconfigured names.
+fn rename_summary(renames: &InterfaceRenames) -> String {
+    if renames.is_empty() {
+        return "No interface renames required".to_string();
+    }
+
+    let renames: BTreeMap<&String, &String> = renames.iter().collect();
+    let renames: Vec<String> = renames
+        .into_iter()
+        .map(|(logical_name, local_name)| format!("{logical_name}->{local_name}"))
+        .collect();
+
+    format!(
+        "Renamed {} interface{}: {}",
+        renames.len(),
+        if renames.len() == 1 { "" } else { "s" },
+        renames.join(", ")
+    )
+}
+
+/// Return the `[connection] type` of the keyfile if it does not correspond to the `interface_type` of the mapping.
+///
+/// OVS port profiles are attached to interfaces of any type and are therefore never reported.
+fn keyfile_type_mismatch(
+    interface_type: &InterfaceType,
+    contents: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let mut c = Ini::new_cs();
+    c.set_comment_symbols(&['#']);
+    c.read(contents.to_owned()).map_err(|e| anyhow!(e))?;
+
+    let Some(keyfile_type) = c.get("connection", "type") else {
+        return Ok(None);
+    };
+
+    let matches = match (interface_type.as_str(), keyfile_type.as_str()) {
+        (_, "ovs-port") => true,
+        ("ethernet", "802-3-ethernet") => true,
+        ("linux-bridge", "bridge") => true,
+        ("mac-vlan" | "mac-vtap", "macvlan") => true,
+        (interface_type, keyfile_type) => interface_type == keyfile_type,
+    };
+
+    Ok((!matches).then_some(keyfile_type))
+}
+
+/// Rewrite the keyfile settings referencing renamed interfaces leaving all other values (e.g. uuids) untouched.
+///
+/// The connection id is adjusted for the `logical_name` -> `local_name` rename of the interface owning the keyfile
+/// while the interface name and references to other interfaces are looked up in `local_interfaces`.
+fn rename_interface_references(
+    contents: &str,
+    logical_name: &str,
+    local_name: &str,
+    local_interfaces: &HashMap<String, String>,
+) -> Result<String, anyhow::Error> {
+    let mut c = Ini::new_cs();
+    // Lists are separated by ';' in keyfiles, which must not be treated as a comment.
+    c.set_comment_symbols(&['#']);
+    c.read(contents.to_owned()).map_err(|e| anyhow!(e))?;
+
+    let mut contents = contents.to_owned();
+
+    if let Some(id) = c.get("connection", "id") {
+        if let Some(renamed) = replace_connection_id_reference(&id, logical_name, local_name) {
+            contents = keyfile::set(&contents, "connection", "id", &renamed);
+        }
+    }
+
+    for (section, key) in [
+        ("connection", "interface-name"),
+        ("connection", "controller"),
+        ("connection", "master"),
+        ("vlan", "parent"),
+    ] {
+        if let Some(renamed) = c
+            .get(section, key)
+            .and_then(|value| local_interfaces.get(&value))
+        {
+            contents = keyfile::set(&contents, section, key, renamed);
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Copy the certificates and keys referenced by relative paths in the keyfile from the host config dir
+/// to the certs dir, returning the contents with the references pointing to the copies.
+///
+/// Absolute references are expected to be provisioned on the host separately and kept as-is.
+/// WireGuard private keys are embedded into the keyfile instead, as NetworkManager cannot read them from files.
+fn copy_referenced_files(
+    contents: &str,
+    host_config_dir: &Path,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<String, anyhow::Error> {
+    let mut contents = contents.to_owned();
+
+    for (section, key, path) in referenced_files(&contents)? {
+        let path = Path::new(&path);
+        if path.is_absolute() && section != WIREGUARD_SECTION {
+            continue;
+        }
+
+        // References are derived from the user provided config and must not escape the host config dir.
+        let filename = match path.file_name() {
+            Some(filename) if path.is_absolute() || path.components().count() == 1 => filename,
+            _ => {
+                return Err(anyhow!(
+                    "Invalid path referenced by {section}.{key}: {path:?}"
+                ))
+            }
+        };
+
+        let source = host_config_dir.join(path);
+        if section == WIREGUARD_SECTION {
+            if options.dry_run {
+                info!("[dry-run] Would embed {source:?} as {section}.{key}");
+                continue;
+            }
+
+            let private_key = fs::read_to_string(&source).context(format!("Reading {source:?}"))?;
+            contents = keyfile::set(&contents, section, key, private_key.trim());
+            info!("Embedded {source:?} as {section}.{key}");
+            continue;
+        }
+
+        let destination = Path::new(&options.nm_certs_dir).join(filename);
+        let reference = destination
+            .to_str()
+            .ok_or_else(|| anyhow!("Determining destination path of {source:?}"))?;
+        contents = keyfile::set(&contents, section, key, reference);
+
+        if options.dry_run {
+            info!("[dry-run] Would copy {source:?} to {destination:?}");
+            continue;
+        }
+
+        let data = fs::read(&source).context(format!("Reading {source:?}"))?;
+
+        fs::create_dir_all(&options.nm_certs_dir)
+            .context(format!("Creating {} directory", options.nm_certs_dir))?;
+        transaction.record(&destination)?;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .open(&destination)
+            .context("Creating file")?;
+        // The mode only applies to newly created files.
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .context("Setting file permissions")?;
+        file.write_all(&data).context("Writing file")?;
+
+        info!("Copied {source:?} to {destination:?}");
+    }
+
+    Ok(contents)
+}
+
+/// Number of connection files stored by `apply`, distinguishing the ones skipped as unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct StoreCounts {
+    written: usize,
+    unchanged: usize,
+}
+
+impl StoreCounts {
+    fn count(&mut self, written: bool) {
+        if written {
+            self.written += 1;
+        } else {
+            self.unchanged += 1;
+        }
+    }
+}
+
+/// Store the connection file in the destination dir and return its path
+/// along with whether it was (or would be) written.
+///
+/// Existing files with identical contents are left untouched, so that neither their modification time
+/// nor NetworkManager are disturbed. New contents get the modification time of the source keyfile (if known).
+fn store_connection_file(
+    filename: &str,
+    contents: String,
+    destination_dir: &str,
+    source_modified: Option<SystemTime>,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<(PathBuf, bool), anyhow::Error> {
+    let destination = keyfile_path(destination_dir, filename)
+        .ok_or_else(|| anyhow!("Determining destination keyfile path"))?;
+
+    if fs::read(&destination).is_ok_and(|existing| existing == contents.as_bytes()) {
+        info!("Connection file unchanged: {destination:?}");
+        return Ok((destination, false));
+    }
+
+    if options.dry_run {
+        info!("[dry-run] Would write connection file: {destination:?}");
+        return Ok((destination, true));
+    }
+
+    transaction.record(&destination)?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o600)
+        .open(&destination)
+        .context("Creating file")?;
+
+    file.write_all(contents.as_bytes())
+        .context("Writing file")?;
+
+    if let Some((uid, gid)) = options.keyfile_owner {
+        std::os::unix::fs::fchown(&file, Some(uid), Some(gid)).map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => anyhow!(
+                "Insufficient privileges to change the owner of {destination:?} to {uid}:{gid}"
+            ),
+            _ => anyhow!(e).context("Changing file owner"),
+        })?;
+    }
+
+    if let Some(modified) = source_modified {
+        file.set_modified(modified)
+            .context("Setting modification time")?;
+    }
+
+    Ok((destination, true))
+}
+
+fn keyfile_path(dir: &str, filename: &str) -> Option<PathBuf> {
+    if dir.is_empty() || filename.is_empty() {
+        return None;
+    }
+
+    // Filenames are derived from the user provided config and must not escape the dir.
+    if filename.contains(['/', '\0']) || filename.contains("..") {
+        return None;
+    }
+
+    let mut destination = Path::new(dir).join(filename).into_os_string();
+
+    // Manually append the extension since Path::with_extension() would overwrite a portion of the
+    // filename (i.e. interface name) in the cases where the interface name contains one or more dots
+    destination.push(".");
+    destination.push(CONNECTION_FILE_EXT);
+
+    Some(destination.into())
+}
+
+/// Install the hostname dispatcher script emitted by `generate` as an executable in the `destination_dir`.
+fn install_hostname_dispatcher(
+    source: &Path,
+    destination_dir: &str,
+    options: &ApplyOptions,
+    transaction: &mut Transaction,
+) -> Result<(), anyhow::Error> {
+    let destination = Path::new(destination_dir).join(HOSTNAME_DISPATCHER_FILE);
+
+    if options.dry_run {
+        info!("[dry-run] Would install hostname dispatcher script: {destination:?}");
+        return Ok(());
+    }
+
+    let contents = fs::read(source).context("Reading script file")?;
+
+    fs::create_dir_all(destination_dir).context(format!("Creating {destination_dir} directory"))?;
+    transaction.record(&destination)?;
+
+    fs::write(&destination, contents).context("Writing script file")?;
+    fs::set_permissions(&destination, fs::Permissions::from_mode(0o755))
+        .context("Setting script permissions")?;
+
+    info!("Installed hostname dispatcher script: {destination:?}");
+    Ok(())
+}
+
+fn disable_wired_connections(
+    config_dir: &str,
+    conn_dir: &str,
+    transaction: &mut Transaction,
+) -> Result<(), anyhow::Error> {
+    let _ = fs::remove_dir_all(conn_dir);
+    fs::create_dir_all(conn_dir).context(format!("Recreating {} directory", conn_dir))?;
+
+    fs::create_dir_all(config_dir).context(format!("Creating {} directory", config_dir))?;
+
+    let config_path = Path::new(config_dir).join("no-auto-default.conf");
+    let config_contents = "[main]\nno-auto-default=*\n";
+
+    transaction.record(&config_path)?;
+
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(config_path)
+        .context("Creating config file")?
+        .write_all(config_contents.as_bytes())
+        .context("Writing config file")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+    use std::{fs, io};
+
+    use network_interface::NetworkInterface;
+
+    use crate::apply_conf::{
+        apply_on, assignment_summary, check_bond_port_speeds, copy_connection_files,
+        copy_referenced_files, copy_unified_connection_files, detect_local_interfaces,
+        detect_local_interfaces_after_renames, disable_wired_connections, explain_renames,
+        handle_mac_changes, identify_host, identify_host_by_hostname, identify_host_by_serial,
+        install_hostname_dispatcher, keyfile_path, keyfile_type_mismatch, live_state_physical_macs,
+        mac_set_hash, parse_hosts, parse_ip_link, parse_keyfile_owner, parse_live_state,
+        parse_mac_change_policy, parse_mac_match_mask, parse_nic_provider, physical_nic_macs,
+        product_serial, reload_connections, rename_interface_references, rename_summary,
+        run_health_check, select_host, set_hostname, store_backup_archive, store_connection_file,
+        store_link_files, unmatched_hosts_sharing_macs, update_bound_mac_address, verify_health,
+        with_permanent_macs, ApplyOptions, MacChangePolicy, NicProvider, StoreCounts, SystemFiles,
+        Transaction, UnifiedLayout,
+    };
+    use crate::error::ConfigError;
+    use crate::state::{self, ApplyState};
+    use crate::types::{Host, Interface, InterfaceReport, InterfaceType};
+    use crate::HOSTNAME_DISPATCHER_FILE;
+
+    #[test]
+    fn install_hostname_dispatcher_as_executable() -> io::Result<()> {
+        let source_dir = "_dispatcher-out";
+        let destination_dir = "_dispatcher-out/dispatcher.d";
+        let source = Path::new(source_dir).join(HOSTNAME_DISPATCHER_FILE);
+
+        fs::create_dir_all(source_dir)?;
+        fs::write(&source, "#!/bin/sh\n")?;
+
+        install_hostname_dispatcher(
+            &source,
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
+        )
+        .unwrap();
+
+        let destination = Path::new(destination_dir).join(HOSTNAME_DISPATCHER_FILE);
+        assert_eq!(fs::read_to_string(&destination)?, "#!/bin/sh\n");
+        assert_eq!(
+            fs::metadata(&destination)?.permissions().mode() & 0o777,
+            0o755
+        );
+
+        // cleanup
+        fs::remove_dir_all(source_dir)
+    }
+
+    #[test]
+    fn disable_wired_conn() {
+        assert!(
+            disable_wired_connections("config", "connections", &mut Transaction::default()).is_ok()
+        );
+
+        assert!(Path::new("config").exists());
+        assert!(Path::new("connections").exists());
+
+        let config_contents = fs::read_to_string("config/no-auto-default.conf").unwrap();
+        assert_eq!(config_contents, "[main]\nno-auto-default=*\n");
+
+        // cleanup
+        assert!(fs::remove_dir_all("config").is_ok());
+        assert!(fs::remove_dir_all("connections").is_ok());
+    }
+
+    #[test]
+    fn identify_host_successfully() {
+        let hosts = vec![
             Host {
                 hostname: "h1".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
                 interfaces: vec![Interface {
                     logical_name: "eth0".to_string(),
                     mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 }],
             },
             Host {
                 hostname: "h2".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
                 interfaces: vec![Interface {
                     logical_name: "".to_string(),
                     mac_address: Option::from("10:10:10:10:10:10".to_string()),
-                    interface_type: "".to_string(),
+                    interface_type: InterfaceType::from(""),
                     connection_ids: Vec::new(),
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 }],
             },
         ];
@@ -334,24 +2496,418 @@ mod tests {
                 index: 0,
             },
             NetworkInterface {
-                name: "eth0".to_string(),
-                mac_addr: Some("00:10:20:30:40:50".to_string()),
+                name: "eth0".to_string(),
+                mac_addr: Some("00:10:20:30:40:50".to_string()),
+                addr: vec![],
+                index: 0,
+            },
+        ];
+
+        let host = identify_host(hosts, &interfaces, &[]).unwrap().unwrap();
+        assert_eq!(host.hostname, "h1");
+        assert_eq!(
+            host.interfaces,
+            vec![Interface {
+                logical_name: "eth0".to_string(),
+                mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                interface_type: InterfaceType::Ethernet,
+                connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn identify_host_by_permanent_mac() {
+        let hosts = vec![Host {
+            hostname: "h1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![Interface {
+                logical_name: "eth0".to_string(),
+                mac_address: Some("00:11:22:33:44:55".to_string()),
+                interface_type: InterfaceType::Ethernet,
+                connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            }],
+        }];
+        // The bond port carries the MAC address of the bond rather than its own.
+        let interfaces = vec![NetworkInterface {
+            name: "eth0".to_string(),
+            mac_addr: Some("00:aa:bb:cc:dd:ee".to_string()),
+            addr: vec![],
+            index: 2,
+        }];
+
+        assert!(identify_host(hosts.clone(), &interfaces, &[])
+            .unwrap()
+            .is_none());
+
+        let interfaces = with_permanent_macs(interfaces, |name| {
+            (name == "eth0").then(|| "00:11:22:33:44:55".to_string())
+        });
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces[1].name, "eth0");
+        assert_eq!(interfaces[1].index, 2);
+
+        let host = identify_host(hosts, &interfaces, &[]).unwrap().unwrap();
+        assert_eq!(host.hostname, "h1");
+
+        // The permanent MAC address is not duplicated if it is also the current one.
+        let interfaces = with_permanent_macs(interfaces, |_| Some("00:aa:bb:cc:dd:ee".to_string()));
+        assert_eq!(interfaces.len(), 2);
+    }
+
+    #[test]
+    fn identify_host_from_live_state() -> Result<(), anyhow::Error> {
+        let contents = fs::read_to_string("testdata/apply/live-state.yaml")?;
+        let network_interfaces = parse_live_state(&contents)?;
+        assert_eq!(
+            network_interfaces
+                .iter()
+                .map(|nic| (nic.name.as_str(), nic.mac_addr.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("lo", Some("00:00:00:00:00:00")),
+                ("ens1f0", Some("00:11:22:33:44:55")),
+                ("ens1f1", Some("00:11:22:33:44:55")),
+                ("ens1f1", Some("00:11:22:33:44:58")),
+                ("bond0", Some("00:11:22:33:44:55")),
+            ]
+        );
+
+        let hosts = parse_hosts("testdata/apply/config")?;
+        let host = identify_host(hosts, &network_interfaces, &[])?.unwrap();
+        assert_eq!(host.hostname, "node1");
+
+        let local_interfaces = detect_local_interfaces(&host, network_interfaces, None);
+        assert_eq!(
+            local_interfaces.get("eth0").map(String::as_str),
+            Some("ens1f0")
+        );
+        assert_eq!(
+            local_interfaces.get("eth1").map(String::as_str),
+            Some("ens1f1")
+        );
+
+        assert!(parse_live_state("interfaces: invalid").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ip_link_output() -> Result<(), anyhow::Error> {
+        let output = br#"[
+            {"ifindex":1,"ifname":"lo","flags":["LOOPBACK","UP","LOWER_UP"],"mtu":65536,
+             "link_type":"loopback","address":"00:00:00:00:00:00","broadcast":"00:00:00:00:00:00"},
+            {"ifindex":2,"ifname":"eth0","flags":["BROADCAST","MULTICAST","UP","LOWER_UP"],"mtu":1500,
+             "link_type":"ether","address":"52:54:00:AB:CD:EF","broadcast":"ff:ff:ff:ff:ff:ff"},
+            {"ifindex":3,"ifname":"wg0","flags":["POINTOPOINT","NOARP","UP","LOWER_UP"],"mtu":1420,
+             "link_type":"none"}
+        ]"#;
+
+        let interfaces = parse_ip_link(output)?;
+        assert_eq!(interfaces.len(), 3);
+        assert_eq!(interfaces[1].name, "eth0");
+        assert_eq!(interfaces[1].index, 2);
+        assert_eq!(interfaces[1].mac_addr.as_deref(), Some("52:54:00:ab:cd:ef"));
+        assert_eq!(interfaces[2].name, "wg0");
+        assert_eq!(interfaces[2].mac_addr, None);
+
+        assert!(parse_ip_link(b"not json").is_err());
+
+        assert_eq!(parse_nic_provider("ip"), Ok(NicProvider::Ip));
+        assert!(parse_nic_provider("dbus").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn identify_host_by_bond_member_mac() {
+        let hosts = vec![Host {
+            hostname: "h1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![Interface {
+                logical_name: "bond0".to_string(),
+                mac_address: None,
+                interface_type: InterfaceType::Bond,
+                connection_ids: vec!["bond0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![
+                    "00:11:22:33:44:55".to_string(),
+                    "00:11:22:33:44:56".to_string(),
+                ],
+            }],
+        }];
+        // Only the second port of the bond is present locally.
+        let interfaces = [NetworkInterface {
+            name: "ens2f1".to_string(),
+            mac_addr: Some("00:11:22:33:44:56".to_string()),
+            addr: vec![],
+            index: 0,
+        }];
+
+        let host = identify_host(hosts, &interfaces, &[]).unwrap().unwrap();
+        assert_eq!(host.hostname, "h1");
+    }
+
+    #[test]
+    fn identify_host_prefers_most_matching_interfaces() {
+        let host = |hostname: &str, macs: &[&str]| Host {
+            hostname: hostname.to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: macs
+                .iter()
+                .enumerate()
+                .map(|(i, mac)| Interface {
+                    logical_name: format!("eth{i}"),
+                    mac_address: Some(mac.to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec![format!("eth{i}")],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                })
+                .collect(),
+        };
+        let nic = |name: &str, mac: &str| NetworkInterface {
+            name: name.to_string(),
+            mac_addr: Some(mac.to_string()),
+            addr: vec![],
+            index: 0,
+        };
+
+        // Both hosts share the management NIC but only "h2" matches the second one as well.
+        let hosts = vec![
+            host("h1", &["00:11:22:33:44:55", "00:00:00:00:00:01"]),
+            host("h2", &["00:11:22:33:44:55", "00:10:20:30:40:50"]),
+        ];
+        let interfaces = [
+            nic("eth0", "00:11:22:33:44:55"),
+            nic("eth1", "00:10:20:30:40:50"),
+        ];
+
+        let host_match = identify_host(hosts.clone(), &interfaces, &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(host_match.hostname, "h2");
+
+        // The shared management NIC flags "h1" as likely misconfigured.
+        assert_eq!(
+            unmatched_hosts_sharing_macs(&hosts, &host_match),
+            vec![("h1".to_string(), 1)]
+        );
+        assert!(unmatched_hosts_sharing_macs(&hosts[1..], &host_match).is_empty());
+
+        // Distinct hosts with an equal number of matches are ambiguous.
+        let interfaces = [nic("eth0", "00:11:22:33:44:55")];
+        let error = identify_host(hosts.clone(), &interfaces, &[]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Ambiguous host match: h1, h2 both match local NICs"
+        );
+        assert!(matches!(
+            error.downcast_ref::<ConfigError>(),
+            Some(ConfigError::AmbiguousHost(hostnames)) if hostnames == &["h1", "h2"]
+        ));
+
+        // Duplicate entries of the same host are not.
+        let hosts = vec![
+            host("h1", &["00:11:22:33:44:55"]),
+            host("h1", &["00:11:22:33:44:55"]),
+        ];
+        let host_match = identify_host(hosts, &interfaces, &[]).unwrap().unwrap();
+        assert_eq!(host_match.hostname, "h1");
+    }
+
+    #[test]
+    fn identify_host_with_dash_separated_mac_addresses() -> Result<(), anyhow::Error> {
+        let config_dir = "_mac-out";
+        fs::create_dir_all(config_dir)?;
+        fs::write(
+            Path::new(config_dir).join("host_config.yaml"),
+            "- hostname: h1\n  interfaces:\n    - logical_name: eth0\n      mac_address: ' 00-11-22-AA-BB-CC '\n      interface_type: ethernet\n      connection_ids:\n        - eth0\n",
+        )?;
+
+        let hosts = parse_hosts(config_dir)?;
+        assert_eq!(
+            hosts[0].interfaces[0].mac_address.as_deref(),
+            Some("00:11:22:aa:bb:cc")
+        );
+
+        let interfaces = [NetworkInterface {
+            name: "eth0".to_string(),
+            mac_addr: Some("00:11:22:aa:bb:cc".to_string()),
+            addr: vec![],
+            index: 0,
+        }];
+        let host = identify_host(hosts, &interfaces, &[])?.unwrap();
+        assert_eq!(host.hostname, "h1");
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn parse_hosts_defaults_interface_type() -> Result<(), anyhow::Error> {
+        let config_dir = "_interface-type-out";
+        fs::create_dir_all(config_dir)?;
+        fs::write(
+            Path::new(config_dir).join("host_config.yaml"),
+            "- hostname: h1\n  interfaces:\n    - logical_name: eth0\n      mac_address: 00:11:22:33:44:55\n",
+        )?;
+
+        let hosts = parse_hosts(config_dir)?;
+        assert_eq!(
+            hosts[0].interfaces[0].interface_type,
+            InterfaceType::Ethernet
+        );
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn set_hostname_with_trailing_newline() -> io::Result<()> {
+        let dir = "_hostname-out";
+        let path = Path::new(dir).join("hostname");
+        fs::create_dir_all(dir)?;
+
+        set_hostname(
+            path.to_str().unwrap(),
+            " node1 ",
+            &mut Transaction::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path)?, "node1\n");
+
+        // cleanup
+        fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn identify_host_by_mac_set_hash() {
+        let interfaces = [
+            NetworkInterface {
+                name: "lo".to_string(),
+                mac_addr: Some("00:00:00:00:00:00".to_string()),
+                addr: vec![],
+                index: 0,
+            },
+            NetworkInterface {
+                name: "eth0".to_string(),
+                mac_addr: Some("00:11:22:33:44:55".to_string()),
+                addr: vec![],
+                index: 0,
+            },
+            NetworkInterface {
+                name: "eth1".to_string(),
+                mac_addr: Some("00:11:22:33:44:56".to_string()),
                 addr: vec![],
                 index: 0,
             },
         ];
+        let hash = mac_set_hash("s3cr3t", &["00-11-22-33-44-56", "00:11:22:33:44:55"]);
+        let hosts = vec![
+            Host {
+                hostname: "h1".to_string(),
+                mac_set_hash: Some(mac_set_hash("s3cr3t", &["00:11:22:33:44:55"])),
+                serial: None,
+                group: None,
+                priority: None,
+                interfaces: vec![],
+            },
+            Host {
+                hostname: "h2".to_string(),
+                mac_set_hash: Some(hash),
+                serial: None,
+                group: None,
+                priority: None,
+                interfaces: vec![Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: None,
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                }],
+            },
+        ];
 
-        let host = identify_host(hosts, &interfaces).unwrap();
-        assert_eq!(host.hostname, "h1");
+        // Only the physical NICs are hashed, a bridge created since (e.g. by a previous apply) does not matter.
+        let physical_macs = [
+            "00:11:22:33:44:55".to_string(),
+            "00:11:22:33:44:56".to_string(),
+        ];
+        let host = identify_host(hosts.clone(), &interfaces, &physical_macs)
+            .unwrap()
+            .unwrap();
+        assert_eq!(host.hostname, "h2");
+
+        assert!(identify_host(hosts, &interfaces, &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn detect_physical_nic_macs() -> Result<(), anyhow::Error> {
+        let sys_class_net = Path::new("_sys-class-net");
+        for (name, address, physical) in [
+            ("eth0", "00:11:22:33:44:55", true),
+            ("eth1", "00:11:22:33:44:55", true),
+            ("bond0", "00:11:22:33:44:55", false),
+            ("docker0", "02:42:ac:11:00:01", false),
+        ] {
+            let dir = sys_class_net.join(name);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("address"), format!("{address}\n"))?;
+            if physical {
+                fs::create_dir_all(dir.join("device"))?;
+            }
+        }
+
+        // The bond port eth1 carries the MAC address of eth0 while its permanent one differs.
+        let permanent_mac = |name: &str| (name == "eth1").then(|| "00:11:22:33:44:56".to_string());
+        let mut macs = physical_nic_macs(sys_class_net, permanent_mac)?;
+        macs.sort();
+        assert_eq!(macs, vec!["00:11:22:33:44:55", "00:11:22:33:44:56"]);
+
+        let live_state = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            mac-address: 00:11:22:33:44:55
+          - name: eth1
+            type: ethernet
+            mac-address: 00:11:22:33:44:55
+            permanent-mac-address: 00:11:22:33:44:56
+          - name: bond0
+            type: bond
+            mac-address: 00:11:22:33:44:55
+        "#;
         assert_eq!(
-            host.interfaces,
-            vec![Interface {
-                logical_name: "eth0".to_string(),
-                mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                interface_type: "ethernet".to_string(),
-                connection_ids: vec!["eth0".to_string()],
-            }]
+            live_state_physical_macs(live_state)?,
+            vec!["00:11:22:33:44:55", "00:11:22:33:44:56"]
         );
+
+        // cleanup
+        fs::remove_dir_all(sys_class_net)?;
+
+        Ok(())
     }
 
     #[test]
@@ -359,31 +2915,402 @@ mod tests {
         let hosts = vec![
             Host {
                 hostname: "h1".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
                 interfaces: vec![Interface {
                     logical_name: "eth0".to_string(),
                     mac_address: Option::from("10:20:30:40:50:60".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 }],
             },
             Host {
                 hostname: "h2".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
                 interfaces: vec![Interface {
                     logical_name: "".to_string(),
                     mac_address: Option::from("00:10:20:30:40:50".to_string()),
-                    interface_type: "".to_string(),
+                    interface_type: InterfaceType::from(""),
                     connection_ids: Vec::new(),
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                }],
+            },
+        ];
+        let interfaces = [NetworkInterface {
+            name: "eth0".to_string(),
+            mac_addr: Some("00:11:22:33:44:55".to_string()),
+            addr: vec![],
+            index: 0,
+        }];
+
+        assert!(identify_host(hosts, &interfaces, &[]).unwrap().is_none())
+    }
+
+    /// Options confining `apply` to `dir` and providing a live state without any NICs,
+    /// so that none of the hosts in the test config match regardless of the machine running the tests.
+    fn isolated_apply_options(dir: &Path) -> io::Result<ApplyOptions> {
+        fs::create_dir_all(dir)?;
+        let live_state = dir.join("live-state.yaml");
+        fs::write(&live_state, "interfaces: []\n")?;
+
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        Ok(ApplyOptions {
+            live_state: Some(live_state),
+            nm_connections_dir: path("system-connections"),
+            nm_config_dir: path("conf.d"),
+            nm_certs_dir: path("certs"),
+            state_file: path("state.json"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn apply_fails_on_ambiguous_host() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_ambiguous-config");
+        fs::create_dir_all(config_dir)?;
+        // Both hosts match the local NIC "ens1f0" only.
+        fs::write(
+            config_dir.join("host_config.yaml"),
+            r#"
+- hostname: node1
+  interfaces:
+    - logical_name: eth0
+      mac_address: 00:11:22:33:44:55
+      interface_type: ethernet
+      connection_ids: [eth0]
+    - logical_name: eth1
+      mac_address: 00:11:22:33:44:01
+      interface_type: ethernet
+      connection_ids: [eth1]
+- hostname: node2
+  interfaces:
+    - logical_name: eth0
+      mac_address: 00:11:22:33:44:55
+      interface_type: ethernet
+      connection_ids: [eth0]
+    - logical_name: eth1
+      mac_address: 00:11:22:33:44:02
+      interface_type: ethernet
+      connection_ids: [eth1]
+"#,
+        )?;
+        let dir = Path::new("_ambiguous");
+        let options = ApplyOptions {
+            live_state: Some("testdata/apply/live-state.yaml".into()),
+            ..isolated_apply_options(dir)?
+        };
+        let system = SystemFiles {
+            hostname: "_ambiguous/hostname",
+            kernel_hostname: "_ambiguous/kernel-hostname",
+            product_serial: "_ambiguous/product_serial",
+            sys_class_net: "_ambiguous/net",
+        };
+
+        let error = apply_on(config_dir.to_str().unwrap(), &options, &system).unwrap_err();
+        assert!(matches!(
+            &error,
+            ConfigError::AmbiguousHost(hostnames) if hostnames == &["node1", "node2"]
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Ambiguous host match: node1, node2 both match local NICs"
+        );
+        assert!(!dir.join("system-connections").exists());
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_allows_no_match() -> Result<(), anyhow::Error> {
+        let config_dir = "testdata/apply/config";
+        let dir = Path::new("_no-match");
+        let options = isolated_apply_options(dir)?;
+        // Neither the hostname nor the serial are available.
+        let system = SystemFiles {
+            hostname: "_no-match/hostname",
+            kernel_hostname: "_no-match/kernel-hostname",
+            product_serial: "_no-match/product_serial",
+            sys_class_net: "_no-match/net",
+        };
+
+        assert!(matches!(
+            apply_on(config_dir, &options, &system),
+            Err(ConfigError::NoMatchingHost)
+        ));
+
+        let report = dir.join("report.json");
+        let options = ApplyOptions {
+            allow_no_match: true,
+            report: Some(report.clone()),
+            ..options
+        };
+        assert!(apply_on(config_dir, &options, &system).is_ok());
+
+        assert!(!Path::new(system.hostname).exists());
+        assert!(!dir.join("conf.d").exists());
+        assert!(!dir.join("system-connections").exists());
+        assert_eq!(
+            fs::read_to_string(&report)?,
+            "{\n  \"hostname\": null,\n  \"interfaces\": []\n}"
+        );
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_skips_unchanged_config() -> Result<(), anyhow::Error> {
+        let config_dir = "testdata/apply/config";
+        let dir = Path::new("_if-changed");
+        let options = ApplyOptions {
+            if_changed: true,
+            ..isolated_apply_options(dir)?
+        };
+        let state_file = Path::new(&options.state_file);
+        let system = SystemFiles {
+            hostname: "_if-changed/hostname",
+            kernel_hostname: "_if-changed/kernel-hostname",
+            product_serial: "_if-changed/product_serial",
+            sys_class_net: "_if-changed/net",
+        };
+
+        // Nothing was recorded yet, so the config is applied and fails to match any host.
+        assert!(matches!(
+            apply_on(config_dir, &options, &system),
+            Err(ConfigError::NoMatchingHost)
+        ));
+        assert!(!state_file.exists());
+
+        let hash = state::config_hash(Path::new(config_dir))?;
+        state::write_state(state_file, &ApplyState::new(None, "outdated".to_string()))?;
+        assert!(matches!(
+            apply_on(config_dir, &options, &system),
+            Err(ConfigError::NoMatchingHost)
+        ));
+
+        state::write_state(state_file, &ApplyState::new(Some("h1".to_string()), hash))?;
+        assert!(apply_on(config_dir, &options, &system).is_ok());
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn identify_host_by_hostname_when_no_mac_matches() {
+        let hosts = vec![
+            Host {
+                hostname: "h1".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
+                interfaces: vec![Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                }],
+            },
+            Host {
+                hostname: "h2".to_string(),
+                mac_set_hash: None,
+                serial: None,
+                group: None,
+                priority: None,
+                interfaces: vec![Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("10:20:30:40:50:60".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 }],
             },
         ];
         let interfaces = [NetworkInterface {
-            name: "eth0".to_string(),
+            name: "eth0".to_string(),
+            mac_addr: Some("00:10:20:30:40:50".to_string()),
+            addr: vec![],
+            index: 0,
+        }];
+
+        assert!(identify_host(hosts.clone(), &interfaces, &[])
+            .unwrap()
+            .is_none());
+
+        let host = identify_host_by_hostname(hosts.clone(), "h2").unwrap();
+        assert_eq!(host.hostname, "h2");
+
+        assert!(identify_host_by_hostname(hosts, "h3").is_none());
+    }
+
+    #[test]
+    fn check_bond_port_speeds_reports_mismatch() -> Result<(), anyhow::Error> {
+        let dir = "_bond-speed-config";
+        fs::create_dir_all(dir)?;
+        let port = |name: &str, bond: &str| {
+            fs::write(
+                Path::new(dir).join(format!("{name}.nmconnection")),
+                format!("[connection]\nid={name}\ninterface-name={name}\nmaster={bond}\nslave-type=bond\n"),
+            )
+        };
+        port("eth0", "bond0")?;
+        port("eth1", "bond0")?;
+        port("eth2", "bond1")?;
+        port("eth3", "bond1")?;
+        fs::write(
+            Path::new(dir).join("bond0.nmconnection"),
+            "[connection]\nid=bond0\ninterface-name=bond0\ntype=bond\n",
+        )?;
+
+        let local_interfaces = HashMap::from([("eth1".to_string(), "ens2f0".to_string())]);
+        let speed = |name: &str| match name {
+            "eth0" => Some(1000),
+            "ens2f0" => Some(10000),
+            "eth2" | "eth3" => Some(25000),
+            _ => None,
+        };
+
+        let warnings = check_bond_port_speeds(Path::new(dir), &local_interfaces, speed)?;
+        assert_eq!(
+            warnings,
+            vec!["Ports of bond bond0 have differing link speeds: ens2f0 (10000 Mb/s), eth0 (1000 Mb/s)"]
+        );
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_host_by_hostname() {
+        let host = |hostname: &str| Host {
+            hostname: hostname.to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![],
+        };
+        let hosts = vec![host("h2"), host("h1")];
+
+        assert_eq!(select_host(hosts.clone(), "h1").unwrap().hostname, "h1");
+
+        let error = select_host(hosts, "h3").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Host 'h3' not found in config, known hosts: h1, h2"
+        );
+    }
+
+    #[test]
+    fn identify_host_by_product_serial() {
+        let host = |hostname: &str, serial: Option<&str>| Host {
+            hostname: hostname.to_string(),
+            mac_set_hash: None,
+            serial: serial.map(str::to_string),
+            group: None,
+            priority: None,
+            interfaces: vec![],
+        };
+        let hosts = vec![
+            host("h1", None),
+            host("h2", Some("SN-0002")),
+            host("h3", Some("SN-0003")),
+        ];
+
+        let matched = identify_host_by_serial(&hosts, Some("SN-0003"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(matched.hostname, "h3");
+
+        assert!(identify_host_by_serial(&hosts, Some("SN-0004"))
+            .unwrap()
+            .is_none());
+        assert!(identify_host_by_serial(&hosts, None).unwrap().is_none());
+
+        let hosts = vec![host("h2", Some("SN-0002")), host("h4", Some("SN-0002"))];
+        let error = identify_host_by_serial(&hosts, Some("SN-0002")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Ambiguous host match: h2 and h4 share the serial 'SN-0002'"
+        );
+    }
+
+    #[test]
+    fn read_product_serial() {
+        let path = "_product-serial";
+
+        fs::write(path, "SN-0001\n").unwrap();
+        assert_eq!(product_serial(path).as_deref(), Some("SN-0001"));
+
+        fs::write(path, "Not Specified\n").unwrap();
+        assert_eq!(product_serial(path), None);
+
+        fs::remove_file(path).unwrap();
+        assert_eq!(product_serial(path), None);
+    }
+
+    #[test]
+    fn identify_host_ignores_excluded_interfaces() {
+        let hosts = vec![Host {
+            hostname: "h1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("10:20:30:40:50:60".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "usb0".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["usb0".to_string()],
+                    exclude_from_match: true,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+            ],
+        }];
+        let interfaces = [NetworkInterface {
+            name: "usb0".to_string(),
             mac_addr: Some("00:11:22:33:44:55".to_string()),
             addr: vec![],
             index: 0,
         }];
 
-        assert!(identify_host(hosts, &interfaces).is_none())
+        assert!(identify_host(hosts, &interfaces, &[]).unwrap().is_none())
     }
 
     #[test]
@@ -400,70 +3327,109 @@ mod tests {
             vec![
                 Host {
                     hostname: "node1".to_string(),
+                    mac_set_hash: None,
+                    serial: None,
+                    group: None,
+                    priority: None,
                     interfaces: vec![
                         Interface {
                             logical_name: "eth0".to_string(),
                             mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                            interface_type: "ethernet".to_string(),
+                            interface_type: InterfaceType::Ethernet,
                             connection_ids: vec!["eth0".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "eth1".to_string(),
                             mac_address: Option::from("00:11:22:33:44:58".to_string()),
-                            interface_type: "ethernet".to_string(),
+                            interface_type: InterfaceType::Ethernet,
                             connection_ids: vec!["eth1".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "eth2".to_string(),
                             mac_address: Option::from("36:5e:6b:a2:ed:80".to_string()),
-                            interface_type: "ethernet".to_string(),
+                            interface_type: InterfaceType::Ethernet,
                             connection_ids: vec!["eth2".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "bond0".to_string(),
                             mac_address: Option::from("00:11:22:aa:44:58".to_string()),
-                            interface_type: "bond".to_string(),
+                            interface_type: InterfaceType::Bond,
                             connection_ids: vec!["bond0".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                     ],
                 },
                 Host {
                     hostname: "node2".to_string(),
+                    mac_set_hash: None,
+                    serial: None,
+                    group: None,
+                    priority: None,
                     interfaces: vec![
                         Interface {
                             logical_name: "eth0".to_string(),
                             mac_address: Option::from("36:5e:6b:a2:ed:81".to_string()),
-                            interface_type: "ethernet".to_string(),
+                            interface_type: InterfaceType::Ethernet,
                             connection_ids: vec!["eth0".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "eth0.1365".to_string(),
                             mac_address: None,
-                            interface_type: "vlan".to_string(),
+                            interface_type: InterfaceType::Vlan,
                             connection_ids: vec!["eth0.1365".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                     ],
                 },
                 Host {
                     hostname: "node3".to_string(),
+                    mac_set_hash: None,
+                    serial: None,
+                    group: None,
+                    priority: None,
                     interfaces: vec![
                         Interface {
                             logical_name: "br1".to_string(),
                             mac_address: None,
-                            interface_type: "ovs-bridge".to_string(),
+                            interface_type: InterfaceType::OvsBridge,
                             connection_ids: vec!["br1-br".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "ovs0".to_string(),
                             mac_address: None,
-                            interface_type: "ovs-interface".to_string(),
+                            interface_type: InterfaceType::from("ovs-interface"),
                             connection_ids: vec!["ovs0-port".to_string(), "ovs0-if".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                         Interface {
                             logical_name: "eth0".to_string(),
                             mac_address: Option::from("95:b2:92:88:1d:3f".to_string()),
-                            interface_type: "ethernet".to_string(),
+                            interface_type: InterfaceType::Ethernet,
                             connection_ids: vec!["eth0".to_string(), "eth0-port".to_string()],
+                            exclude_from_match: false,
+                            unmanaged: false,
+                            member_mac_addresses: vec![],
                         },
                     ],
                 },
@@ -475,36 +3441,55 @@ mod tests {
     fn detect_interface_differences() {
         let host = Host {
             hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
             interfaces: vec![
                 Interface {
                     logical_name: "eth0".to_string(),
                     mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth0.1365".to_string(),
                     mac_address: None,
-                    interface_type: "vlan".to_string(),
+                    interface_type: InterfaceType::Vlan,
                     connection_ids: vec!["eth0.1365".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth2".to_string(),
                     mac_address: Option::from("00:11:22:33:44:56".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth2".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth2.bridge".to_string(),
                     mac_address: None,
-                    interface_type: "linux-bridge".to_string(),
+                    interface_type: InterfaceType::LinuxBridge,
                     connection_ids: vec!["eth2.bridge".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "bond0".to_string(),
                     mac_address: Option::from("00:11:22:33:44:58".to_string()),
-                    interface_type: "bond".to_string(),
+                    interface_type: InterfaceType::Bond,
                     connection_ids: vec!["bond0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
             ],
         };
@@ -529,7 +3514,7 @@ mod tests {
             },
         ];
 
-        let local_interfaces = detect_local_interfaces(&host, interfaces);
+        let local_interfaces = detect_local_interfaces(&host, interfaces, None);
         assert_eq!(
             local_interfaces,
             HashMap::from([
@@ -539,26 +3524,639 @@ mod tests {
         )
     }
 
+    #[test]
+    fn handle_replaced_nic() {
+        let interface = |name: &str, mac: &str| Interface {
+            logical_name: name.to_string(),
+            mac_address: Some(mac.to_string()),
+            interface_type: InterfaceType::Ethernet,
+            connection_ids: vec![name.to_string()],
+            exclude_from_match: false,
+            unmanaged: false,
+            member_mac_addresses: vec![],
+        };
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                interface("eth0", "00:11:22:33:44:55"),
+                interface("eth1", "00:11:22:33:44:56"),
+            ],
+        };
+        let nic = |name: &str, mac: &str| NetworkInterface {
+            name: name.to_string(),
+            mac_addr: Some(mac.to_string()),
+            addr: vec![],
+            index: 0,
+        };
+        // The NIC of eth0 was replaced.
+        let network_interfaces = [
+            nic("eth0", "00:aa:bb:cc:dd:ee"),
+            nic("eth1", "00:11:22:33:44:56"),
+        ];
+
+        let mut warned = host.clone();
+        handle_mac_changes(&mut warned, &network_interfaces, MacChangePolicy::Warn).unwrap();
+        assert_eq!(warned, host);
+
+        let mut failed = host.clone();
+        assert_eq!(
+            handle_mac_changes(&mut failed, &network_interfaces, MacChangePolicy::Fail)
+                .unwrap_err()
+                .to_string(),
+            "Detected interfaces whose MAC address is not present locally: eth0 (00:11:22:33:44:55)"
+        );
+
+        let mut renamed = host.clone();
+        handle_mac_changes(&mut renamed, &network_interfaces, MacChangePolicy::Rename).unwrap();
+        assert_eq!(
+            renamed.interfaces[0].mac_address.as_deref(),
+            Some("00:aa:bb:cc:dd:ee")
+        );
+        assert_eq!(renamed.interfaces[1], host.interfaces[1]);
+        assert!(detect_local_interfaces(&renamed, network_interfaces.to_vec(), None).is_empty());
+
+        // The replacement is not named after the preconfigured interface.
+        let network_interfaces = [
+            nic("ens1f0", "00:aa:bb:cc:dd:ee"),
+            nic("eth1", "00:11:22:33:44:56"),
+        ];
+        let mut renamed = host.clone();
+        assert_eq!(
+            handle_mac_changes(&mut renamed, &network_interfaces, MacChangePolicy::Rename)
+                .unwrap_err()
+                .to_string(),
+            "Detected interfaces whose MAC address is not present locally: eth0 (00:11:22:33:44:55), \
+             no local NIC named eth0 can replace it"
+        );
+
+        assert_eq!(
+            parse_mac_change_policy("rename"),
+            Ok(MacChangePolicy::Rename)
+        );
+        assert!(parse_mac_change_policy("ignore").is_err());
+    }
+
+    #[test]
+    fn update_bound_mac_address_of_keyfile() {
+        let contents = "[connection]\nid=eth0\n\n[ethernet]\nmac-address=00:11:22:33:44:55\n";
+        assert_eq!(
+            update_bound_mac_address(contents, "00:aa:bb:cc:dd:ee").unwrap(),
+            "[connection]\nid=eth0\n\n[ethernet]\nmac-address=00:aa:bb:cc:dd:ee\n"
+        );
+        assert_eq!(
+            update_bound_mac_address(contents, "00:11:22:33:44:55").unwrap(),
+            contents
+        );
+
+        let contents = "[connection]\nid=eth0\ninterface-name=eth0\n";
+        assert_eq!(
+            update_bound_mac_address(contents, "00:aa:bb:cc:dd:ee").unwrap(),
+            contents
+        );
+    }
+
+    #[test]
+    fn detect_local_interfaces_under_mac_match_mask() {
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![Interface {
+                logical_name: "eth0".to_string(),
+                mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                interface_type: InterfaceType::Ethernet,
+                connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            }],
+        };
+        // Same MAC address with the locally administered bit set.
+        let network_interfaces = vec![NetworkInterface {
+            name: "ens1f0".to_string(),
+            mac_addr: Some("02:11:22:33:44:55".to_string()),
+            addr: vec![],
+            index: 0,
+        }];
+
+        assert!(detect_local_interfaces(&host, network_interfaces.clone(), None).is_empty());
+
+        let mask = parse_mac_match_mask("fd:ff:ff:ff:ff:ff").unwrap();
+        assert_eq!(
+            detect_local_interfaces(&host, network_interfaces.clone(), Some(mask)),
+            HashMap::from([("eth0".to_string(), "ens1f0".to_string())])
+        );
+
+        let mask = parse_mac_match_mask("00-FF-FF-FF-FF-FF").unwrap();
+        assert_eq!(
+            detect_local_interfaces(&host, network_interfaces.clone(), Some(mask)),
+            HashMap::from([("eth0".to_string(), "ens1f0".to_string())])
+        );
+
+        let mask = parse_mac_match_mask("ff:ff:ff:ff:ff:ff").unwrap();
+        assert!(detect_local_interfaces(&host, network_interfaces, Some(mask)).is_empty());
+
+        // Interfaces without MAC address (e.g. hashed ones) are not mapped to NICs lacking one either.
+        let mut host = host;
+        host.interfaces[0].mac_address = None;
+        let network_interfaces = vec![NetworkInterface {
+            name: "wg0".to_string(),
+            mac_addr: None,
+            addr: vec![],
+            index: 0,
+        }];
+        assert!(detect_local_interfaces(&host, network_interfaces, None).is_empty());
+
+        assert_eq!(
+            parse_mac_match_mask("ff:ff:ff").unwrap_err(),
+            "invalid MAC match mask 'ff:ff:ff', expected six hex octets (e.g. 'fd:ff:ff:ff:ff:ff')"
+        );
+    }
+
+    #[test]
+    fn detect_local_interfaces_after_renames_uses_new_names() -> Result<(), anyhow::Error> {
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth0.100".to_string(),
+                    mac_address: None,
+                    interface_type: InterfaceType::Vlan,
+                    connection_ids: vec!["eth0.100".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+            ],
+        };
+        let nic = |name: &str| NetworkInterface {
+            name: name.to_string(),
+            mac_addr: Some("00:11:22:33:44:55".to_string()),
+            addr: vec![],
+            index: 0,
+        };
+
+        // Without the renames the connections would target the current name.
+        assert_eq!(
+            detect_local_interfaces(&host, vec![nic("ens1f0")], None),
+            HashMap::from([
+                ("eth0".to_string(), "ens1f0".to_string()),
+                ("eth0.100".to_string(), "ens1f0.100".to_string())
+            ])
+        );
+
+        let link_dir = "_persist-names-links";
+        let renamed = std::cell::Cell::new(false);
+
+        let local_interfaces = detect_local_interfaces_after_renames(
+            &host,
+            || {
+                store_link_files(
+                    &host,
+                    link_dir,
+                    &ApplyOptions::default(),
+                    &mut Transaction::default(),
+                )?;
+                renamed.set(true);
+                Ok(())
+            },
+            || Ok(vec![nic(if renamed.get() { "eth0" } else { "ens1f0" })]),
+            None,
+        )?;
+        assert!(local_interfaces.is_empty());
+
+        assert_eq!(
+            fs::read_to_string(Path::new(link_dir).join("10-nmc-eth0.link"))?,
+            "[Match]\nMACAddress=00:11:22:33:44:55\n\n[Link]\nName=eth0\n"
+        );
+        assert_eq!(fs::read_dir(link_dir)?.count(), 1);
+
+        fs::remove_dir_all(link_dir)?;
+
+        // NICs which were not renamed keep being handled by the regular detection.
+        let local_interfaces = detect_local_interfaces_after_renames(
+            &host,
+            || Ok(()),
+            || Ok(vec![nic("ens1f0")]),
+            None,
+        )?;
+        assert_eq!(local_interfaces.get("eth0"), Some(&"ens1f0".to_string()));
+
+        assert!(detect_local_interfaces_after_renames(
+            &host,
+            || Err(anyhow::anyhow!("udevadm failed")),
+            || Ok(vec![]),
+            None
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn detect_interface_differences_respects_name_boundaries() {
+        let interface =
+            |logical_name: &str, mac_address: Option<&str>, interface_type: &str| Interface {
+                logical_name: logical_name.to_string(),
+                mac_address: mac_address.map(str::to_string),
+                interface_type: interface_type.into(),
+                connection_ids: vec![logical_name.to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            };
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                interface("eth1", Some("00:11:22:33:44:55"), "ethernet"),
+                interface("eth10", Some("00:11:22:33:44:56"), "ethernet"),
+                interface("eth1.100", None, "vlan"),
+                interface("veth1", None, "veth"),
+            ],
+        };
+        let interfaces = vec![
+            NetworkInterface {
+                name: "ens1f0".to_string(),
+                mac_addr: Some("00:11:22:33:44:55".to_string()),
+                addr: vec![],
+                index: 0,
+            },
+            NetworkInterface {
+                name: "eth10".to_string(),
+                mac_addr: Some("00:11:22:33:44:56".to_string()),
+                addr: vec![],
+                index: 0,
+            },
+        ];
+
+        let local_interfaces = detect_local_interfaces(&host, interfaces, None);
+        assert_eq!(
+            local_interfaces,
+            HashMap::from([
+                ("eth1".to_string(), "ens1f0".to_string()),
+                ("eth1.100".to_string(), "ens1f0.100".to_string())
+            ])
+        )
+    }
+
+    #[test]
+    fn detect_interface_differences_is_idempotent() {
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth0.1365".to_string(),
+                    mac_address: None,
+                    interface_type: InterfaceType::Vlan,
+                    connection_ids: vec!["eth0.1365".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+            ],
+        };
+        let nic = |name: &str| NetworkInterface {
+            name: name.to_string(),
+            mac_addr: Some("00:11:22:33:44:55".to_string()),
+            addr: vec![],
+            index: 0,
+        };
+
+        // Second run after the NIC was persistently renamed to its preconfigured name.
+        let local_interfaces = detect_local_interfaces(
+            &host,
+            vec![nic("br-ex"), nic("eth0"), nic("eth0.1365")],
+            None,
+        );
+        assert!(local_interfaces.is_empty());
+
+        // Second run after "eth0" -> "ens1f0" was applied and NM created the VLAN on top of it.
+        let local_interfaces =
+            detect_local_interfaces(&host, vec![nic("ens1f0.1365"), nic("ens1f0")], None);
+        assert_eq!(
+            local_interfaces,
+            HashMap::from([
+                ("eth0".to_string(), "ens1f0".to_string()),
+                ("eth0.1365".to_string(), "ens1f0.1365".to_string())
+            ])
+        );
+    }
+
     #[test]
     fn copy_unified_connection_files_successfully() -> io::Result<()> {
         let source_dir = "testdata/apply/node1";
         let destination_dir = "_all-out";
 
-        assert!(copy_unified_connection_files(source_dir.into(), destination_dir).is_ok());
+        assert!(copy_unified_connection_files(
+            source_dir.into(),
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default()
+        )
+        .is_ok());
+
+        let destination_path = Path::new(destination_dir);
+        for entry in fs::read_dir(source_dir)? {
+            let entry = entry?;
+            let filename = entry.file_name().into_string().unwrap();
+
+            let input = fs::read_to_string(entry.path())?;
+            let output = fs::read_to_string(destination_path.join(&filename))?;
+
+            assert_eq!(input, output);
+        }
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)
+    }
+
+    #[test]
+    fn copy_unified_connection_files_selection() -> Result<(), anyhow::Error> {
+        let source_dir = "testdata/apply/node1";
+        let destination_dir = "_select-out";
+        let options = ApplyOptions {
+            select: vec!["eth0".to_string(), "bond0".to_string()],
+            ..Default::default()
+        };
+
+        copy_unified_connection_files(
+            source_dir.into(),
+            destination_dir,
+            &options,
+            &mut Transaction::default(),
+        )?;
+
+        let mut copied: Vec<String> = fs::read_dir(destination_dir)?
+            .map(|entry| entry.map(|e| e.file_name().into_string().unwrap()))
+            .collect::<Result<_, _>>()?;
+        copied.sort();
+        assert_eq!(copied, vec!["bond0.nmconnection", "eth0.nmconnection"]);
+
+        let options = ApplyOptions {
+            select: vec!["eth0".to_string(), "eth9".to_string()],
+            ..Default::default()
+        };
+        let error = copy_unified_connection_files(
+            source_dir.into(),
+            destination_dir,
+            &options,
+            &mut Transaction::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Selected connections not found in unified config: eth9"
+        );
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_unified_connection_files_with_subdirs() -> Result<(), anyhow::Error> {
+        let source_dir = Path::new("_nested-source");
+        let destination_dir = "_nested-out";
+        let keyfile = "[connection]\nid=eth0\ntype=ethernet\n";
+        fs::create_dir_all(source_dir.join("site"))?;
+        fs::write(source_dir.join("eth0.nmconnection"), keyfile)?;
+        fs::write(source_dir.join("site").join("eth1.nmconnection"), keyfile)?;
+
+        let copy = |unified_layout| {
+            let options = ApplyOptions {
+                unified_layout,
+                ..Default::default()
+            };
+            copy_unified_connection_files(
+                source_dir.into(),
+                destination_dir,
+                &options,
+                &mut Transaction::default(),
+            )
+        };
+        let destination = Path::new(destination_dir);
+
+        copy(UnifiedLayout::Ignore)?;
+        assert!(destination.join("eth0.nmconnection").exists());
+        assert!(!destination.join("site").exists());
+        fs::remove_dir_all(destination_dir)?;
+
+        copy(UnifiedLayout::Nested)?;
+        assert!(destination.join("eth0.nmconnection").exists());
+        assert_eq!(
+            fs::read_to_string(destination.join("site").join("eth1.nmconnection"))?,
+            keyfile
+        );
+        fs::remove_dir_all(destination_dir)?;
+
+        copy(UnifiedLayout::Flatten)?;
+        assert!(destination.join("eth0.nmconnection").exists());
+        assert!(destination.join("eth1.nmconnection").exists());
+        assert!(!destination.join("site").exists());
+        fs::remove_dir_all(destination_dir)?;
+
+        fs::write(source_dir.join("site").join("eth0.nmconnection"), keyfile)?;
+        assert!(copy(UnifiedLayout::Flatten)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Conflicting connection file"));
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)?;
+        fs::remove_dir_all(source_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_unified_connection_files_rejects_duplicate_mac_bindings() -> Result<(), anyhow::Error> {
+        let source_dir = Path::new("_duplicate-mac-source");
+        let destination_dir = "_duplicate-mac-out";
+        let keyfile = |id: &str, mac_address: &str| {
+            format!(
+                "[connection]\nid={id}\ntype=ethernet\n\n[ethernet]\nmac-address={mac_address}\n"
+            )
+        };
+        fs::create_dir_all(source_dir)?;
+        fs::write(
+            source_dir.join("eth0.nmconnection"),
+            keyfile("eth0", "00:11:22:33:44:55"),
+        )?;
+        fs::write(
+            source_dir.join("eth1.nmconnection"),
+            keyfile("eth1", "00:11:22:33:44:56"),
+        )?;
+
+        let copy = |options: &ApplyOptions| {
+            copy_unified_connection_files(
+                source_dir.into(),
+                destination_dir,
+                options,
+                &mut Transaction::default(),
+            )
+        };
+
+        copy(&ApplyOptions::default())?;
+        fs::remove_dir_all(destination_dir)?;
+
+        fs::write(
+            source_dir.join("uplink.nmconnection"),
+            keyfile("uplink", "00-11-22-33-44-55"),
+        )?;
+        let error = copy(&ApplyOptions::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Connection files in unified config bind the same MAC address: \
+             00:11:22:33:44:55 (eth0.nmconnection, uplink.nmconnection)"
+        );
+        assert!(!Path::new(destination_dir).exists());
+
+        // Keyfiles which are not copied do not conflict.
+        copy(&ApplyOptions {
+            select: vec!["eth0".to_string(), "eth1".to_string()],
+            ..Default::default()
+        })?;
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)?;
+        fs::remove_dir_all(source_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn health_check_passes_immediately() {
+        let options = ApplyOptions {
+            health_rollback: true,
+            ..Default::default()
+        };
+        assert!(verify_health("true", &options, &mut Transaction::default()).is_ok());
+        assert!(run_health_check("exit 0", Duration::ZERO, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn health_check_timeout_triggers_rollback() -> Result<(), anyhow::Error> {
+        let dir = "_health-check-out";
+        let path = Path::new(dir).join("eth0.nmconnection");
+        fs::create_dir_all(dir)?;
+        fs::write(&path, "[connection]\nid=eth0\n")?;
+
+        let mut transaction = Transaction::default();
+        transaction.record(&path)?;
+        fs::write(&path, "[connection]\nid=broken\n")?;
+
+        let options = ApplyOptions {
+            health_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let error = verify_health("false", &options, &mut transaction).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Health check 'false' did not pass within 50ms"
+        );
+        // Changes are kept unless requested otherwise.
+        assert_eq!(fs::read_to_string(&path)?, "[connection]\nid=broken\n");
+
+        let options = ApplyOptions {
+            health_rollback: true,
+            ..options
+        };
+        assert!(verify_health("false", &options, &mut transaction).is_err());
+        assert_eq!(fs::read_to_string(&path)?, "[connection]\nid=eth0\n");
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_connections_falls_back() {
+        assert!(reload_connections(&[&["false"], &["true"]]).is_ok());
+
+        let error = reload_connections(&[&["false"], &["<missing>"]]).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Reloading NetworkManager connections failed: false: exit status: 1"));
+    }
 
-        let destination_path = Path::new(destination_dir);
-        for entry in fs::read_dir(source_dir)? {
-            let entry = entry?;
-            let filename = entry.file_name().into_string().unwrap();
+    #[test]
+    fn store_backup_archive_successfully() -> Result<(), anyhow::Error> {
+        let base_dir = Path::new("_backup");
+        let connections_dir = base_dir.join("system-connections");
+        let hostname_file = base_dir.join("hostname");
+        let archive_path = base_dir.join("backup.tar.gz");
+        fs::create_dir_all(&connections_dir)?;
+        fs::write(
+            connections_dir.join("eth0.nmconnection"),
+            "[connection]\nid=eth0\n",
+        )?;
+        fs::write(&hostname_file, "node1\n")?;
 
-            let input = fs::read_to_string(entry.path())?;
-            let output = fs::read_to_string(destination_path.join(&filename))?;
+        let options = ApplyOptions {
+            nm_connections_dir: connections_dir.to_str().unwrap().to_string(),
+            nm_config_dir: base_dir.join("<missing>").to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        store_backup_archive(&archive_path, &options, hostname_file.to_str().unwrap())?;
 
-            assert_eq!(input, output);
+        let mut entries = HashMap::new();
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(&archive_path)?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let mut contents = String::new();
+            io::Read::read_to_string(&mut entry, &mut contents)?;
+            entries.insert(entry.path()?.to_path_buf(), contents);
         }
 
+        assert_eq!(entries[&hostname_file], "node1\n");
+        assert_eq!(
+            entries[&connections_dir.join("eth0.nmconnection")],
+            "[connection]\nid=eth0\n"
+        );
+
         // cleanup
-        fs::remove_dir_all(destination_dir)
+        fs::remove_dir_all(base_dir)?;
+
+        Ok(())
     }
 
     #[test]
@@ -567,54 +4165,114 @@ mod tests {
         let destination_dir = "_out";
         let host = Host {
             hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
             interfaces: vec![
                 Interface {
                     logical_name: "eth0".to_string(),
                     mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth0.1365".to_string(),
                     mac_address: None,
-                    interface_type: "vlan".to_string(),
+                    interface_type: InterfaceType::Vlan,
                     connection_ids: vec!["eth0.1365".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "br1".to_string(),
                     mac_address: None,
-                    interface_type: "ovs-bridge".to_string(),
+                    interface_type: InterfaceType::OvsBridge,
                     connection_ids: vec!["br1-br".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth2".to_string(),
                     mac_address: Option::from("00:11:22:33:44:56".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth2".to_string(), "eth2-port".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "eth1".to_string(),
                     mac_address: Option::from("00:11:22:33:44:57".to_string()),
-                    interface_type: "ethernet".to_string(),
+                    interface_type: InterfaceType::Ethernet,
                     connection_ids: vec!["eth1".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
                 Interface {
                     logical_name: "bond0".to_string(),
                     mac_address: Option::from("00:11:22:33:44:58".to_string()),
-                    interface_type: "bond".to_string(),
+                    interface_type: InterfaceType::Bond,
                     connection_ids: vec!["bond0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
                 },
             ],
         };
         let detected_interfaces = HashMap::from([("eth2".to_string(), "eth4".to_string())]);
 
-        assert!(copy_connection_files(
-            host,
+        let (reports, counts, renames) = copy_connection_files(
+            host.clone(),
             detected_interfaces.clone(),
             source_dir,
-            destination_dir
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
         )
-        .is_ok());
+        .unwrap();
+        assert_eq!(
+            counts,
+            StoreCounts {
+                written: 7,
+                unchanged: 0
+            }
+        );
+        assert_eq!(rename_summary(&renames), "Renamed 1 interface: eth2->eth4");
+        assert_eq!(
+            reports.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "eth0 -> eth0 (from node1/eth0.nmconnection)",
+                "eth0.1365 -> eth0.1365 (from node1/eth0.1365.nmconnection)",
+                "br1 -> br1 (from node1/br1-br.nmconnection)",
+                "eth2 -> eth4 (from node1/eth2.nmconnection, node1/eth2-port.nmconnection)",
+                "eth1 -> eth1 (from node1/eth1.nmconnection)",
+                "bond0 -> bond0 (from node1/bond0.nmconnection)",
+            ]
+        );
+        assert_eq!(
+            reports[3],
+            InterfaceReport {
+                logical_name: "eth2".to_string(),
+                local_name: "eth4".to_string(),
+                source_keyfiles: vec![
+                    "node1/eth2.nmconnection".to_string(),
+                    "node1/eth2-port.nmconnection".to_string()
+                ],
+                destination_keyfiles: vec![
+                    PathBuf::from("_out/eth4.nmconnection"),
+                    PathBuf::from("_out/eth4-port.nmconnection")
+                ],
+                renamed: true,
+            }
+        );
+        assert!(!reports[0].renamed);
 
         let source_path = Path::new(source_dir).join("node1");
         let destination_path = Path::new(destination_dir);
@@ -641,6 +4299,397 @@ mod tests {
             assert_eq!(input, output);
         }
 
+        // Re-applying the same config leaves the identical files untouched.
+        let (_, counts, _) = copy_connection_files(
+            host,
+            detected_interfaces,
+            source_dir,
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            counts,
+            StoreCounts {
+                written: 0,
+                unchanged: 7
+            }
+        );
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)
+    }
+
+    #[test]
+    fn rename_interface_references_precisely() {
+        let local_interfaces = HashMap::from([
+            ("eth0".to_string(), "ens1f0".to_string()),
+            ("eth0.1365".to_string(), "ens1f0.1365".to_string()),
+        ]);
+
+        let contents = "[connection]\nid=eth0\nuuid=eth0c6e2-96c6-594b-adcb-6f949ef3eth0\n\
+                        interface-name=eth0\ntype=ethernet\n\n[ipv4]\ndns=1.1.1.1;\nmethod=auto\n";
+        assert_eq!(
+            rename_interface_references(contents, "eth0", "ens1f0", &local_interfaces).unwrap(),
+            "[connection]\nid=ens1f0\nuuid=eth0c6e2-96c6-594b-adcb-6f949ef3eth0\n\
+             interface-name=ens1f0\ntype=ethernet\n\n[ipv4]\ndns=1.1.1.1;\nmethod=auto\n"
+        );
+
+        let contents = "[connection]\nid=vlan-eth0.1365\ninterface-name=eth0.1365\ntype=vlan\n\n\
+                        [vlan]\nid=1365\nparent=eth0\n";
+        assert_eq!(
+            rename_interface_references(contents, "eth0.1365", "ens1f0.1365", &local_interfaces)
+                .unwrap(),
+            "[connection]\nid=vlan-ens1f0.1365\ninterface-name=ens1f0.1365\ntype=vlan\n\n\
+             [vlan]\nid=1365\nparent=ens1f0\n"
+        );
+    }
+
+    #[test]
+    fn rename_interface_references_keeps_comments_and_order() {
+        let local_interfaces = HashMap::from([("eth0".to_string(), "ens1f0".to_string())]);
+
+        let contents = "# managed by nmc\n[connection]\nid=eth0\ntype=ethernet\n\
+                        # pinned to the uplink port\ninterface-name=eth0\nautoconnect=true\n\n\
+                        [ipv4]\n#method=manual\nmethod=auto\n";
+        assert_eq!(
+            rename_interface_references(contents, "eth0", "ens1f0", &local_interfaces).unwrap(),
+            "# managed by nmc\n[connection]\nid=ens1f0\ntype=ethernet\n\
+             # pinned to the uplink port\ninterface-name=ens1f0\nautoconnect=true\n\n\
+             [ipv4]\n#method=manual\nmethod=auto\n"
+        );
+    }
+
+    #[test]
+    fn explain_renames_lists_matched_lines() -> Result<(), anyhow::Error> {
+        let dir = "_explain-renames-config";
+        fs::create_dir_all(dir)?;
+        fs::write(
+            Path::new(dir).join("eth0.nmconnection"),
+            "[connection]\nid=eth0\nuuid=eth0c6e2-96c6-594b-adcb-6f949ef3eth0\n\
+             interface-name=eth0\ntype=ethernet\n",
+        )?;
+        fs::write(
+            Path::new(dir).join("eth0.1365.nmconnection"),
+            "[connection]\nid=eth0.1365\ninterface-name=eth0.1365\ntype=vlan\n\n\
+             [vlan]\nid=1365\nparent=eth0\n",
+        )?;
+        let interface = |name: &str, interface_type: InterfaceType| Interface {
+            logical_name: name.to_string(),
+            mac_address: None,
+            interface_type,
+            connection_ids: vec![name.to_string()],
+            exclude_from_match: false,
+            unmanaged: false,
+            member_mac_addresses: vec![],
+        };
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                interface("eth0", InterfaceType::Ethernet),
+                interface("eth0.1365", InterfaceType::Vlan),
+            ],
+        };
+        let local_interfaces = HashMap::from([
+            ("eth0".to_string(), "ens1f0".to_string()),
+            ("eth0.1365".to_string(), "ens1f0.1365".to_string()),
+        ]);
+
+        let explanation = explain_renames(&host, Path::new(dir), &local_interfaces)?;
+        assert_eq!(
+            explanation,
+            vec![
+                "Rename eth0 -> ens1f0:",
+                "  eth0.nmconnection:2: id=eth0 (rewritten)",
+                "  eth0.nmconnection:3: uuid=eth0c6e2-96c6-594b-adcb-6f949ef3eth0 (kept, substring match)",
+                "  eth0.nmconnection:4: interface-name=eth0 (rewritten)",
+                "  eth0.1365.nmconnection:2: id=eth0.1365 (rewritten)",
+                "  eth0.1365.nmconnection:3: interface-name=eth0.1365 (rewritten)",
+                "  eth0.1365.nmconnection:8: parent=eth0 (rewritten)",
+                "Rename eth0.1365 -> ens1f0.1365:",
+                "  eth0.1365.nmconnection:2: id=eth0.1365 (rewritten)",
+                "  eth0.1365.nmconnection:3: interface-name=eth0.1365 (rewritten)",
+            ]
+        );
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn assignment_summary_of_sample_host() -> Result<(), anyhow::Error> {
+        let destination_dir = "_summary-out";
+        let host = parse_hosts("testdata/apply/config")?
+            .into_iter()
+            .find(|host| host.hostname == "node1")
+            .unwrap();
+        let local_interfaces = HashMap::from([("eth2".to_string(), "ens1f0".to_string())]);
+
+        let (reports, _, _) = copy_connection_files(
+            host,
+            local_interfaces,
+            "testdata/apply",
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
+        )?;
+        assert_eq!(
+            assignment_summary(&reports),
+            vec![
+                "Interface assignment summary:",
+                "  eth0 -> eth0 (from node1/eth0.nmconnection)",
+                "  eth1 -> eth1 (from node1/eth1.nmconnection)",
+                "  eth2 -> ens1f0 (from node1/eth2.nmconnection)",
+                "  bond0 -> bond0 (from node1/bond0.nmconnection)",
+            ]
+        );
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_summary_of_interfaces() {
+        assert_eq!(
+            rename_summary(&HashMap::new()),
+            "No interface renames required"
+        );
+        assert_eq!(
+            rename_summary(&HashMap::from([
+                ("eth2.bridge".to_string(), "ens1f0.bridge".to_string()),
+                ("eth2".to_string(), "ens1f0".to_string()),
+            ])),
+            "Renamed 2 interfaces: eth2->ens1f0, eth2.bridge->ens1f0.bridge"
+        );
+    }
+
+    #[test]
+    fn copy_connection_files_only_requested_interfaces() -> Result<(), anyhow::Error> {
+        let source_dir = "_only-config";
+        let destination_dir = "_only-out";
+        let host_dir = Path::new(source_dir).join("node1");
+        fs::create_dir_all(&host_dir)?;
+        fs::create_dir_all(destination_dir)?;
+        for (name, interface_type) in [
+            ("eth0", "ethernet"),
+            ("eth1", "ethernet"),
+            ("bond0", "bond"),
+        ] {
+            fs::write(
+                host_dir.join(format!("{name}.nmconnection")),
+                format!("[connection]\nid={name}\ninterface-name={name}\ntype={interface_type}\n"),
+            )?;
+        }
+        let existing = Path::new(destination_dir).join("eth1.nmconnection");
+        fs::write(&existing, "[connection]\nid=eth1\n")?;
+
+        let interface = |name: &str, interface_type: InterfaceType| Interface {
+            logical_name: name.to_string(),
+            mac_address: None,
+            interface_type,
+            connection_ids: vec![name.to_string()],
+            exclude_from_match: false,
+            unmanaged: false,
+            member_mac_addresses: vec![],
+        };
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                interface("eth0", InterfaceType::Ethernet),
+                interface("eth1", InterfaceType::Ethernet),
+                interface("bond0", InterfaceType::Bond),
+            ],
+        };
+        let local_interfaces = HashMap::from([("eth0".to_string(), "ens1f0".to_string())]);
+
+        // Interfaces are requested by their detected local or preconfigured name.
+        let options = ApplyOptions {
+            only: vec!["ens1f0".to_string(), "bond0".to_string()],
+            ..Default::default()
+        };
+        let (reports, _, _) = copy_connection_files(
+            host.clone(),
+            local_interfaces.clone(),
+            source_dir,
+            destination_dir,
+            &options,
+            &mut Transaction::default(),
+        )?;
+        let copied: Vec<&str> = reports.iter().map(|r| r.logical_name.as_str()).collect();
+        assert_eq!(copied, vec!["eth0", "bond0"]);
+        assert!(Path::new(destination_dir)
+            .join("ens1f0.nmconnection")
+            .exists());
+        assert!(Path::new(destination_dir)
+            .join("bond0.nmconnection")
+            .exists());
+        assert_eq!(fs::read_to_string(&existing)?, "[connection]\nid=eth1\n");
+
+        let options = ApplyOptions {
+            only: vec!["bond0".to_string(), "eth9".to_string()],
+            ..Default::default()
+        };
+        let error = copy_connection_files(
+            host,
+            local_interfaces,
+            source_dir,
+            destination_dir,
+            &options,
+            &mut Transaction::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Requested interfaces not found on host node1: eth9"
+        );
+
+        // cleanup
+        fs::remove_dir_all(source_dir)?;
+        fs::remove_dir_all(destination_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn detect_keyfile_type_mismatch() {
+        let keyfile = |keyfile_type: &str| format!("[connection]\nid=eth0\ntype={keyfile_type}\n");
+
+        assert_eq!(
+            keyfile_type_mismatch(&"ethernet".into(), &keyfile("bond")).unwrap(),
+            Some("bond".to_string())
+        );
+        assert_eq!(
+            keyfile_type_mismatch(&"ethernet".into(), &keyfile("ethernet")).unwrap(),
+            None
+        );
+        assert_eq!(
+            keyfile_type_mismatch(&"linux-bridge".into(), &keyfile("bridge")).unwrap(),
+            None
+        );
+        assert_eq!(
+            keyfile_type_mismatch(&"ethernet".into(), &keyfile("ovs-port")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn copy_connection_files_dry_run() {
+        let destination_dir = "_dry-run-out";
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![Interface {
+                logical_name: "eth2".to_string(),
+                mac_address: Option::from("00:11:22:33:44:56".to_string()),
+                interface_type: InterfaceType::Ethernet,
+                connection_ids: vec!["eth2".to_string(), "eth2-port".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            }],
+        };
+        let detected_interfaces = HashMap::from([("eth2".to_string(), "eth4".to_string())]);
+
+        assert!(copy_connection_files(
+            host,
+            detected_interfaces,
+            "testdata/apply",
+            destination_dir,
+            &ApplyOptions {
+                dry_run: true,
+                ..Default::default()
+            },
+            &mut Transaction::default()
+        )
+        .is_ok());
+
+        assert!(!Path::new(destination_dir).exists());
+    }
+
+    #[test]
+    fn copy_connection_files_rolls_back_on_failure() -> io::Result<()> {
+        let destination_dir = "_rollback-out";
+        let existing_path = Path::new(destination_dir).join("eth1.nmconnection");
+        let host = Host {
+            hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces: vec![
+                Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth1".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:57".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth1".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth3".to_string(),
+                    mac_address: Option::from("00:11:22:33:44:59".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["missing".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+            ],
+        };
+
+        fs::create_dir_all(destination_dir)?;
+        fs::write(&existing_path, "previous")?;
+
+        let mut transaction = Transaction::default();
+        assert!(copy_connection_files(
+            host,
+            HashMap::new(),
+            "testdata/apply",
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut transaction
+        )
+        .is_err());
+
+        // eth0 and eth1 were written before failing on the missing keyfile
+        assert!(Path::new(destination_dir)
+            .join("eth0.nmconnection")
+            .exists());
+        assert_ne!(fs::read_to_string(&existing_path)?, "previous");
+
+        transaction.rollback();
+
+        assert!(!Path::new(destination_dir)
+            .join("eth0.nmconnection")
+            .exists());
+        assert_eq!(fs::read_to_string(&existing_path)?, "previous");
+
         // cleanup
         fs::remove_dir_all(destination_dir)
     }
@@ -652,19 +4701,259 @@ mod tests {
 
         let host = Host {
             hostname: "node1".to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
             interfaces: vec![Interface {
                 logical_name: "eth0".to_string(),
                 mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: Vec::new(),
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             }],
         };
 
-        assert!(
-            copy_connection_files(host, HashMap::new(), source_dir, destination_dir)
-                .is_err_and(|e| e.to_string().contains("Missing connection ids"))
+        assert!(copy_connection_files(
+            host,
+            HashMap::new(),
+            source_dir,
+            destination_dir,
+            &ApplyOptions::default(),
+            &mut Transaction::default()
+        )
+        .is_err_and(|e| e.to_string().contains("Missing connection ids")));
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)
+    }
+
+    #[test]
+    fn copy_referenced_files_to_certs_dir() -> Result<(), anyhow::Error> {
+        let host_config_dir = Path::new("_referenced-config/node1");
+        let certs_dir = "_referenced-certs";
+        fs::create_dir_all(host_config_dir)?;
+        fs::write(
+            host_config_dir.join("ca.pem"),
+            "-----BEGIN CERTIFICATE-----\n",
+        )?;
+
+        let contents = "[connection]\nid=eth0\n\n[802-1x]\neap=tls;\nca-cert=ca.pem\n\
+                        private-key=/etc/pki/client.key\n";
+        let options = ApplyOptions {
+            nm_certs_dir: certs_dir.to_string(),
+            ..Default::default()
+        };
+
+        let mut transaction = Transaction::default();
+        let updated = copy_referenced_files(contents, host_config_dir, &options, &mut transaction)?;
+        assert_eq!(
+            updated,
+            "[connection]\nid=eth0\n\n[802-1x]\neap=tls;\nca-cert=_referenced-certs/ca.pem\n\
+             private-key=/etc/pki/client.key\n"
+        );
+
+        let destination = Path::new(certs_dir).join("ca.pem");
+        assert_eq!(
+            fs::read_to_string(&destination)?,
+            "-----BEGIN CERTIFICATE-----\n"
+        );
+        assert_eq!(
+            fs::metadata(&destination)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        transaction.rollback();
+        assert!(!destination.exists());
+
+        let escaping = "[802-1x]\nca-cert=../node2/ca.pem\n";
+        assert!(copy_referenced_files(
+            escaping,
+            host_config_dir,
+            &options,
+            &mut Transaction::default()
+        )
+        .is_err());
+
+        // cleanup
+        fs::remove_dir_all("_referenced-config")?;
+        fs::remove_dir_all(certs_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_referenced_vpn_secret_to_certs_dir() -> Result<(), anyhow::Error> {
+        let host_config_dir = Path::new("_referenced-vpn-config/node1");
+        let certs_dir = "_referenced-vpn-certs";
+        fs::create_dir_all(host_config_dir)?;
+        fs::write(host_config_dir.join("static.key"), "static key\n")?;
+
+        let contents = "[connection]\nid=vpn0\n\n[vpn]\nsecret=file://static.key\n";
+        let options = ApplyOptions {
+            nm_certs_dir: certs_dir.to_string(),
+            ..Default::default()
+        };
+
+        let updated = copy_referenced_files(
+            contents,
+            host_config_dir,
+            &options,
+            &mut Transaction::default(),
+        )?;
+        assert_eq!(
+            updated,
+            "[connection]\nid=vpn0\n\n[vpn]\nsecret=_referenced-vpn-certs/static.key\n"
+        );
+
+        let destination = Path::new(certs_dir).join("static.key");
+        assert_eq!(fs::read_to_string(&destination)?, "static key\n");
+        assert_eq!(
+            fs::metadata(&destination)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        // cleanup
+        fs::remove_dir_all("_referenced-vpn-config")?;
+        fs::remove_dir_all(certs_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn embed_referenced_wireguard_private_key() -> Result<(), anyhow::Error> {
+        let host_config_dir = Path::new("_referenced-wireguard-config/node1");
+        let certs_dir = "_referenced-wireguard-certs";
+        let private_key = "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=";
+        fs::create_dir_all(host_config_dir)?;
+        fs::write(host_config_dir.join("wg0.key"), format!("{private_key}\n"))?;
+
+        let options = ApplyOptions {
+            nm_certs_dir: certs_dir.to_string(),
+            ..Default::default()
+        };
+
+        let contents = "[connection]\nid=wg0\n\n[wireguard]\nprivate-key=file://wg0.key\n";
+        let updated = copy_referenced_files(
+            contents,
+            host_config_dir,
+            &options,
+            &mut Transaction::default(),
+        )?;
+        assert_eq!(
+            updated,
+            format!("[connection]\nid=wg0\n\n[wireguard]\nprivate-key={private_key}\n")
+        );
+        // NetworkManager cannot read the key from a file, so it is not copied.
+        assert!(!Path::new(certs_dir).exists());
+
+        // Inline keys are kept as-is.
+        assert_eq!(
+            copy_referenced_files(
+                &updated,
+                host_config_dir,
+                &options,
+                &mut Transaction::default()
+            )?,
+            updated
+        );
+
+        let missing = "[wireguard]\nprivate-key=file://wg1.key\n";
+        assert!(copy_referenced_files(
+            missing,
+            host_config_dir,
+            &options,
+            &mut Transaction::default()
+        )
+        .is_err());
+
+        // cleanup
+        fs::remove_dir_all("_referenced-wireguard-config")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_connection_file_sets_owner() -> io::Result<()> {
+        let destination_dir = "_owner-out";
+        let path = Path::new(destination_dir).join("eth0.nmconnection");
+
+        // Only root is able to hand over files to other users.
+        let is_root = fs::metadata("/proc/self")?.uid() == 0;
+        let owner = if is_root { (65534, 65534) } else { (0, 0) };
+        let options = ApplyOptions {
+            keyfile_owner: Some(owner),
+            ..Default::default()
+        };
+
+        fs::create_dir_all(destination_dir)?;
+
+        let result = store_connection_file(
+            "eth0",
+            "[connection]\nid=eth0\n".to_string(),
+            destination_dir,
+            None,
+            &options,
+            &mut Transaction::default(),
         );
 
+        if is_root {
+            assert!(result.is_ok());
+            let metadata = fs::metadata(&path)?;
+            assert_eq!((metadata.uid(), metadata.gid()), owner);
+        } else {
+            assert!(result.is_err_and(|e| e
+                .to_string()
+                .starts_with("Insufficient privileges to change the owner of")));
+        }
+
+        // cleanup
+        fs::remove_dir_all(destination_dir)
+    }
+
+    #[test]
+    fn parse_keyfile_owner_format() {
+        assert_eq!(parse_keyfile_owner("1000:100"), Ok((1000, 100)));
+        assert!(parse_keyfile_owner("1000").is_err());
+        assert!(parse_keyfile_owner("nm:100").is_err());
+        assert!(parse_keyfile_owner("1000:-1").is_err());
+    }
+
+    #[test]
+    fn store_connection_file_keeps_modification_time() -> io::Result<()> {
+        let destination_dir = "_mtime-out";
+        let path = Path::new(destination_dir).join("eth0.nmconnection");
+        let contents = "[connection]\nid=eth0\n".to_string();
+        let source_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        fs::create_dir_all(destination_dir)?;
+
+        // New contents get the modification time of the source keyfile.
+        store_connection_file(
+            "eth0",
+            contents.clone(),
+            destination_dir,
+            Some(source_modified),
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::metadata(&path)?.modified()?, source_modified);
+
+        // Unchanged contents preserve the modification time of the existing file.
+        store_connection_file(
+            "eth0",
+            contents,
+            destination_dir,
+            Some(SystemTime::now()),
+            &ApplyOptions::default(),
+            &mut Transaction::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::metadata(&path)?.modified()?, source_modified);
+
         // cleanup
         fs::remove_dir_all(destination_dir)
     }
@@ -682,4 +4971,16 @@ mod tests {
         assert!(keyfile_path("some-dir", "").is_none());
         assert!(keyfile_path("", "eth0").is_none());
     }
+
+    #[test]
+    fn keyfile_path_rejects_path_traversal() {
+        assert!(keyfile_path("some-dir", "../evil").is_none());
+        assert!(keyfile_path("some-dir", "..").is_none());
+        assert!(keyfile_path("some-dir", "a/b").is_none());
+        assert!(keyfile_path("some-dir", "/etc/passwd").is_none());
+        assert_eq!(
+            keyfile_path("some-dir", "eth0.1365"),
+            Some(PathBuf::from("some-dir/eth0.1365.nmconnection"))
+        );
+    }
 }
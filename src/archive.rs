@@ -0,0 +1,185 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tempfile::TempDir;
+
+/// Mode of extracted keyfiles regardless of the one stored in the archive, as they may contain secrets.
+const KEYFILE_MODE: u32 = 0o600;
+
+/// Whether the config dir refers to a gzipped tarball rather than a directory.
+pub(crate) fn is_config_archive(config_dir: &str) -> bool {
+    (config_dir.ends_with(".tar.gz") || config_dir.ends_with(".tgz"))
+        && Path::new(config_dir).is_file()
+}
+
+/// Extract the gzipped tarball at `path` into a temporary dir
+/// which is removed once the returned handle is dropped.
+pub(crate) fn extract_config_archive(path: &Path) -> anyhow::Result<TempDir> {
+    let file = fs::File::open(path).context(format!("Opening config archive {path:?}"))?;
+
+    extract(file)
+}
+
+/// Extract a gzipped tarball read from `reader` into a temporary dir.
+pub(crate) fn extract(reader: impl Read) -> anyhow::Result<TempDir> {
+    let dir = TempDir::new().context("Creating temporary config dir")?;
+
+    tar::Archive::new(GzDecoder::new(reader))
+        .unpack(dir.path())
+        .context("Extracting config archive")?;
+    restrict_keyfiles(dir.path())?;
+
+    Ok(dir)
+}
+
+/// Store the given files and dirs (skipping missing ones) in a gzipped tarball at `path`.
+///
+/// Entries are named after their source paths without the leading `/`, e.g. `etc/hostname`.
+pub(crate) fn create_archive(path: &Path, sources: &[&Path]) -> anyhow::Result<()> {
+    let file = fs::File::create(path).context(format!("Creating archive {path:?}"))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    for source in sources {
+        let name = source.strip_prefix("/").unwrap_or(source);
+
+        if source.is_dir() {
+            archive
+                .append_dir_all(name, source)
+                .context(format!("Archiving {source:?}"))?;
+        } else if source.exists() {
+            archive
+                .append_path_with_name(source, name)
+                .context(format!("Archiving {source:?}"))?;
+        }
+    }
+
+    archive
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .context(format!("Writing archive {path:?}"))?;
+
+    Ok(())
+}
+
+/// Write the given entries (path, contents and mode) as a gzipped tarball to `writer`.
+pub(crate) fn write_archive(
+    writer: impl Write,
+    entries: &[(String, String, u32)],
+) -> anyhow::Result<()> {
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+
+    for (name, contents, mode) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(*mode);
+        archive
+            .append_data(&mut header, name, contents.as_bytes())
+            .context(format!("Archiving {name}"))?;
+    }
+
+    archive.into_inner().and_then(GzEncoder::finish)?;
+
+    Ok(())
+}
+
+fn restrict_keyfiles(dir: &Path) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            restrict_keyfiles(&path)?;
+        } else if path.extension() == Some(OsStr::new("nmconnection")) {
+            fs::set_permissions(&path, fs::Permissions::from_mode(KEYFILE_MODE))
+                .context(format!("Setting mode of {path:?}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use crate::archive::{extract_config_archive, is_config_archive, write_archive};
+
+    #[test]
+    fn extract_config_archive_successfully() -> Result<(), anyhow::Error> {
+        let path = "_extract-config.tar.gz";
+        let mut contents = Vec::new();
+        write_archive(
+            &mut contents,
+            &[
+                ("host_config.yaml".to_string(), "[]\n".to_string(), 0o644),
+                (
+                    "node1/eth0.nmconnection".to_string(),
+                    "[connection]\nid=eth0\n".to_string(),
+                    0o644,
+                ),
+            ],
+        )?;
+        fs::write(path, contents)?;
+        assert!(is_config_archive(path));
+
+        let dir = extract_config_archive(Path::new(path))?;
+
+        let mapping = dir.path().join("host_config.yaml");
+        assert_eq!(fs::read_to_string(&mapping)?, "[]\n");
+        assert_eq!(fs::metadata(&mapping)?.permissions().mode() & 0o777, 0o644);
+
+        let keyfile = dir.path().join("node1").join("eth0.nmconnection");
+        assert_eq!(fs::metadata(&keyfile)?.permissions().mode() & 0o777, 0o600);
+
+        // cleanup
+        let dir_path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!dir_path.exists());
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_from_config_archive() -> Result<(), anyhow::Error> {
+        let path = "_generate-config.tgz";
+        let out_dir = "_archive-out";
+        let mut contents = Vec::new();
+        write_archive(
+            &mut contents,
+            &[(
+                "node1.yaml".to_string(),
+                fs::read_to_string("testdata/generate/config/node1.yaml")?,
+                0o644,
+            )],
+        )?;
+        fs::write(path, contents)?;
+
+        crate::generate(path, out_dir)?;
+        assert!(Path::new(out_dir)
+            .join("node1")
+            .join("eth0.nmconnection")
+            .exists());
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn detect_config_archive() {
+        assert!(!is_config_archive("testdata/generate/config"));
+        assert!(!is_config_archive("<missing>.tar.gz"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use log::{debug, info, warn};
+
+use crate::apply_conf::{
+    CONFIG_DIR, DISPATCHER_DIR, HOSTNAME_FILE, LINK_FILE_PREFIX, STATIC_SYSTEM_CONNECTIONS_DIR,
+    SYSTEMD_NETWORK_DIR,
+};
+use crate::error::ConfigError;
+use crate::state::STATE_FILE;
+use crate::types::ApplyReport;
+use crate::HOSTNAME_DISPATCHER_FILE;
+
+/// Optional behaviour of the `clean` command.
+#[non_exhaustive]
+pub struct CleanOptions {
+    /// Report stored by `apply` listing the connection files to remove.
+    pub report: Option<PathBuf>,
+    /// Remove all connection files within the connections dir instead of the ones listed in the report.
+    pub all: bool,
+    /// Remove the hostname file as well.
+    pub reset_hostname: bool,
+    /// Remove the files instead of only logging them.
+    pub confirm: bool,
+    /// Directory of the connection files.
+    pub nm_connections_dir: String,
+    /// NetworkManager configuration directory storing the disabled default wired connections.
+    pub nm_config_dir: String,
+    /// File recording the last applied config.
+    pub state_file: String,
+}
+
+impl Default for CleanOptions {
+    fn default() -> Self {
+        Self {
+            report: None,
+            all: false,
+            reset_hostname: false,
+            confirm: false,
+            nm_connections_dir: STATIC_SYSTEM_CONNECTIONS_DIR.to_owned(),
+            nm_config_dir: CONFIG_DIR.to_owned(),
+            state_file: STATE_FILE.to_owned(),
+        }
+    }
+}
+
+/// Remove the files written by `apply`, only listing them unless confirmed.
+///
+/// Files which do not exist (e.g. removed by a previous run) are skipped.
+pub(crate) fn clean(options: &CleanOptions) -> Result<(), ConfigError> {
+    let mut paths = connection_files(options)?;
+    paths.extend(link_files(SYSTEMD_NETWORK_DIR).context("Listing link files")?);
+    paths.push(Path::new(&options.nm_config_dir).join("no-auto-default.conf"));
+    paths.push(Path::new(DISPATCHER_DIR).join(HOSTNAME_DISPATCHER_FILE));
+    paths.push(PathBuf::from(&options.state_file));
+    if options.reset_hostname {
+        paths.push(PathBuf::from(HOSTNAME_FILE));
+    }
+
+    remove_files(&paths, options.confirm)?;
+
+    if !options.confirm {
+        warn!("Nothing was removed, pass --confirm to remove the listed files");
+    }
+
+    Ok(())
+}
+
+/// Determine the connection files to remove from the report or the connections dir.
+fn connection_files(options: &CleanOptions) -> Result<Vec<PathBuf>, anyhow::Error> {
+    if options.all {
+        let entries = match fs::read_dir(&options.nm_connections_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Reading connections dir"),
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.context("Reading connections dir")?.path();
+            if path.extension() == Some(OsStr::new("nmconnection")) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        return Ok(paths);
+    }
+
+    let report = options
+        .report
+        .as_ref()
+        .ok_or_else(|| anyhow!("Either a report or removing all connection files is required"))?;
+    let contents = fs::read_to_string(report).context(format!("Reading report {report:?}"))?;
+    let report: ApplyReport = serde_json::from_str(&contents).context("Parsing report")?;
+
+    Ok(report
+        .interfaces
+        .into_iter()
+        .flat_map(|interface| interface.destination_keyfiles)
+        .collect())
+}
+
+/// List the `.link` files pinning the interface names within the given dir.
+fn link_files(dir: &str) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_link_file = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.starts_with(LINK_FILE_PREFIX) && name.ends_with(".link"));
+        if is_link_file {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn remove_files(paths: &[PathBuf], confirm: bool) -> Result<(), anyhow::Error> {
+    for path in paths {
+        if fs::symlink_metadata(path).is_err() {
+            debug!("Already removed: {path:?}");
+            continue;
+        }
+
+        if !confirm {
+            info!("Would remove: {path:?}");
+            continue;
+        }
+
+        fs::remove_file(path).context(format!("Removing {path:?}"))?;
+        info!("Removed: {path:?}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::clean_conf::{connection_files, link_files, remove_files, CleanOptions};
+
+    #[test]
+    fn connection_files_from_report_or_dir() -> Result<(), anyhow::Error> {
+        let dir = Path::new("_clean-connections");
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("eth0.nmconnection"), "")?;
+        fs::write(dir.join("eth1.nmconnection"), "")?;
+        fs::write(dir.join("README"), "")?;
+
+        let report = dir.join("report.json");
+        fs::write(
+            &report,
+            r#"{"hostname":"node1","interfaces":[{"logical_name":"eth0","local_name":"ens1f0",
+            "source_keyfiles":["node1/eth0.nmconnection"],
+            "destination_keyfiles":["_clean-connections/ens1f0.nmconnection"],"renamed":true}]}"#,
+        )?;
+
+        let options = CleanOptions {
+            report: Some(report),
+            nm_connections_dir: dir.to_str().unwrap().to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_files(&options)?,
+            vec![PathBuf::from("_clean-connections/ens1f0.nmconnection")]
+        );
+
+        let options = CleanOptions {
+            all: true,
+            ..options
+        };
+        assert_eq!(
+            connection_files(&options)?,
+            vec![dir.join("eth0.nmconnection"), dir.join("eth1.nmconnection")]
+        );
+
+        assert!(connection_files(&CleanOptions::default()).is_err());
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_files_only_when_confirmed() -> Result<(), anyhow::Error> {
+        let dir = Path::new("_clean-remove");
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("10-nmc-eth0.link"), "")?;
+        fs::write(dir.join("99-default.link"), "")?;
+        let paths = link_files(dir.to_str().unwrap())?;
+        assert_eq!(paths, vec![dir.join("10-nmc-eth0.link")]);
+
+        remove_files(&paths, false)?;
+        assert!(dir.join("10-nmc-eth0.link").exists());
+
+        remove_files(&paths, true)?;
+        assert!(!dir.join("10-nmc-eth0.link").exists());
+        assert!(dir.join("99-default.link").exists());
+
+        // Running again is a no-op.
+        remove_files(&paths, true)?;
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+use nmstate::{InterfaceState, InterfaceType, NetworkState};
+use serde_yaml::{Mapping, Value};
+
+/// File storing the cloud-init network configuration of a host.
+pub(crate) const CLOUD_INIT_FILE: &str = "network-config.yaml";
+
+/// Translate the nmstate desired state into the cloud-init network-config version 2 format.
+///
+/// Ethernet, VLAN and bond interfaces are supported while any other type is rejected.
+/// Loopback and absent interfaces are skipped.
+pub(crate) fn network_config(network_state: &NetworkState) -> anyhow::Result<String> {
+    let mut interfaces: Vec<&nmstate::Interface> = network_state
+        .interfaces
+        .iter()
+        .filter(|i| i.iface_type() != InterfaceType::Loopback)
+        .filter(|i| i.base_iface().state != InterfaceState::Absent)
+        .collect();
+    interfaces.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut ethernets = Mapping::new();
+    let mut bonds = Mapping::new();
+    let mut vlans = Mapping::new();
+
+    for interface in interfaces {
+        let mut settings = Mapping::new();
+
+        match interface {
+            nmstate::Interface::Ethernet(_) => {
+                if let Some(mac) = &interface.base_iface().mac_address {
+                    settings.insert(
+                        "match".into(),
+                        Value::Mapping(Mapping::from_iter([(
+                            "macaddress".into(),
+                            mac.to_lowercase().into(),
+                        )])),
+                    );
+                    settings.insert("set-name".into(), interface.name().into());
+                }
+            }
+            nmstate::Interface::Bond(bond) => {
+                let ports: Vec<Value> = interface
+                    .ports()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Value::from)
+                    .collect();
+                settings.insert("interfaces".into(), ports.into());
+
+                if let Some(mode) = bond.bond.as_ref().and_then(|b| b.mode.as_ref()) {
+                    settings.insert(
+                        "parameters".into(),
+                        Value::Mapping(Mapping::from_iter([(
+                            "mode".into(),
+                            mode.to_string().into(),
+                        )])),
+                    );
+                }
+            }
+            nmstate::Interface::Vlan(vlan) => {
+                let config = vlan
+                    .vlan
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("VLAN interface {} has no config", interface.name()))?;
+                let link = config.base_iface.as_ref().ok_or_else(|| {
+                    anyhow!("VLAN interface {} has no base interface", interface.name())
+                })?;
+
+                settings.insert("id".into(), config.id.into());
+                settings.insert("link".into(), link.as_str().into());
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Interface {} of type '{}' is not supported by the cloud-init backend",
+                    interface.name(),
+                    interface.iface_type()
+                ))
+            }
+        }
+
+        insert_ip_settings(&mut settings, interface, network_state);
+
+        let section = match interface.iface_type() {
+            InterfaceType::Bond => &mut bonds,
+            InterfaceType::Vlan => &mut vlans,
+            _ => &mut ethernets,
+        };
+        section.insert(interface.name().into(), Value::Mapping(settings));
+    }
+
+    let mut network = Mapping::new();
+    network.insert("version".into(), 2.into());
+    for (name, section) in [("ethernets", ethernets), ("bonds", bonds), ("vlans", vlans)] {
+        if !section.is_empty() {
+            network.insert(name.into(), Value::Mapping(section));
+        }
+    }
+
+    let config = Mapping::from_iter([("network".into(), Value::Mapping(network))]);
+
+    Ok(quote_mac_addresses(&serde_yaml::to_string(&config)?))
+}
+
+/// Quote the plain MAC address scalars as YAML 1.1 parsers (e.g. PyYAML used by cloud-init)
+/// may read some of them as sexagesimal numbers, e.g. `10:11:22:33:44:55`.
+fn quote_mac_addresses(config: &str) -> String {
+    config
+        .lines()
+        .map(|line| match line.split_once("macaddress: ") {
+            Some((indent, mac)) if !mac.starts_with(['\'', '"']) => {
+                format!("{indent}macaddress: \"{mac}\"\n")
+            }
+            _ => format!("{line}\n"),
+        })
+        .collect()
+}
+
+/// Add the addresses, DHCP, MTU, routes and DNS settings of the interface.
+///
+/// Link-local IPv6 addresses are skipped as they are assigned automatically.
+/// The global DNS settings are attached to every interface with static addresses.
+fn insert_ip_settings(
+    settings: &mut Mapping,
+    interface: &nmstate::Interface,
+    network_state: &NetworkState,
+) {
+    let base = interface.base_iface();
+
+    if let Some(mtu) = base.mtu {
+        settings.insert("mtu".into(), mtu.into());
+    }
+
+    let ipv4 = base.ipv4.as_ref().filter(|ip| ip.enabled);
+    let ipv6 = base.ipv6.as_ref().filter(|ip| ip.enabled);
+
+    if ipv4.is_some_and(|ip| ip.dhcp == Some(true)) {
+        settings.insert("dhcp4".into(), true.into());
+    }
+    if ipv6.is_some_and(|ip| ip.dhcp == Some(true)) {
+        settings.insert("dhcp6".into(), true.into());
+    }
+
+    let addresses: Vec<Value> = ipv4
+        .and_then(|ip| ip.addresses.as_ref())
+        .into_iter()
+        .chain(ipv6.and_then(|ip| ip.addresses.as_ref()))
+        .flatten()
+        .filter(|address| !is_ipv6_link_local(&address.ip))
+        .map(|address| format!("{}/{}", address.ip, address.prefix_length).into())
+        .collect();
+    let has_addresses = !addresses.is_empty();
+    if has_addresses {
+        settings.insert("addresses".into(), addresses.into());
+    }
+
+    let routes: Vec<Value> = network_state
+        .routes
+        .config
+        .iter()
+        .flatten()
+        .filter(|route| route.next_hop_iface.as_deref() == Some(interface.name()))
+        .filter_map(|route| {
+            let mut entry = Mapping::new();
+            entry.insert("to".into(), route.destination.as_deref()?.into());
+            if let Some(via) = &route.next_hop_addr {
+                entry.insert("via".into(), via.as_str().into());
+            }
+            if let Some(metric) = route.metric {
+                entry.insert("metric".into(), metric.into());
+            }
+            Some(Value::Mapping(entry))
+        })
+        .collect();
+    if !routes.is_empty() {
+        settings.insert("routes".into(), routes.into());
+    }
+
+    let dns = network_state
+        .dns
+        .as_ref()
+        .and_then(|dns| dns.config.as_ref());
+    if let (true, Some(dns)) = (has_addresses, dns) {
+        let mut nameservers = Mapping::new();
+        for (key, values) in [("addresses", &dns.server), ("search", &dns.search)] {
+            if let Some(values) = values.as_ref().filter(|values| !values.is_empty()) {
+                let values: Vec<Value> = values.iter().map(|v| v.as_str().into()).collect();
+                nameservers.insert(key.into(), values.into());
+            }
+        }
+        if !nameservers.is_empty() {
+            settings.insert("nameservers".into(), Value::Mapping(nameservers));
+        }
+    }
+}
+
+fn is_ipv6_link_local(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use nmstate::NetworkState;
+
+    use crate::cloud_init::network_config;
+
+    #[test]
+    fn network_config_matches_golden_file() -> Result<(), anyhow::Error> {
+        let data = fs::read_to_string("testdata/cloud-init/config/node1.yaml")?;
+        let network_state = NetworkState::new_from_yaml(&data)?;
+
+        assert_eq!(
+            network_config(&network_state)?,
+            fs::read_to_string("testdata/cloud-init/expected/node1/network-config.yaml")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn network_config_rejects_unsupported_types() {
+        let data = r#"---
+        interfaces:
+          - name: br0
+            type: linux-bridge
+            state: up
+        "#;
+        let network_state = NetworkState::new_from_yaml(data).unwrap();
+
+        assert_eq!(
+            network_config(&network_state).unwrap_err().to_string(),
+            "Interface br0 of type 'linux-bridge' is not supported by the cloud-init backend"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use thiserror::Error;
+
+/// Failures of the `generate`, `validate` and `apply` entry points callers may want to branch on.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Empty config directory")]
+    EmptyConfigDir,
+    #[error("None of the preconfigured hosts match local NICs")]
+    NoMatchingHost,
+    /// Distinct hosts match the local NICs equally well.
+    #[error(
+        "Ambiguous host match: {} {} match local NICs",
+        .0.join(", "),
+        if .0.len() == 2 { "both" } else { "all" }
+    )]
+    AmbiguousHost(Vec<String>),
+    #[error("No Ethernet interfaces were provided")]
+    MissingEthernet,
+    #[error("No matching interface found for connection file: {0}")]
+    UnmatchedConnectionFile(String),
+    /// Any other failure, e.g. I/O errors or invalid nmstate documents.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// Internal helpers report typed failures wrapped in `anyhow::Error`,
+/// these are recovered as long as no further context was attached to them.
+impl From<anyhow::Error> for ConfigError {
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().count() > 1 {
+            return ConfigError::Other(err);
+        }
+
+        err.downcast::<ConfigError>()
+            .unwrap_or_else(ConfigError::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use crate::error::ConfigError;
+
+    #[test]
+    fn convert_from_anyhow() {
+        let err = ConfigError::from(anyhow::Error::from(ConfigError::EmptyConfigDir));
+        assert!(matches!(err, ConfigError::EmptyConfigDir));
+
+        let err = ConfigError::from(anyhow!("Invalid YAML string"));
+        assert!(matches!(err, ConfigError::Other(..)));
+        assert_eq!(err.to_string(), "Invalid YAML string");
+
+        // Context is preserved rather than dropped in favour of the typed error.
+        let err = ConfigError::from(
+            Err::<(), _>(ConfigError::MissingEthernet)
+                .context("Generating config")
+                .unwrap_err(),
+        );
+        assert!(matches!(err, ConfigError::Other(..)));
+        assert_eq!(
+            format!("{err:#}"),
+            "Generating config: No Ethernet interfaces were provided"
+        );
+    }
+}
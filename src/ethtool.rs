@@ -0,0 +1,88 @@
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::raw::{c_char, c_void};
+use std::os::unix::io::AsRawFd;
+
+/// `ioctl` request for ethtool commands.
+const SIOCETHTOOL: libc::c_ulong = 0x8946;
+/// ethtool command retrieving the permanent hardware address.
+const ETHTOOL_GPERMADDR: u32 = 0x20;
+/// Maximum hardware address length supported by the kernel.
+const MAX_ADDR_LEN: usize = 32;
+
+#[repr(C)]
+struct PermAddr {
+    cmd: u32,
+    size: u32,
+    data: [u8; MAX_ADDR_LEN],
+}
+
+#[repr(C)]
+struct IfReq {
+    name: [c_char; libc::IFNAMSIZ],
+    data: *mut c_void,
+    // Pad to the size of the kernel's `ifreq` union.
+    _pad: [u8; 16],
+}
+
+/// Read the permanent (hardware) MAC address of the given interface via ethtool.
+///
+/// Returns `None` if the interface does not support the query (e.g. non-Ethernet)
+/// or does not have a permanent address.
+pub(crate) fn permanent_mac(name: &str) -> Option<String> {
+    if name.len() >= libc::IFNAMSIZ {
+        return None;
+    }
+
+    let mut perm_addr = PermAddr {
+        cmd: ETHTOOL_GPERMADDR,
+        size: MAX_ADDR_LEN as u32,
+        data: [0; MAX_ADDR_LEN],
+    };
+    let mut request = IfReq {
+        name: [0; libc::IFNAMSIZ],
+        data: &mut perm_addr as *mut PermAddr as *mut c_void,
+        _pad: [0; 16],
+    };
+    for (dst, src) in request.name.iter_mut().zip(name.bytes()) {
+        *dst = src as c_char;
+    }
+
+    // SAFETY: the socket is only used for the ioctl below and closed once `socket` is dropped.
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return None;
+        }
+        OwnedFd::from_raw_fd(fd)
+    };
+
+    // SAFETY: `request` points to a valid `PermAddr` outliving the call.
+    let result = unsafe { libc::ioctl(socket.as_raw_fd(), SIOCETHTOOL as _, &mut request) };
+    if result < 0 {
+        return None;
+    }
+
+    let address = perm_addr.data.get(..perm_addr.size as usize)?;
+    if address.is_empty() || address.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+
+    Some(
+        address
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ethtool::permanent_mac;
+
+    #[test]
+    fn permanent_mac_of_unknown_interface() {
+        assert_eq!(permanent_mac("<missing>"), None);
+        assert_eq!(permanent_mac("lo"), None);
+    }
+}
@@ -1,62 +1,769 @@
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs;
-use std::path::Path;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
-use crate::types::{Host, Interface};
-use crate::{ALL_HOSTS_DIR, ALL_HOSTS_FILE, HOST_MAPPING_FILE};
+use crate::apply_conf::mac_set_hash;
+use crate::archive;
+use crate::cloud_init::{self, CLOUD_INIT_FILE};
+use crate::error::ConfigError;
+use crate::keyfile;
+use crate::merge_conf::{merge_documents, MergePolicy};
+use crate::timing::Timings;
+use crate::types::{validate_unique_mac_addresses, Host, Interface, InterfaceType};
+use crate::{
+    ALL_HOSTS_DIR, ALL_HOSTS_FILE, HOSTNAME_DISPATCHER_FILE, HOST_MAPPING_FILE,
+    HOST_MAPPING_JSON_FILE,
+};
 use anyhow::{anyhow, Context};
 use configparser::ini::Ini;
-use log::{info, warn};
-use nmstate::{InterfaceType, NetworkState};
+use log::{debug, info, warn};
+use nmstate::{NetworkState, NmstateError};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
 
 /// `NetworkConfig` contains the generated configurations in the
 /// following format: `Vec<(config_file_name, config_content>)`
-type NetworkConfig = Vec<(String, String)>;
+pub type NetworkConfig = Vec<(String, String)>;
+
+/// Config dir value reading a single host's configuration from stdin instead.
+pub const STDIN_CONFIG_DIR: &str = "-";
+
+/// Valid range of 802.1Q VLAN ids.
+const VLAN_IDS: RangeInclusive<u16> = 1..=4094;
+
+/// Suffix of the `<hostname>.override.yaml` files layered on the unified config.
+const OVERRIDE_SUFFIX: &str = ".override";
+
+/// Top-level section of the desired states describing the rollout of the host, stripped before nmstate sees it.
+const ROLLOUT_KEY: &str = "rollout";
+
+/// Ordered list of hostnames for a rolling apply across the fleet.
+const APPLY_ORDER_FILE: &str = "apply_order.txt";
+
+/// Subdirectory storing the per-interface nmstate fragments of a host.
+const FRAGMENTS_DIR: &str = "fragments";
+
+/// Script bringing up the generated connections of a host in dependency order.
+const ACTIVATION_SCRIPT_FILE: &str = "activate.sh";
+
+/// Dispatcher script persisting the hostname received via DHCP in `/etc/hostname`.
+const HOSTNAME_DISPATCHER_SCRIPT: &str = r#"#!/bin/sh
+# Generated by nmc
+# Keeps /etc/hostname in sync with the hostname provided via DHCP.
+
+case "$2" in
+    up|dhcp4-change)
+        if [ -n "$DHCP4_HOST_NAME" ] && [ "$DHCP4_HOST_NAME" != "$(cat /etc/hostname 2>/dev/null)" ]; then
+            echo "$DHCP4_HOST_NAME" > /etc/hostname
+        fi
+        ;;
+esac
+"#;
+
+/// Optional behaviour of the `generate` command.
+#[derive(Default)]
+#[non_exhaustive]
+pub struct GenerateOptions {
+    /// Emit an activation script next to the connection files of every host.
+    pub emit_activation_script: bool,
+    /// Emit a dispatcher script syncing the static hostname with the DHCP provided one.
+    pub emit_hostname_dispatcher: bool,
+    /// Template for `connection.stable-id` set on all connections not specifying one.
+    pub stable_id: Option<String>,
+    /// Fail on advisory findings instead of only logging them.
+    pub strict: bool,
+    /// Naming convention all connection ids must follow.
+    pub id_pattern: Option<Regex>,
+    /// Expand `${VAR}` references in the input from the process environment.
+    pub env_substitute: bool,
+    /// Keep references to undefined environment variables as-is instead of failing.
+    pub allow_undefined: bool,
+    /// Log the duration of the individual phases once done.
+    pub timing: bool,
+    /// Only generate the configurations of these hosts (all if empty) preserving the output of the others.
+    pub hosts: Vec<String>,
+    /// Mode of the generated connection files, defaults to the one derived from the umask.
+    pub output_mode: Option<u32>,
+    /// Emit the nmstate definition of every interface as a standalone YAML fragment.
+    pub emit_fragments: bool,
+    /// Attribute the Ethernet connections are bound to, keeps the nmstate output if not set.
+    pub bind_by: Option<BindBy>,
+    /// Host the configuration read from stdin belongs to.
+    pub hostname: Option<String>,
+    /// VLAN ids reserved within the organisation, reported as advisory findings.
+    pub reserved_vlans: Vec<RangeInclusive<u16>>,
+    /// Arbitrary keys set on the generated connection files, applied in order.
+    pub overrides: Vec<KeyfileOverride>,
+    /// Emit a Graphviz diagram of the interface hierarchy as `<host>.dot` for every host.
+    pub emit_topology: bool,
+    /// Warn about secrets (e.g. Wi-Fi PSKs) stored in plaintext in the generated connection files.
+    pub warn_on_secrets: bool,
+    /// Emit the hostnames ordered by the `rollout` sections of the desired states as `apply_order.txt`.
+    pub emit_apply_order: bool,
+    /// Store a hash of every host's MAC addresses salted with this value in the host mapping
+    /// instead of the addresses themselves.
+    pub mac_set_hash_salt: Option<String>,
+    /// Value of `connection.autoconnect` set on all connections not specifying one.
+    pub autoconnect: Option<bool>,
+    /// Value of `ipv6.ip6-privacy` set on all IPv6 enabled connections not specifying one.
+    pub ipv6_privacy: Option<u8>,
+    /// Validation applied to the nmstate input on top of its own parsing.
+    pub nmstate_strictness: NmstateStrictness,
+    /// Format of the generated network configuration.
+    pub backend: Backend,
+    /// Serialization format of the host mapping.
+    pub mapping_format: MappingFormat,
+    /// Resolution of interfaces defined by both the unified config and a per-host override.
+    pub merge_policy: MergePolicy,
+    /// Number of connections per host (or unified bundle) above which an advisory finding is reported.
+    pub max_connections: Option<usize>,
+    /// Maximum number of hosts generated in parallel (defaults to the number of CPUs).
+    pub jobs: Option<usize>,
+}
+
+/// Keyfile value set on all generated connection files or only on the one with the given id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyfileOverride {
+    /// Only applied to the connection with this id if set.
+    pub connection_id: Option<String>,
+    pub section: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// Parse a global keyfile override in the `<section>.<key>=<value>` format.
+pub fn parse_override(value: &str) -> Result<KeyfileOverride, String> {
+    let (path, override_value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid override '{value}', expected <section>.<key>=<value>"))?;
+    // Section names may contain dots (e.g. 'wireguard-peer.<key>') contrary to the keys.
+    let (section, key) = path
+        .rsplit_once('.')
+        .filter(|(section, key)| !section.is_empty() && !key.is_empty())
+        .ok_or_else(|| format!("invalid override '{value}', expected <section>.<key>=<value>"))?;
+
+    Ok(KeyfileOverride {
+        connection_id: None,
+        section: section.to_owned(),
+        key: key.to_owned(),
+        value: override_value.to_owned(),
+    })
+}
+
+/// Parse a per-connection keyfile override in the `<id>:<section>.<key>=<value>` format.
+pub fn parse_connection_override(value: &str) -> Result<KeyfileOverride, String> {
+    let (connection_id, setting) = value
+        .split_once(':')
+        .filter(|(connection_id, _)| !connection_id.is_empty())
+        .ok_or_else(|| {
+            format!("invalid override '{value}', expected <id>:<section>.<key>=<value>")
+        })?;
+
+    Ok(KeyfileOverride {
+        connection_id: Some(connection_id.to_owned()),
+        ..parse_override(setting)?
+    })
+}
+
+/// Attribute binding an Ethernet connection to a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindBy {
+    /// `connection.interface-name`, renamed to the local interface name by `apply`.
+    Name,
+    /// `ethernet.mac-address`, matching the device regardless of its name.
+    Mac,
+}
+
+/// Parse the `--bind-by` value.
+pub fn parse_bind_by(value: &str) -> Result<BindBy, String> {
+    match value {
+        "name" => Ok(BindBy::Name),
+        "mac" => Ok(BindBy::Mac),
+        _ => Err(format!(
+            "invalid binding '{value}', expected 'name' or 'mac'"
+        )),
+    }
+}
+
+/// Format of the generated network configuration.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    /// NetworkManager keyfiles along with the host mapping used by `apply`.
+    #[default]
+    NetworkManager,
+    /// A single cloud-init network-config (version 2) file per host.
+    CloudInit,
+}
+
+/// Parse the `--backend` value.
+pub fn parse_backend(value: &str) -> Result<Backend, String> {
+    match value {
+        "networkmanager" => Ok(Backend::NetworkManager),
+        "cloud-init" => Ok(Backend::CloudInit),
+        _ => Err(format!(
+            "invalid backend '{value}', expected 'networkmanager' or 'cloud-init'"
+        )),
+    }
+}
+
+/// Serialization format of the host mapping.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MappingFormat {
+    /// `host_config.yaml`
+    #[default]
+    Yaml,
+    /// `host_config.json`
+    Json,
+}
+
+impl MappingFormat {
+    fn filename(self) -> &'static str {
+        match self {
+            MappingFormat::Yaml => HOST_MAPPING_FILE,
+            MappingFormat::Json => HOST_MAPPING_JSON_FILE,
+        }
+    }
+
+    fn serialize(self, hosts: &[Host]) -> anyhow::Result<String> {
+        match self {
+            MappingFormat::Yaml => serde_yaml::to_string(hosts).context("Serializing mapping file"),
+            MappingFormat::Json => {
+                serde_json::to_string_pretty(hosts).context("Serializing mapping file")
+            }
+        }
+    }
+}
+
+/// Parse the `--mapping-format` value.
+pub fn parse_mapping_format(value: &str) -> Result<MappingFormat, String> {
+    match value {
+        "yaml" => Ok(MappingFormat::Yaml),
+        "json" => Ok(MappingFormat::Json),
+        _ => Err(format!(
+            "invalid mapping format '{value}', expected 'yaml' or 'json'"
+        )),
+    }
+}
+
+/// Determine the path of the host mapping within `dir`, preferring the YAML one
+/// and falling back to the JSON one only if it exists.
+pub(crate) fn mapping_file_path(dir: &Path) -> PathBuf {
+    let json = dir.join(HOST_MAPPING_JSON_FILE);
+    if !dir.join(HOST_MAPPING_FILE).exists() && json.exists() {
+        return json;
+    }
+
+    dir.join(HOST_MAPPING_FILE)
+}
+
+/// Read the host mapping in the format given by the extension of the `path`.
+pub(crate) fn read_mapping(path: &Path) -> anyhow::Result<Vec<Host>> {
+    let contents = fs::read_to_string(path)?;
+
+    if path.extension() == Some(OsStr::new("json")) {
+        serde_json::from_str(&contents).context("Parsing host mapping")
+    } else {
+        serde_yaml::from_str(&contents).context("Parsing host mapping")
+    }
+}
+
+/// Validation applied to the nmstate input on top of its own parsing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NmstateStrictness {
+    /// Accept everything nmstate parses, e.g. interfaces of unknown or omitted type.
+    #[default]
+    Permissive,
+    /// Additionally reject interfaces whose type nmstate does not recognise.
+    Strict,
+}
+
+/// Parse the `--nmstate-strictness` value.
+pub fn parse_nmstate_strictness(value: &str) -> Result<NmstateStrictness, String> {
+    match value {
+        "permissive" => Ok(NmstateStrictness::Permissive),
+        "strict" => Ok(NmstateStrictness::Strict),
+        _ => Err(format!(
+            "invalid strictness '{value}', expected 'strict' or 'permissive'"
+        )),
+    }
+}
 
 /// Generate network configurations from all YAML files in the `config_dir`
 /// and store the result *.nmconnection files and host mapping (if applicable) under `output_dir`.
-pub(crate) fn generate(config_dir: &str, output_dir: &str) -> anyhow::Result<()> {
-    let files_count = fs::read_dir(config_dir)?.count();
+/// The `config_dir` may also be a gzipped tarball (`.tar.gz` or `.tgz`) which is extracted temporarily.
+pub(crate) fn generate(
+    config_dir: &str,
+    output_dir: &str,
+    options: &GenerateOptions,
+) -> Result<(), ConfigError> {
+    let mut timings = Timings::default();
 
-    if files_count == 0 {
-        return Err(anyhow!("Empty config directory"));
-    } else if files_count == 1 {
-        let path = Path::new(config_dir).join(ALL_HOSTS_FILE);
-        if let Ok(contents) = fs::read_to_string(&path) {
-            info!("Generating config from {path:?}...");
+    let hosts = generate_hosts(config_dir, options, &mut timings)?;
 
-            let (_, config) = generate_config(contents, false)?;
-            return store_network_config(output_dir, ALL_HOSTS_DIR, config)
-                .context("Storing network config");
-        };
+    let mut mapping = if options.hosts.is_empty() {
+        Vec::new()
+    } else {
+        let regenerated: Vec<&str> = hosts.iter().map(|(h, _)| h.hostname.as_str()).collect();
+        retained_network_mapping(output_dir, &regenerated)
+            .context("Updating existing network mapping")?
     };
 
-    for entry in fs::read_dir(config_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    for (host, config) in hosts {
+        let phase = format!("store {}", host.hostname);
+        timings.measure(phase, || -> anyhow::Result<()> {
+            if options.emit_topology {
+                let path = Path::new(output_dir).join(format!("{}.dot", host.hostname));
+                fs::create_dir_all(output_dir).context("Creating output dir")?;
+                fs::write(
+                    &path,
+                    topology_dot(&host.hostname, &host.interfaces, &config)?,
+                )
+                .context("Writing topology diagram")?;
+            }
+            store_network_config(output_dir, &host.hostname, config, options.output_mode)
+                .context("Storing network config")?;
+            if options.emit_activation_script {
+                store_activation_script(output_dir, &host.hostname, &host.interfaces)
+                    .context("Storing activation script")?;
+            }
 
-        if entry.metadata()?.is_dir() {
-            warn!("Ignoring unexpected dir: {path:?}");
-            continue;
+            // Host mapping is neither necessary for unified configurations nor used by cloud-init.
+            if host.hostname != ALL_HOSTS_DIR && options.backend == Backend::NetworkManager {
+                mapping.push(mapping_entry(host, options));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    if !mapping.is_empty() {
+        if options.emit_apply_order {
+            fs::write(
+                Path::new(output_dir).join(APPLY_ORDER_FILE),
+                apply_order(&mapping),
+            )
+            .context("Writing apply order")?;
+        }
+        store_network_mapping(output_dir, mapping, options.mapping_format)
+            .context("Storing network mapping")?;
+    }
+
+    if options.emit_hostname_dispatcher {
+        store_hostname_dispatcher(output_dir).context("Storing hostname dispatcher script")?;
+    }
+
+    if options.timing {
+        info!("{}", timings.summary());
+    }
+
+    Ok(())
+}
+
+/// Same as `generate` but writes the output tree as a gzipped tarball to `writer` instead of storing it,
+/// e.g. for streaming it to a remote `apply` without intermediate files.
+///
+/// Keyfiles are archived with the configured output mode, defaulting to `0600`.
+pub(crate) fn generate_archive(
+    config_dir: &str,
+    writer: impl Write,
+    options: &GenerateOptions,
+) -> Result<(), ConfigError> {
+    let mut timings = Timings::default();
+
+    let hosts = generate_hosts(config_dir, options, &mut timings)?;
+
+    let mut entries = Vec::new();
+    let mut mapping = Vec::new();
+
+    for (host, config) in hosts {
+        if options.emit_topology {
+            entries.push((
+                format!("{}.dot", host.hostname),
+                topology_dot(&host.hostname, &host.interfaces, &config)?,
+                0o644,
+            ));
+        }
+        for (filename, content) in config {
+            entries.push((
+                format!("{}/{filename}", host.hostname),
+                content,
+                options.output_mode.unwrap_or(0o600),
+            ));
+        }
+        if options.emit_activation_script {
+            entries.push((
+                format!("{}/{ACTIVATION_SCRIPT_FILE}", host.hostname),
+                activation_script(&host.interfaces),
+                0o755,
+            ));
+        }
+
+        // Host mapping is neither necessary for unified configurations nor used by cloud-init.
+        if host.hostname != ALL_HOSTS_DIR && options.backend == Backend::NetworkManager {
+            mapping.push(mapping_entry(host, options));
+        }
+    }
+
+    if !mapping.is_empty() {
+        if options.emit_apply_order {
+            entries.push((APPLY_ORDER_FILE.to_owned(), apply_order(&mapping), 0o644));
         }
+        sort_network_mapping(&mut mapping);
+        let contents = options.mapping_format.serialize(&mapping)?;
+        entries.push((
+            options.mapping_format.filename().to_owned(),
+            contents,
+            0o644,
+        ));
+    }
+    if options.emit_hostname_dispatcher {
+        entries.push((
+            HOSTNAME_DISPATCHER_FILE.to_owned(),
+            HOSTNAME_DISPATCHER_SCRIPT.to_owned(),
+            0o755,
+        ));
+    }
+
+    timings
+        .measure("archive", || archive::write_archive(writer, &entries))
+        .context("Writing config archive")?;
+
+    if options.timing {
+        info!("{}", timings.summary());
+    }
+
+    Ok(())
+}
+
+/// Generate the network configurations of the `config_dir` without persisting them,
+/// extracting it first if it is a gzipped tarball.
+pub(crate) fn generate_network_configs(
+    config_dir: &str,
+    options: &GenerateOptions,
+) -> Result<Vec<(Host, NetworkConfig)>, ConfigError> {
+    let mut timings = Timings::default();
+
+    let hosts = generate_hosts(config_dir, options, &mut timings)?;
+
+    if options.timing {
+        info!("{}", timings.summary());
+    }
+
+    Ok(hosts)
+}
+
+/// Generate the network configurations of the `config_dir`, extracting it first if it is a gzipped tarball.
+fn generate_hosts(
+    config_dir: &str,
+    options: &GenerateOptions,
+    timings: &mut Timings,
+) -> anyhow::Result<Vec<(Host, NetworkConfig)>> {
+    let extracted = if archive::is_config_archive(config_dir) {
+        Some(archive::extract_config_archive(Path::new(config_dir))?)
+    } else {
+        None
+    };
+    let config_dir = match &extracted {
+        Some(dir) => dir
+            .path()
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid temporary config dir"))?,
+        None => config_dir,
+    };
+
+    generate_in_memory(config_dir, options, timings)
+}
+
+/// Generate network configurations from all YAML files in the `config_dir` without persisting them.
+///
+/// Unified configurations are returned as a single host named after `ALL_HOSTS_DIR`.
+/// A `config_dir` of `-` reads the configuration of the host given in the options from stdin.
+pub(crate) fn generate_in_memory(
+    config_dir: &str,
+    options: &GenerateOptions,
+    timings: &mut Timings,
+) -> anyhow::Result<Vec<(Host, NetworkConfig)>> {
+    if config_dir == STDIN_CONFIG_DIR {
+        let hostname = options
+            .hostname
+            .as_deref()
+            .ok_or_else(|| anyhow!("Reading config from stdin requires a hostname"))?;
+
+        info!("Generating config for {hostname} from stdin...");
+
+        let data = timings
+            .measure(format!("read {hostname}"), || {
+                io::read_to_string(io::stdin())
+            })
+            .context("Reading network config from stdin")?;
+
+        // MAC addresses are still required unless the input is meant for all hosts.
+        let (interfaces, config, rollout) = timings
+            .measure(format!("gen_conf {hostname}"), || {
+                generate_config_with_rollout("stdin", data, hostname != ALL_HOSTS_DIR, options)
+            })?;
+        let host = Host {
+            hostname: hostname.to_owned(),
+            mac_set_hash: None,
+            serial: None,
+            group: rollout.group,
+            priority: rollout.priority,
+            interfaces,
+        };
+        return Ok(vec![(host, config)]);
+    }
+
+    let paths = find_network_configs(Path::new(config_dir))?;
+
+    if paths.is_empty() {
+        return Err(ConfigError::EmptyConfigDir.into());
+    }
+
+    if let Some(path) = unified_config_file(config_dir, &paths)? {
+        let contents = timings
+            .measure(format!("read {ALL_HOSTS_DIR}"), || {
+                fs::read_to_string(&path)
+            })
+            .context("Reading network config")?;
 
         info!("Generating config from {path:?}...");
 
+        let mut hosts = Vec::new();
+        for override_path in paths
+            .iter()
+            .filter(|path| override_hostname(path).is_some())
+        {
+            let hostname = override_hostname(override_path)
+                .expect("filtered override files")
+                .to_owned();
+
+            if hosts
+                .iter()
+                .any(|(host, _): &(Host, _)| host.hostname == hostname)
+            {
+                return Err(anyhow!("Duplicate override for hostname '{hostname}'"));
+            }
+
+            if !options.hosts.is_empty() && !options.hosts.contains(&hostname) {
+                debug!("Skipping host not selected for generation: {hostname}");
+                continue;
+            }
+
+            info!("Generating config from {override_path:?} layered on {path:?}...");
+
+            let data = fs::read_to_string(override_path).context("Reading override config")?;
+            let data = merge_documents(&contents, &data, options.merge_policy)
+                .context(format!("Merging {override_path:?} into {path:?}"))?;
+
+            // MAC addresses are optional as the hosts are identified by their hostname instead.
+            let source = override_path.file_name().and_then(OsStr::to_str);
+            let (interfaces, config, rollout) = timings
+                .measure(format!("gen_conf {hostname}"), || {
+                    generate_config_with_rollout(source.unwrap_or_default(), data, false, options)
+                })?;
+            validate_referenced_files(
+                &config,
+                override_path.parent().unwrap_or(Path::new(config_dir)),
+            )?;
+
+            hosts.push((
+                Host {
+                    hostname,
+                    mac_set_hash: None,
+                    serial: None,
+                    group: rollout.group,
+                    priority: rollout.priority,
+                    interfaces,
+                },
+                config,
+            ));
+        }
+
+        let (interfaces, config) = timings.measure(format!("gen_conf {ALL_HOSTS_DIR}"), || {
+            generate_config(ALL_HOSTS_FILE, contents, false, options)
+        })?;
+        validate_referenced_files(&config, Path::new(config_dir))?;
+        let host = Host {
+            hostname: ALL_HOSTS_DIR.to_owned(),
+            mac_set_hash: None,
+            serial: None,
+            group: None,
+            priority: None,
+            interfaces,
+        };
+        hosts.insert(0, (host, config));
+        return Ok(hosts);
+    }
+
+    let mut hosts = Vec::new();
+    let mut jobs = Vec::new();
+    let mut host_paths: HashMap<String, PathBuf> = HashMap::new();
+
+    for path in paths {
         let hostname = extract_hostname(&path)
             .and_then(OsStr::to_str)
             .ok_or_else(|| anyhow!("Invalid file path"))?
             .to_owned();
 
-        let data = fs::read_to_string(&path).context("Reading network config")?;
+        if hostname == ALL_HOSTS_DIR {
+            return Err(anyhow!(
+                "Unified config {path:?} must be placed directly in the config dir"
+            ));
+        }
+
+        if override_hostname(&path).is_some() {
+            return Err(anyhow!(
+                "Override config {path:?} requires a unified config ({ALL_HOSTS_FILE}) to be layered on"
+            ));
+        }
 
-        let (interfaces, config) = generate_config(data, true)?;
+        if let Some(other) = host_paths.insert(hostname.clone(), path.clone()) {
+            return Err(anyhow!(
+                "Duplicate hostname '{hostname}': {other:?} and {path:?}"
+            ));
+        }
 
-        store_network_config(output_dir, &hostname, config).context("Storing network config")?;
-        store_network_mapping(output_dir, hostname, interfaces)
-            .context("Storing network mapping")?;
+        if !options.hosts.is_empty() && !options.hosts.contains(&hostname) {
+            debug!("Skipping host not selected for generation: {hostname}");
+            continue;
+        }
+
+        jobs.push((hostname, path));
     }
 
-    Ok(())
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .context("Creating generation thread pool")?;
+
+    // Results are collected in input order so the subsequent writes stay deterministic.
+    let results = pool.install(|| {
+        jobs.into_par_iter()
+            .map(|(hostname, path)| generate_host(config_dir, hostname, &path, options))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+
+    for (host, config, host_timings) in results {
+        timings.extend(host_timings);
+        hosts.push((host, config));
+    }
+
+    options
+        .hosts
+        .iter()
+        .filter(|hostname| !hosts.iter().any(|(host, _)| &host.hostname == *hostname))
+        .for_each(|hostname| warn!("Selected host not found in config dir: {hostname}"));
+
+    Ok(hosts)
+}
+
+/// Generate the config of a single host stored at `path`.
+///
+/// Runs on the generation thread pool, hence the phases are measured separately.
+fn generate_host(
+    config_dir: &str,
+    hostname: String,
+    path: &Path,
+    options: &GenerateOptions,
+) -> anyhow::Result<(Host, NetworkConfig, Timings)> {
+    info!("Generating config from {path:?}...");
+
+    let mut timings = Timings::default();
+
+    let data = timings
+        .measure(format!("read {hostname}"), || fs::read_to_string(path))
+        .context("Reading network config")?;
+
+    let source = path.file_name().and_then(OsStr::to_str);
+    let (interfaces, config, rollout) = timings.measure(format!("gen_conf {hostname}"), || {
+        generate_config_with_rollout(source.unwrap_or_default(), data, true, options)
+    })?;
+    validate_referenced_files(&config, path.parent().unwrap_or(Path::new(config_dir)))?;
+
+    let host = Host {
+        hostname,
+        mac_set_hash: None,
+        serial: None,
+        group: rollout.group,
+        priority: rollout.priority,
+        interfaces,
+    };
+
+    Ok((host, config, timings))
+}
+
+/// List the files within the `config_dir` and all of its subdirectories (e.g. grouping hosts per rack) in sorted order.
+pub(crate) fn find_config_files(config_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(config_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(find_config_files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Return the path of the unified configuration if it is among the config `paths`.
+///
+/// Unified configurations apply to all hosts and cannot be combined with per-host ones.
+pub(crate) fn unified_config_file(
+    config_dir: &str,
+    paths: &[PathBuf],
+) -> anyhow::Result<Option<PathBuf>> {
+    let unified = Path::new(config_dir).join(ALL_HOSTS_FILE);
+    if !paths.contains(&unified) {
+        return Ok(None);
+    }
+
+    let others: Vec<String> = paths
+        .iter()
+        .filter(|path| **path != unified && override_hostname(path).is_none())
+        .map(|path| path.display().to_string())
+        .collect();
+    if !others.is_empty() {
+        return Err(anyhow!(
+            "Unified config {unified:?} cannot be combined with per-host configs: {}",
+            others.join(", ")
+        ));
+    }
+
+    Ok(Some(unified))
+}
+
+/// List the nmstate files within the `config_dir`, i.e. YAML files or files without an extension,
+/// skipping auxiliary ones such as the certificates referenced by 802.1x settings.
+pub(crate) fn find_network_configs(config_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(find_config_files(config_dir)?
+        .into_iter()
+        .filter(|path| match path.extension() {
+            None => true,
+            Some(ext) if ext == "yml" || ext == "yaml" => true,
+            Some(..) => {
+                debug!("Skipping auxiliary file {path:?}");
+                false
+            }
+        })
+        .collect())
+}
+
+/// Determine the host of a `<hostname>.override.yaml` file layered on the unified config.
+pub(crate) fn override_hostname(path: &Path) -> Option<&str> {
+    extract_hostname(path)?
+        .to_str()?
+        .strip_suffix(OVERRIDE_SUFFIX)
+        .filter(|hostname| !hostname.is_empty())
 }
 
 fn extract_hostname(path: &Path) -> Option<&OsStr> {
@@ -70,298 +777,3420 @@ fn extract_hostname(path: &Path) -> Option<&OsStr> {
     }
 }
 
-fn generate_config(
+/// Generate the NetworkManager configuration for a single nmstate document read from `source`.
+///
+/// The returned interfaces are ordered so that each one follows the interfaces it is built on
+/// (e.g. Ethernet ports before their bond, a bond before the VLAN or bridge on top of it).
+pub(crate) fn generate_config(
+    source: &str,
     data: String,
     require_mac_addresses: bool,
+    options: &GenerateOptions,
 ) -> anyhow::Result<(Vec<Interface>, NetworkConfig)> {
-    let network_state = NetworkState::new_from_yaml(&data)?;
+    let (interfaces, config, _) =
+        generate_config_with_rollout(source, data, require_mac_addresses, options)?;
+    Ok((interfaces, config))
+}
+
+/// Generate the NetworkManager configuration like `generate_config`, additionally returning the rollout section.
+fn generate_config_with_rollout(
+    source: &str,
+    data: String,
+    require_mac_addresses: bool,
+    options: &GenerateOptions,
+) -> anyhow::Result<(Vec<Interface>, NetworkConfig, Rollout)> {
+    let (data, rollout) = prepare_document(data, options, |name| std::env::var(name).ok())?;
+
+    let network_state =
+        NetworkState::new_from_yaml(&data).map_err(|e| nmstate_error(source, &data, e))?;
+    if options.nmstate_strictness == NmstateStrictness::Strict {
+        validate_interface_types(&network_state)?;
+    }
 
-    let mut interfaces = extract_interfaces(&network_state);
+    let mut interfaces = extract_interfaces(&network_state)?;
+    for interface in &interfaces {
+        debug!(
+            "Extracted interface '{}' of type '{}' with MAC address {}",
+            interface.logical_name,
+            interface.interface_type,
+            interface.mac_address.as_deref().unwrap_or("<none>")
+        );
+    }
+    let dependencies = interface_dependencies(&network_state);
+    sort_by_dependencies(&mut interfaces, &dependencies);
     validate_interfaces(&interfaces, require_mac_addresses)?;
+    validate_unique_mac_addresses(&interfaces)?;
+    let mut topology_findings = validate_vlan_ids(&network_state, &options.reserved_vlans)?;
+    topology_findings.extend(find_isolated_interfaces(&network_state, &dependencies));
+
+    if options.backend == Backend::CloudInit {
+        check_advisories(topology_findings, options.strict)?;
+        let config = vec![(
+            CLOUD_INIT_FILE.to_owned(),
+            cloud_init::network_config(&network_state)?,
+        )];
+        return Ok((interfaces, config, rollout));
+    }
 
-    let config = network_state
-        .gen_conf()?
+    let mut config = network_state
+        .gen_conf()
+        .map_err(|e| nmstate_error(source, &data, e))?
         .get("NetworkManager")
         .ok_or_else(|| anyhow!("Invalid NM configuration"))?
         .to_owned();
+    debug!(
+        "Generated connection files: {}",
+        config
+            .iter()
+            .map(|(filename, _)| filename.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    post_process_config(&mut config, options)?;
 
     populate_connection_ids(&mut interfaces, &config)?;
     validate_connection_ids(&interfaces)?;
+    validate_parent_refs(&config)?;
+    if let Some(pattern) = &options.id_pattern {
+        validate_connection_id_pattern(&interfaces, pattern)?;
+    }
 
-    Ok((interfaces, config))
-}
+    if let Some(bind_by) = options.bind_by {
+        bind_connections(&mut config, &interfaces, bind_by)?;
+    }
 
-fn validate_connection_ids(interfaces: &[Interface]) -> anyhow::Result<()> {
-    let empty_connection_ids: Vec<String> = interfaces
-        .iter()
-        .filter(|i| i.connection_ids.is_empty())
-        .map(|i| i.logical_name.to_owned())
-        .collect();
+    let mut findings = validate_flow_control(&config)?;
+    findings.extend(validate_dns_servers(&config)?);
+    if let Some(max_connections) = options.max_connections {
+        findings.extend(check_connection_count(&config, max_connections));
+    }
+    findings.extend(topology_findings);
+    check_advisories(findings, options.strict)?;
 
-    if !empty_connection_ids.is_empty() {
-        return Err(anyhow!(
-            "Detected interfaces without connection files: {}",
-            empty_connection_ids.join(", ")
-        ));
+    if options.warn_on_secrets {
+        if let Some(finding) = find_plaintext_secrets(&config)? {
+            warn!("{finding}");
+        }
+    }
+
+    if options.emit_fragments {
+        config.extend(extract_fragments(&network_state)?);
+    }
+
+    Ok((interfaces, config, rollout))
+}
+
+/// Expand the environment variable references of the desired state if requested and split off its rollout section,
+/// so that the rollout may refer to environment variables as well.
+fn prepare_document(
+    data: String,
+    options: &GenerateOptions,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<(String, Rollout)> {
+    let data = if options.env_substitute {
+        substitute_env_vars(&data, options.allow_undefined, lookup)?
+    } else {
+        data
     };
 
-    Ok(())
+    split_rollout(data)
 }
 
-fn populate_connection_ids(
-    interfaces: &mut [Interface],
-    config: &NetworkConfig,
-) -> anyhow::Result<()> {
-    for (filename, content) in config {
-        let mut c = Ini::new();
-        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+/// Position of a host within a rolling apply across the fleet (see `--emit-apply-order`).
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Rollout {
+    group: Option<String>,
+    priority: Option<i32>,
+}
 
-        if c.get("connection", "type").is_some_and(|t| t == "loopback") {
-            continue;
-        }
+/// Split the optional rollout section off the desired state, leaving a plain nmstate document.
+///
+/// Documents without such a section are returned as is, so that nmstate reports any errors
+/// (e.g. invalid YAML) against the original lines.
+fn split_rollout(data: String) -> anyhow::Result<(String, Rollout)> {
+    let Ok(mut state) = serde_yaml::from_str::<serde_yaml::Value>(&data) else {
+        return Ok((data, Rollout::default()));
+    };
+    let Some(rollout) = state
+        .as_mapping_mut()
+        .and_then(|state| state.remove(ROLLOUT_KEY))
+    else {
+        return Ok((data, Rollout::default()));
+    };
 
-        let interface_name = c
-            .get("connection", "interface-name")
-            .ok_or_else(|| anyhow!("No interface-name found in connection file: {}", filename))?;
-        let connection_id = c
-            .get("connection", "id")
-            .ok_or_else(|| anyhow!("No connection id found in connection file: {}", filename))?;
-        interfaces
-            .iter_mut()
-            .find(|x| x.logical_name == interface_name)
-            .ok_or_else(|| {
-                anyhow!(
-                    "No matching interface found for connection file: {}",
-                    filename
-                )
-            })?
-            .connection_ids
-            .push(connection_id);
-    }
+    let rollout = serde_yaml::from_value(rollout).context("Parsing rollout section")?;
+    let data = serde_yaml::to_string(&state).context("Serializing network state")?;
 
-    Ok(())
+    Ok((data, rollout))
 }
 
-fn extract_interfaces(network_state: &NetworkState) -> Vec<Interface> {
-    network_state
+/// Prepare the host mapping entry of the `host`, replacing its MAC addresses by their salted hash if requested.
+///
+/// Hosts without any MAC address (e.g. overrides of a unified config) are left untouched.
+fn mapping_entry(mut host: Host, options: &GenerateOptions) -> Host {
+    let Some(salt) = &options.mac_set_hash_salt else {
+        return host;
+    };
+
+    let macs: Vec<&str> = host
         .interfaces
         .iter()
-        .filter(|i| i.iface_type() != InterfaceType::Loopback)
-        .map(|i| Interface {
-            logical_name: i.name().to_owned(),
-            mac_address: i.base_iface().mac_address.clone(),
-            interface_type: i.iface_type().to_string(),
-            connection_ids: Vec::new(),
-        })
-        .collect()
-}
+        .flat_map(|i| i.mac_address.iter().chain(&i.member_mac_addresses))
+        .map(String::as_str)
+        .collect();
+    if macs.is_empty() {
+        return host;
+    }
 
-fn validate_interfaces(
-    interfaces: &[Interface],
-    require_mac_addresses: bool,
-) -> anyhow::Result<()> {
-    let ethernet_interfaces: Vec<&Interface> = interfaces
+    host.mac_set_hash = Some(mac_set_hash(salt, &macs));
+    for interface in &mut host.interfaces {
+        interface.mac_address = None;
+        interface.member_mac_addresses.clear();
+    }
+
+    host
+}
+
+/// Order the hostnames for a rolling apply by ascending priority (defaulting to 0),
+/// keeping the hosts of a group together and falling back to the hostname.
+///
+/// Groups are ordered by the lowest priority of their hosts, the hosts within a group by their own priority.
+fn apply_order(hosts: &[Host]) -> String {
+    let priority = |host: &Host| host.priority.unwrap_or(0);
+
+    let mut group_priorities: HashMap<&str, i32> = HashMap::new();
+    for host in hosts {
+        if let Some(group) = &host.group {
+            let group_priority = group_priorities.entry(group).or_insert(priority(host));
+            *group_priority = (*group_priority).min(priority(host));
+        }
+    }
+    let key = |host: &Host| {
+        let group_priority = match &host.group {
+            Some(group) => group_priorities[group.as_str()],
+            None => priority(host),
+        };
+        (
+            group_priority,
+            host.group.clone(),
+            priority(host),
+            host.hostname.clone(),
+        )
+    };
+
+    let mut hosts: Vec<&Host> = hosts.iter().collect();
+    hosts.sort_by_key(|host| key(host));
+
+    hosts
         .iter()
-        .filter(|i| i.interface_type == InterfaceType::Ethernet.to_string())
-        .collect();
+        .map(|host| format!("{}\n", host.hostname))
+        .collect()
+}
 
-    if ethernet_interfaces.is_empty() {
-        return Err(anyhow!("No Ethernet interfaces were provided"));
+/// Attach the `source` of the nmstate document to a failure of nmstate and, if determinable,
+/// the name of the offending interface.
+///
+/// The interface is either the one mentioned by the error or, as the deserialization errors of nmstate
+/// do not name it, the first one which fails to be parsed on its own.
+fn nmstate_error(source: &str, data: &str, error: NmstateError) -> anyhow::Error {
+    // Malformed YAML is reported as such rather than as a failure of nmstate.
+    let state = match serde_yaml::from_str::<serde_yaml::Value>(data) {
+        Ok(state) => state,
+        Err(e) => return anyhow::Error::new(e).context(format!("Failed parsing {source}")),
+    };
+    let interfaces: Vec<serde_yaml::Value> = state
+        .get("interfaces")
+        .and_then(|interfaces| interfaces.as_sequence().cloned())
+        .unwrap_or_default();
+    let name_of = |interface: &serde_yaml::Value| -> Option<String> {
+        interface.get("name")?.as_str().map(str::to_owned)
+    };
+
+    // Prefer the longest match, e.g. `bond0.100` rather than `bond0`.
+    let interface = interfaces
+        .iter()
+        .filter_map(name_of)
+        .filter(|name| error.msg().contains(name.as_str()))
+        .max_by_key(String::len)
+        .or_else(|| {
+            interfaces
+                .iter()
+                .find(|interface| {
+                    let state = serde_yaml::Mapping::from_iter([(
+                        "interfaces".into(),
+                        serde_yaml::Value::Sequence(vec![(*interface).clone()]),
+                    )]);
+                    serde_yaml::to_string(&state)
+                        .is_ok_and(|state| NetworkState::new_from_yaml(&state).is_err())
+                })
+                .and_then(name_of)
+        });
+
+    let context = match interface {
+        Some(interface) => format!("Failed generating config for {source} (interface {interface})"),
+        None => format!("Failed generating config for {source}"),
+    };
+    anyhow::Error::new(error).context(context)
+}
+
+/// Expand all `${VAR}` references in `data` with the values of the respective environment variables
+/// as returned by `lookup`.
+fn substitute_env_vars(
+    data: &str,
+    allow_undefined: bool,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<String> {
+    let reference = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)}")?;
+    let mut undefined = Vec::new();
+
+    let data = reference.replace_all(data, |captures: &regex::Captures| {
+        lookup(&captures[1]).unwrap_or_else(|| {
+            undefined.push(captures[1].to_owned());
+            captures[0].to_owned()
+        })
+    });
+
+    if !undefined.is_empty() {
+        if !allow_undefined {
+            return Err(anyhow!(
+                "Detected undefined environment variables: {}",
+                undefined.join(", ")
+            ));
+        }
+        warn!(
+            "Keeping references to undefined environment variables: {}",
+            undefined.join(", ")
+        );
     }
 
-    if !require_mac_addresses {
-        return Ok(());
+    Ok(data.into_owned())
+}
+
+/// Apply the keyfile adjustments requested via `options` to all generated connections.
+fn post_process_config(
+    config: &mut NetworkConfig,
+    options: &GenerateOptions,
+) -> anyhow::Result<()> {
+    for (_, content) in config.iter_mut() {
+        if let Some(stable_id) = &options.stable_id {
+            *content = keyfile::set_default(content, "connection", "stable-id", stable_id);
+        }
+        if let Some(autoconnect) = options.autoconnect {
+            *content = keyfile::set_default(
+                content,
+                "connection",
+                "autoconnect",
+                &autoconnect.to_string(),
+            );
+        }
+        if let Some(ipv6_privacy) = options.ipv6_privacy {
+            if ipv6_enabled(content)? {
+                *content =
+                    keyfile::set_default(content, "ipv6", "ip6-privacy", &ipv6_privacy.to_string());
+            }
+        }
+
+        if options.overrides.is_empty() {
+            continue;
+        }
+
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+        let connection_id = c.get("connection", "id");
+
+        // Global overrides are applied first so that per-connection ones take precedence.
+        let overrides = options
+            .overrides
+            .iter()
+            .filter(|o| o.connection_id.is_none())
+            .chain(
+                options
+                    .overrides
+                    .iter()
+                    .filter(|o| o.connection_id.is_some() && o.connection_id == connection_id),
+            );
+        for o in overrides {
+            *content = keyfile::set(content, &o.section, &o.key, &o.value);
+        }
     }
 
-    let ethernet_interfaces: Vec<String> = ethernet_interfaces
+    Ok(())
+}
+
+fn validate_connection_ids(interfaces: &[Interface]) -> anyhow::Result<()> {
+    let empty_connection_ids: Vec<String> = interfaces
         .iter()
-        .filter(|i| i.mac_address.is_none())
+        .filter(|i| i.connection_ids.is_empty() && !i.unmanaged)
         .map(|i| i.logical_name.to_owned())
         .collect();
 
-    if !ethernet_interfaces.is_empty() {
+    if !empty_connection_ids.is_empty() {
         return Err(anyhow!(
-            "Detected Ethernet interfaces without a MAC address: {}",
-            ethernet_interfaces.join(", ")
+            "Detected interfaces without connection files: {}",
+            empty_connection_ids.join(", ")
         ));
     };
 
     Ok(())
 }
 
-fn store_network_config(
-    output_dir: &str,
-    hostname: &str,
-    config: NetworkConfig,
+fn validate_connection_id_pattern(interfaces: &[Interface], pattern: &Regex) -> anyhow::Result<()> {
+    let invalid_connection_ids: Vec<&str> = interfaces
+        .iter()
+        .flat_map(|i| &i.connection_ids)
+        .filter(|id| !pattern.is_match(id))
+        .map(String::as_str)
+        .collect();
+
+    if !invalid_connection_ids.is_empty() {
+        return Err(anyhow!(
+            "Detected connection ids not matching the pattern '{}': {}",
+            pattern,
+            invalid_connection_ids.join(", ")
+        ));
+    };
+
+    Ok(())
+}
+
+/// Reject VLAN ids outside of the valid range and return the ones within a reserved range as advisory findings.
+fn validate_vlan_ids(
+    network_state: &NetworkState,
+    reserved: &[RangeInclusive<u16>],
+) -> anyhow::Result<Vec<String>> {
+    let vlans: Vec<(&str, u16)> = network_state
+        .interfaces
+        .iter()
+        .filter_map(|i| match i {
+            nmstate::Interface::Vlan(vlan) => {
+                vlan.vlan.as_ref().map(|config| (i.name(), config.id))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut invalid: Vec<String> = vlans
+        .iter()
+        .filter(|(_, id)| !VLAN_IDS.contains(id))
+        .map(|(name, id)| format!("{name} ({id})"))
+        .collect();
+    if !invalid.is_empty() {
+        invalid.sort();
+        return Err(anyhow!(
+            "Detected VLAN ids outside of {}-{}: {}",
+            VLAN_IDS.start(),
+            VLAN_IDS.end(),
+            invalid.join(", ")
+        ));
+    }
+
+    let mut findings: Vec<String> = vlans
+        .iter()
+        .filter_map(|(name, id)| {
+            reserved
+                .iter()
+                .find(|range| range.contains(id))
+                .map(|range| {
+                    format!(
+                        "VLAN interface {name} uses id {id} within the reserved range {}-{}",
+                        range.start(),
+                        range.end()
+                    )
+                })
+        })
+        .collect();
+    findings.sort();
+
+    Ok(findings)
+}
+
+/// Parse a VLAN id range in the `<start>-<end>` format, a single id is accepted as well.
+pub fn parse_vlan_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+
+    match (start.trim().parse::<u16>(), end.trim().parse::<u16>()) {
+        (Ok(start), Ok(end)) if start <= end => Ok(start..=end),
+        _ => Err(format!("invalid VLAN range '{value}'")),
+    }
+}
+
+/// Log the advisory findings as warnings or fail on them in strict mode.
+fn check_advisories(findings: Vec<String>, strict: bool) -> anyhow::Result<()> {
+    if strict && !findings.is_empty() {
+        return Err(anyhow!(
+            "Detected advisory findings in strict mode: {}",
+            findings.join("; ")
+        ));
+    }
+
+    findings.iter().for_each(|finding| warn!("{finding}"));
+    Ok(())
+}
+
+/// Rewrite the Ethernet connection files to be bound to their device by the given attribute only.
+///
+/// Binding by MAC address requires the interface to have a MAC address which is unique
+/// within the host, otherwise the connection remains bound by its interface name.
+fn bind_connections(
+    config: &mut NetworkConfig,
+    interfaces: &[Interface],
+    bind_by: BindBy,
 ) -> anyhow::Result<()> {
-    let path = Path::new(output_dir).join(hostname);
+    for (filename, content) in config.iter_mut() {
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+        if !c
+            .get("connection", "type")
+            .is_some_and(|t| t == "ethernet" || t == "802-3-ethernet")
+        {
+            continue;
+        }
+
+        match bind_by {
+            BindBy::Name => {
+                *content = keyfile::remove(content, "ethernet", "mac-address");
+            }
+            BindBy::Mac => {
+                let Some(interface_name) = c.get("connection", "interface-name") else {
+                    continue;
+                };
+                let Some(mac_address) = interfaces
+                    .iter()
+                    .find(|i| i.logical_name == interface_name)
+                    .and_then(|i| i.mac_address.as_ref())
+                else {
+                    warn!("Binding {filename} by interface name as '{interface_name}' has no MAC address");
+                    continue;
+                };
+
+                // Virtual interfaces (e.g. VLANs) commonly share the MAC address of their parent.
+                let shared = interfaces
+                    .iter()
+                    .filter(|i| i.interface_type == InterfaceType::Ethernet)
+                    .filter_map(|i| i.mac_address.as_ref())
+                    .filter(|mac| mac.eq_ignore_ascii_case(mac_address))
+                    .count()
+                    > 1;
+                if shared {
+                    warn!("Binding {filename} by interface name as its MAC address {mac_address} is not unique");
+                    continue;
+                }
+
+                *content = keyfile::set(content, "ethernet", "mac-address", mac_address);
+                *content = keyfile::remove(content, "connection", "interface-name");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate the `dns` entries of the `[ipv4]` and `[ipv6]` sections of all connection files.
+///
+/// Malformed addresses are rejected while duplicate servers are returned as advisory findings.
+fn validate_dns_servers(config: &NetworkConfig) -> anyhow::Result<Vec<String>> {
+    let mut findings = Vec::new();
+
+    for (filename, content) in config {
+        let mut c = Ini::new_cs();
+        // Lists are separated by ';' in keyfiles, which must not be treated as a comment.
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+        for section in ["ipv4", "ipv6"] {
+            let Some(servers) = c.get(section, "dns") else {
+                continue;
+            };
+
+            let mut seen = HashSet::new();
+            for server in servers
+                .split([';', ','])
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+            {
+                // IPv6 servers may be scoped to an interface, e.g. 'fe80::1%eth0'.
+                let address = server
+                    .split_once('%')
+                    .map_or(server, |(address, _)| address);
+                let valid = match section {
+                    "ipv4" => address.parse::<Ipv4Addr>().is_ok(),
+                    _ => address.parse::<Ipv6Addr>().is_ok(),
+                };
+                if !valid {
+                    return Err(anyhow!(
+                        "Connection file {filename} contains malformed DNS server '{server}' in [{section}]"
+                    ));
+                }
+
+                if !seen.insert(address) {
+                    findings.push(format!(
+                        "Connection file {filename} lists DNS server {server} more than once in [{section}]"
+                    ));
+                }
+            }
+        }
+    }
+    findings.sort();
+
+    Ok(findings)
+}
+
+/// Whether the keyfile configures IPv6, i.e. it has an `[ipv6]` section which is neither disabled nor ignored.
+fn ipv6_enabled(content: &str) -> anyhow::Result<bool> {
+    let mut c = Ini::new_cs();
+    c.set_comment_symbols(&['#']);
+    c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+    Ok(c.sections().iter().any(|section| section == "ipv6")
+        && !matches!(
+            c.get("ipv6", "method").as_deref(),
+            Some("disabled" | "ignore")
+        ))
+}
+
+/// Keyfile settings referring to another connection (by id or UUID) or to a device by its name.
+const PARENT_REFERENCE_KEYS: [(&str, &str); 5] = [
+    ("connection", "controller"),
+    ("connection", "master"),
+    ("vlan", "parent"),
+    ("macvlan", "parent"),
+    ("vxlan", "parent"),
+];
+
+/// Ensure that the parent and controller references of all connection files resolve to
+/// the id, UUID or interface name of another connection within the same configuration.
+fn validate_parent_refs(config: &NetworkConfig) -> anyhow::Result<()> {
+    let mut keyfiles = Vec::new();
+    for (filename, content) in config {
+        if !filename.ends_with(".nmconnection") {
+            continue;
+        }
+
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+        keyfiles.push((filename, c));
+    }
+
+    let known: HashSet<String> = keyfiles
+        .iter()
+        .flat_map(|(_, c)| {
+            ["id", "uuid", "interface-name"]
+                .into_iter()
+                .filter_map(|key| c.get("connection", key))
+        })
+        .collect();
+
+    let mut dangling = Vec::new();
+    for (filename, c) in &keyfiles {
+        for (section, key) in PARENT_REFERENCE_KEYS {
+            if let Some(reference) = c.get(section, key).filter(|r| !known.contains(r)) {
+                dangling.push(format!("{filename} ({section}.{key}={reference})"));
+            }
+        }
+    }
+
+    if !dangling.is_empty() {
+        return Err(anyhow!(
+            "Connection files reference parent connections which are not part of the config: {}",
+            dangling.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Keyfile settings holding secrets, matched in any section unless qualified by one.
+const SECRET_KEYS: [(Option<&str>, &str); 14] = [
+    (None, "psk"),
+    (None, "password"),
+    (None, "pin"),
+    (None, "leap-password"),
+    (None, "wep-key0"),
+    (None, "wep-key1"),
+    (None, "wep-key2"),
+    (None, "wep-key3"),
+    (None, "private-key-password"),
+    (None, "phase2-private-key-password"),
+    (None, "preshared-key"),
+    (None, "mka-cak"),
+    // The 802-1x private key settings are paths to key files instead.
+    (Some("wireguard"), "private-key"),
+    (Some("vpn-secrets"), ""),
+];
+
+/// Report connection files storing secrets in plaintext, which are easily leaked
+/// e.g. by committing the generated output to version control.
+fn find_plaintext_secrets(config: &NetworkConfig) -> anyhow::Result<Option<String>> {
+    let mut secrets = Vec::new();
+    for (filename, content) in config {
+        if !filename.ends_with(".nmconnection") {
+            continue;
+        }
+
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        let map = c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+        let mut keys = Vec::new();
+        for (section, entries) in &map {
+            for (key, value) in entries {
+                let is_secret = SECRET_KEYS.iter().any(|(secret_section, secret_key)| {
+                    secret_section.is_none_or(|s| s == section)
+                        && (secret_key.is_empty() || secret_key == key)
+                });
+                if is_secret && value.as_deref().is_some_and(|v| !v.is_empty()) {
+                    keys.push(format!("{section}.{key}"));
+                }
+            }
+        }
+        keys.sort();
+
+        if !keys.is_empty() {
+            secrets.push(format!("{filename} ({})", keys.join(", ")));
+        }
+    }
+
+    Ok((!secrets.is_empty()).then(|| {
+        format!(
+            "Connection files contain plaintext secrets: {}; keep the output out of version control \
+             or provide the secrets separately (e.g. via an NM secret agent)",
+            secrets.join(", ")
+        )
+    }))
+}
+
+/// Report configurations with more connection files than `max_connections`,
+/// as large numbers of connections noticeably slow down the NetworkManager startup.
+fn check_connection_count(config: &NetworkConfig, max_connections: usize) -> Option<String> {
+    let count = config
+        .iter()
+        .filter(|(filename, _)| filename.ends_with(".nmconnection"))
+        .count();
+
+    (count > max_connections).then(|| {
+        format!(
+            "Configuration contains {count} connections exceeding the limit of {max_connections}"
+        )
+    })
+}
+
+/// Keyfile section of WireGuard settings, whose private key NetworkManager only accepts inline.
+pub(crate) const WIREGUARD_SECTION: &str = "wireguard";
+
+/// Settings referring to certificates and keys stored in external files as `(section, key)` pairs.
+const REFERENCED_FILE_KEYS: [(&str, &str); 8] = [
+    ("802-1x", "ca-cert"),
+    ("802-1x", "client-cert"),
+    ("802-1x", "private-key"),
+    ("802-1x", "phase2-ca-cert"),
+    ("802-1x", "phase2-client-cert"),
+    ("802-1x", "phase2-private-key"),
+    ("vpn", "secret"),
+    (WIREGUARD_SECTION, "private-key"),
+];
+
+/// List the `(section, key, path)` triples of the external files referenced by a keyfile.
+///
+/// Certificates embedded as blobs (`data:` values) are skipped and `file://` prefixes are removed.
+/// WireGuard private keys are usually given inline and only taken as file references if prefixed by `file://`.
+pub(crate) fn referenced_files(
+    content: &str,
+) -> anyhow::Result<Vec<(&'static str, &'static str, String)>> {
+    let mut c = Ini::new_cs();
+    c.set_comment_symbols(&['#']);
+    c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+    Ok(REFERENCED_FILE_KEYS
+        .into_iter()
+        .filter_map(|(section, key)| {
+            let value = c.get(section, key)?;
+            let path = match value.strip_prefix("file://") {
+                Some(path) => path,
+                None if section == WIREGUARD_SECTION => return None,
+                None => &value,
+            };
+            (!path.is_empty() && !path.starts_with("data:"))
+                .then(|| (section, key, path.to_owned()))
+        })
+        .collect())
+}
+
+/// Ensure that the relative certificate/key paths referenced by the connection files
+/// exist within `config_dir`, i.e. next to the nmstate file they were generated from.
+///
+/// Absolute paths are expected to be provisioned on the target host separately and not checked.
+pub(crate) fn validate_referenced_files(
+    config: &NetworkConfig,
+    config_dir: &Path,
+) -> anyhow::Result<()> {
+    let mut missing = Vec::new();
+
+    for (filename, content) in config {
+        if !filename.ends_with(".nmconnection") {
+            continue;
+        }
+
+        for (section, key, path) in referenced_files(content)? {
+            if Path::new(&path).is_absolute() {
+                debug!(
+                    "Connection file {filename} references {path} ({section}.{key}) on the target host"
+                );
+                continue;
+            }
+
+            let path = config_dir.join(path);
+            if !path.is_file() {
+                missing.push(path.display().to_string());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Referenced files are missing from the config dir: {}",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Detect ports of the same bond/bridge with contradicting `[ethtool]` pause (flow control) settings.
+fn validate_flow_control(config: &NetworkConfig) -> anyhow::Result<Vec<String>> {
+    // controller -> [(connection file, pause key, value)]
+    let mut settings: HashMap<String, Vec<(&str, &str, bool)>> = HashMap::new();
+
+    for (filename, content) in config {
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+        let Some(controller) = c
+            .get("connection", "controller")
+            .or_else(|| c.get("connection", "master"))
+        else {
+            continue;
+        };
+
+        for key in ["pause-rx", "pause-tx"] {
+            if let Some(value) = c.getbool("ethtool", key).map_err(|e| anyhow!(e))? {
+                settings
+                    .entry(controller.clone())
+                    .or_default()
+                    .push((filename, key, value));
+            }
+        }
+    }
+
+    let mut findings = Vec::new();
+    for (controller, settings) in settings {
+        for (filename, key, _) in settings.iter().filter(|(_, _, value)| !value) {
+            let peers: Vec<&str> = settings
+                .iter()
+                .filter(|(_, k, value)| k == key && *value)
+                .map(|(f, _, _)| *f)
+                .collect();
+
+            if !peers.is_empty() {
+                findings.push(format!(
+                    "Connection file {filename} disables '{key}' while other ports of {controller} enable it: {}",
+                    peers.join(", ")
+                ));
+            }
+        }
+    }
+    findings.sort();
+
+    Ok(findings)
+}
+
+fn populate_connection_ids(
+    interfaces: &mut [Interface],
+    config: &NetworkConfig,
+) -> anyhow::Result<()> {
+    let keyfiles = config
+        .iter()
+        .map(|(_, content)| {
+            let mut c = Ini::new_cs();
+            c.set_comment_symbols(&['#']);
+            c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+            Ok(c)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for (index, (filename, _)) in config.iter().enumerate() {
+        let c = &keyfiles[index];
+
+        if c.get("connection", "type").is_some_and(|t| t == "loopback") {
+            continue;
+        }
+
+        let interface_name =
+            resolve_interface_name(index, &keyfiles, interfaces, &mut HashSet::new()).ok_or_else(
+                || anyhow!("No interface-name found in connection file: {}", filename),
+            )?;
+        let connection_id = c
+            .get("connection", "id")
+            .ok_or_else(|| anyhow!("No connection id found in connection file: {}", filename))?;
+        interfaces
+            .iter_mut()
+            .find(|x| x.logical_name == interface_name)
+            .ok_or_else(|| ConfigError::UnmatchedConnectionFile(filename.to_owned()))?
+            .connection_ids
+            .push(connection_id);
+    }
+
+    Ok(())
+}
+
+/// Resolve the interface a connection file belongs to.
+///
+/// Connection files lacking `interface-name` (e.g. OVS ports) are attributed by following their links:
+/// an OVS port to the interface attached to it, any other connection to its controller.
+fn resolve_interface_name(
+    index: usize,
+    keyfiles: &[Ini],
+    interfaces: &[Interface],
+    visited: &mut HashSet<usize>,
+) -> Option<String> {
+    // Guard against cyclic references which would otherwise recurse indefinitely.
+    if !visited.insert(index) {
+        return None;
+    }
+
+    let c = &keyfiles[index];
+    if let Some(interface_name) = c.get("connection", "interface-name") {
+        return Some(interface_name);
+    }
+
+    let references = |c: &Ini, reference: &str| {
+        ["id", "uuid", "interface-name"]
+            .iter()
+            .any(|key| c.get("connection", key).as_deref() == Some(reference))
+    };
+    let controller = |c: &Ini| {
+        c.get("connection", "controller")
+            .or_else(|| c.get("connection", "master"))
+    };
+
+    if c.get("connection", "type").as_deref() == Some("ovs-port") {
+        let attached = keyfiles.iter().enumerate().find(|(_, other)| {
+            other.get("connection", "slave-type").as_deref() == Some("ovs-port")
+                && controller(other).is_some_and(|reference| references(c, &reference))
+        });
+        if let Some((attached, _)) = attached {
+            return resolve_interface_name(attached, keyfiles, interfaces, visited);
+        }
+    }
+
+    let reference = controller(c)?;
+    match keyfiles
+        .iter()
+        .position(|other| references(other, &reference))
+    {
+        Some(controller) => resolve_interface_name(controller, keyfiles, interfaces, visited),
+        None => interfaces
+            .iter()
+            .any(|i| i.logical_name == reference)
+            .then_some(reference),
+    }
+}
+
+/// Reject interfaces of a type unknown to nmstate (including omitted ones) which it otherwise accepts.
+fn validate_interface_types(network_state: &NetworkState) -> anyhow::Result<()> {
+    let unknown: Vec<&str> = network_state
+        .interfaces
+        .iter()
+        .filter(|i| {
+            matches!(
+                i.iface_type(),
+                nmstate::InterfaceType::Unknown | nmstate::InterfaceType::Other(_)
+            )
+        })
+        .map(|i| i.name())
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "Detected interfaces of unknown type: {}",
+            unknown.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extract all interfaces except loopback ones.
+///
+/// Customized loopback interfaces are reported as they would otherwise be silently discarded,
+/// assigning routable addresses to them is rejected.
+fn extract_interfaces(network_state: &NetworkState) -> anyhow::Result<Vec<Interface>> {
+    let mut interfaces = Vec::new();
+
+    for i in network_state.interfaces.iter() {
+        if i.iface_type() == nmstate::InterfaceType::Loopback {
+            if let Some(warning) = check_loopback(i)? {
+                warn!("{warning}");
+            }
+            continue;
+        }
+
+        interfaces.push(Interface {
+            logical_name: i.name().to_owned(),
+            mac_address: i.base_iface().mac_address.clone(),
+            interface_type: i.iface_type().into(),
+            connection_ids: Vec::new(),
+            exclude_from_match: false,
+            unmanaged: i.is_ignore(),
+            member_mac_addresses: Vec::new(),
+        });
+    }
+
+    // Bonds carry the MAC addresses of their ports as their own one is not stable.
+    for i in network_state.interfaces.iter() {
+        if i.iface_type() != nmstate::InterfaceType::Bond {
+            continue;
+        }
+
+        let member_mac_addresses: Vec<String> = i
+            .ports()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|port| {
+                interfaces
+                    .iter()
+                    .find(|interface| interface.logical_name == port)
+                    .and_then(|interface| interface.mac_address.clone())
+            })
+            .collect();
+
+        if let Some(bond) = interfaces.iter_mut().find(|x| x.logical_name == i.name()) {
+            bond.member_mac_addresses = member_mac_addresses;
+        }
+    }
+
+    Ok(interfaces)
+}
+
+/// Detect loopback addresses other than the default `127.0.0.1/8` and `::1/128`.
+fn check_loopback(interface: &nmstate::Interface) -> anyhow::Result<Option<String>> {
+    let base = interface.base_iface();
+    let addresses = base
+        .ipv4
+        .as_ref()
+        .and_then(|ip| ip.addresses.as_ref())
+        .into_iter()
+        .chain(base.ipv6.as_ref().and_then(|ip| ip.addresses.as_ref()))
+        .flatten();
+
+    let mut routable = Vec::new();
+    let mut custom = Vec::new();
+    for address in addresses {
+        let formatted = format!("{}/{}", address.ip, address.prefix_length);
+        if formatted == "127.0.0.1/8" || formatted == "::1/128" {
+            continue;
+        }
+
+        let is_routable = match address.ip {
+            IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_link_local() && !ip.is_unspecified(),
+            IpAddr::V6(ip) => {
+                !ip.is_loopback() && !ip.is_unicast_link_local() && !ip.is_unspecified()
+            }
+        };
+        if is_routable {
+            routable.push(formatted);
+        } else {
+            custom.push(formatted);
+        }
+    }
+
+    if !routable.is_empty() {
+        return Err(anyhow!(
+            "Loopback interface {} is assigned routable addresses which would not be applied: {}",
+            interface.name(),
+            routable.join(", ")
+        ));
+    }
+
+    if custom.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(format!(
+        "Loopback interface {} has custom addresses which are not applied: {}",
+        interface.name(),
+        custom.join(", ")
+    )))
+}
+
+/// Serialize every non-loopback interface as a standalone nmstate document
+/// stored as `fragments/<name>.<type>.yaml` (e.g. for reuse in other tools).
+fn extract_fragments(network_state: &NetworkState) -> anyhow::Result<NetworkConfig> {
+    network_state
+        .interfaces
+        .iter()
+        .filter(|i| i.iface_type() != nmstate::InterfaceType::Loopback)
+        .map(|i| {
+            let filename = format!("{FRAGMENTS_DIR}/{}.{}.yaml", i.name(), i.iface_type());
+            let fragment = HashMap::from([("interfaces", vec![i])]);
+            let content = serde_yaml::to_string(&fragment)
+                .with_context(|| format!("Serializing fragment of interface '{}'", i.name()))?;
+
+            Ok((filename, content))
+        })
+        .collect()
+}
+
+/// Map each interface to the interfaces it depends on, i.e. its ports (bonds, bridges)
+/// and its parent (VLANs).
+fn interface_dependencies(network_state: &NetworkState) -> HashMap<String, Vec<String>> {
+    network_state
+        .interfaces
+        .iter()
+        .map(|i| {
+            let mut dependencies: Vec<String> = i
+                .ports()
+                .unwrap_or_default()
+                .into_iter()
+                .map(str::to_owned)
+                .collect();
+
+            if let nmstate::Interface::Vlan(vlan) = i {
+                if let Some(parent) = vlan.vlan.as_ref().and_then(|v| v.base_iface.as_ref()) {
+                    dependencies.push(parent.to_owned());
+                }
+            }
+
+            (i.name().to_owned(), dependencies)
+        })
+        .collect()
+}
+
+/// Detect virtual interfaces (e.g. bridges without ports) which are not connected to any Ethernet interface
+/// through the port and VLAN parent relations and therefore can never carry traffic.
+///
+/// References to interfaces which are not part of the config are assumed to exist on the host.
+fn find_isolated_interfaces(
+    network_state: &NetworkState,
+    dependencies: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    let interfaces: HashMap<&str, &nmstate::Interface> = network_state
+        .interfaces
+        .iter()
+        .filter(|i| i.base_iface().state != nmstate::InterfaceState::Absent)
+        .map(|i| (i.name(), i))
+        .collect();
+
+    // The relations are followed in both directions, e.g. from a bridge to its ports and vice versa.
+    let mut neighbours: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, references) in dependencies {
+        for reference in references {
+            neighbours.entry(name).or_default().push(reference);
+            neighbours.entry(reference).or_default().push(name);
+        }
+    }
+
+    let mut reachable: HashSet<&str> = HashSet::new();
+    let mut queue: Vec<&str> = neighbours
+        .keys()
+        .copied()
+        .filter(|name| {
+            interfaces
+                .get(name)
+                .is_none_or(|i| i.iface_type() == nmstate::InterfaceType::Ethernet)
+        })
+        .chain(
+            interfaces
+                .values()
+                .filter(|i| i.iface_type() == nmstate::InterfaceType::Ethernet)
+                .map(|i| i.name()),
+        )
+        .collect();
+    while let Some(name) = queue.pop() {
+        if reachable.insert(name) {
+            queue.extend(neighbours.get(name).into_iter().flatten());
+        }
+    }
+
+    let mut isolated: Vec<&str> = interfaces
+        .values()
+        .filter(|i| {
+            matches!(
+                i.iface_type(),
+                nmstate::InterfaceType::Bond
+                    | nmstate::InterfaceType::LinuxBridge
+                    | nmstate::InterfaceType::OvsBridge
+                    | nmstate::InterfaceType::OvsInterface
+                    | nmstate::InterfaceType::Vlan
+            )
+        })
+        .map(|i| i.name())
+        .filter(|name| !reachable.contains(name))
+        .collect();
+    isolated.sort();
+
+    isolated
+        .into_iter()
+        .map(|name| format!("Interface {name} is not connected to any Ethernet interface"))
+        .collect()
+}
+
+/// Sort the interfaces by their depth in the dependency graph and alphabetically within the same depth.
+fn sort_by_dependencies(interfaces: &mut [Interface], dependencies: &HashMap<String, Vec<String>>) {
+    fn depth(
+        name: &str,
+        dependencies: &HashMap<String, Vec<String>>,
+        visiting: &mut HashSet<String>,
+    ) -> usize {
+        // Guard against cyclic references which would otherwise recurse indefinitely.
+        if !visiting.insert(name.to_owned()) {
+            return 0;
+        }
+
+        let depth = dependencies
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|dependency| depth(dependency, dependencies, visiting) + 1)
+            .max()
+            .unwrap_or_default();
+
+        visiting.remove(name);
+        depth
+    }
+
+    interfaces.sort_by_cached_key(|i| {
+        (
+            depth(&i.logical_name, dependencies, &mut HashSet::new()),
+            i.logical_name.clone(),
+        )
+    });
+}
+
+fn validate_interfaces(
+    interfaces: &[Interface],
+    require_mac_addresses: bool,
+) -> anyhow::Result<()> {
+    let ethernet_interfaces: Vec<&Interface> = interfaces
+        .iter()
+        .filter(|i| i.interface_type == InterfaceType::Ethernet)
+        .collect();
+
+    if ethernet_interfaces.is_empty() {
+        return Err(ConfigError::MissingEthernet.into());
+    }
+
+    if !require_mac_addresses {
+        return Ok(());
+    }
+
+    let ethernet_interfaces: Vec<String> = ethernet_interfaces
+        .iter()
+        .filter(|i| i.mac_address.is_none())
+        .map(|i| i.logical_name.to_owned())
+        .collect();
+
+    if !ethernet_interfaces.is_empty() {
+        return Err(anyhow!(
+            "Detected Ethernet interfaces without a MAC address: {}",
+            ethernet_interfaces.join(", ")
+        ));
+    };
+
+    Ok(())
+}
+
+fn store_network_config(
+    output_dir: &str,
+    hostname: &str,
+    config: NetworkConfig,
+    mode: Option<u32>,
+) -> anyhow::Result<()> {
+    let path = Path::new(output_dir).join(hostname);
+
+    fs::create_dir_all(&path).context("Creating output dir")?;
+
+    config.iter().try_for_each(|(filename, content)| {
+        let path = path.join(filename);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Creating output subdir")?;
+        }
+
+        // Create the file with the mode right away as its content may include secrets.
+        let mut open_options = fs::OpenOptions::new();
+        open_options.create(true).truncate(true).write(true);
+        if let Some(mode) = mode {
+            open_options.mode(mode);
+        }
+        let mut file = open_options.open(&path).context("Creating config file")?;
+
+        // The mode is otherwise subject to the umask and not applied to existing files.
+        if let Some(mode) = mode {
+            file.set_permissions(fs::Permissions::from_mode(mode))
+                .context("Setting config file mode")?;
+        }
+
+        file.write_all(content.as_bytes())
+            .context("Writing config file")
+    })
+}
+
+/// Parse a file mode given in octal notation (e.g. `600` or `0o600`).
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid octal file mode '{value}'")),
+    }
+}
+
+/// Render a shell script activating all connections of the given (dependency ordered) interfaces.
+fn activation_script(interfaces: &[Interface]) -> String {
+    let mut script = String::from("#!/bin/sh\n# Generated by nmc\nset -e\n\n");
+
+    interfaces
+        .iter()
+        .flat_map(|i| &i.connection_ids)
+        .for_each(|id| {
+            script.push_str(&format!(
+                "nmcli connection up '{}'\n",
+                id.replace('\'', r"'\''")
+            ))
+        });
+
+    script
+}
+
+fn store_hostname_dispatcher(output_dir: &str) -> anyhow::Result<()> {
+    fs::create_dir_all(output_dir).context("Creating output dir")?;
+
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o755)
+        .open(Path::new(output_dir).join(HOSTNAME_DISPATCHER_FILE))
+        .context("Creating script file")?
+        .write_all(HOSTNAME_DISPATCHER_SCRIPT.as_bytes())
+        .context("Writing script file")
+}
+
+/// Render the interface hierarchy as a Graphviz DOT digraph
+/// with edges pointing from every interface to the ones built on top of it
+/// (e.g. Ethernet -> bond -> VLAN -> bridge).
+fn topology_dot(
+    hostname: &str,
+    interfaces: &[Interface],
+    config: &NetworkConfig,
+) -> anyhow::Result<String> {
+    let quote = |value: &str| format!("\"{}\"", value.replace('"', "\\\""));
+
+    let mut dot = format!("digraph {} {{\n    rankdir=LR;\n", quote(hostname));
+
+    for interface in interfaces {
+        dot.push_str(&format!(
+            "    {} [label={}];\n",
+            quote(&interface.logical_name),
+            quote(&format!(
+                "{}\\n({})",
+                interface.logical_name, interface.interface_type
+            ))
+        ));
+    }
+
+    let mut edges = Vec::new();
+    for (_, content) in config {
+        let mut c = Ini::new_cs();
+        c.set_comment_symbols(&['#']);
+        c.read(content.to_string()).map_err(|e| anyhow!(e))?;
+
+        let Some(name) = c
+            .get("connection", "interface-name")
+            .or_else(|| c.get("connection", "id"))
+        else {
+            continue;
+        };
+
+        if let Some(controller) = c
+            .get("connection", "controller")
+            .or_else(|| c.get("connection", "master"))
+        {
+            edges.push((name.clone(), controller));
+        }
+        if let Some(parent) = c.get("vlan", "parent") {
+            edges.push((parent, name));
+        }
+    }
+    edges.sort();
+    edges.dedup();
+
+    for (from, to) in edges {
+        dot.push_str(&format!("    {} -> {};\n", quote(&from), quote(&to)));
+    }
+    dot.push_str("}\n");
+
+    Ok(dot)
+}
+
+fn store_activation_script(
+    output_dir: &str,
+    hostname: &str,
+    interfaces: &[Interface],
+) -> anyhow::Result<()> {
+    let path = Path::new(output_dir).join(hostname);
+
+    fs::create_dir_all(&path).context("Creating output dir")?;
+
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o755)
+        .open(path.join(ACTIVATION_SCRIPT_FILE))
+        .context("Creating script file")?
+        .write_all(activation_script(interfaces).as_bytes())
+        .context("Writing script file")
+}
+
+/// Read the existing mapping without the entries of the given hosts, which are about to be regenerated.
+fn retained_network_mapping(output_dir: &str, hostnames: &[&str]) -> anyhow::Result<Vec<Host>> {
+    let path = mapping_file_path(Path::new(output_dir));
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut hosts = read_mapping(&path)?;
+    hosts.retain(|h| !hostnames.contains(&h.hostname.as_str()));
+
+    Ok(hosts)
+}
+
+/// Order the hosts by hostname and their interfaces by logical name,
+/// so that the mapping does not depend on the order the input files were read in.
+fn sort_network_mapping(hosts: &mut [Host]) {
+    hosts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+    for host in hosts {
+        host.interfaces
+            .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+    }
+}
+
+/// Write the mapping of all hosts at once, atomically replacing an existing one
+/// by renaming a temporary file within the output dir into place.
+///
+/// A mapping previously stored in the other format is removed as it would take precedence otherwise.
+fn store_network_mapping(
+    output_dir: &str,
+    mut hosts: Vec<Host>,
+    format: MappingFormat,
+) -> anyhow::Result<()> {
+    sort_network_mapping(&mut hosts);
+
+    fs::create_dir_all(output_dir).context("Creating output dir")?;
+    let mut file = tempfile::Builder::new()
+        .prefix(".host_config")
+        .tempfile_in(output_dir)
+        .context("Creating temporary mapping file")?;
+    file.write_all(format.serialize(&hosts)?.as_bytes())
+        .context("Writing mapping file")?;
+    file.as_file()
+        .set_permissions(fs::Permissions::from_mode(0o644))
+        .context("Setting mapping file permissions")?;
+    file.persist(Path::new(output_dir).join(format.filename()))
+        .context("Replacing mapping file")?;
+
+    for other in [HOST_MAPPING_FILE, HOST_MAPPING_JSON_FILE] {
+        if other != format.filename() {
+            let path = Path::new(output_dir).join(other);
+            if path.exists() {
+                fs::remove_file(&path).context(format!("Removing {path:?}"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive;
+    use crate::error::ConfigError;
+    use crate::generate_conf::{
+        activation_script, apply_order, bind_connections, check_connection_count, check_loopback,
+        extract_hostname, extract_interfaces, find_config_files, find_isolated_interfaces,
+        find_network_configs, find_plaintext_secrets, generate, generate_archive, generate_config,
+        generate_in_memory, interface_dependencies, mac_set_hash, mapping_file_path,
+        override_hostname, parse_backend, parse_connection_override, parse_mapping_format,
+        parse_mode, parse_nmstate_strictness, parse_override, parse_vlan_range,
+        populate_connection_ids, post_process_config, prepare_document, read_mapping,
+        referenced_files, substitute_env_vars, topology_dot, validate_connection_id_pattern,
+        validate_connection_ids, validate_dns_servers, validate_flow_control, validate_interfaces,
+        validate_parent_refs, validate_referenced_files, validate_vlan_ids, Backend, BindBy,
+        GenerateOptions, KeyfileOverride, MappingFormat, NmstateStrictness, Rollout,
+        APPLY_ORDER_FILE, FRAGMENTS_DIR, STDIN_CONFIG_DIR,
+    };
+    use crate::keyfile;
+    use crate::merge_conf::MergePolicy;
+    use crate::timing::Timings;
+    use crate::types::{Host, Interface, InterfaceType};
+    use crate::{HOSTNAME_DISPATCHER_FILE, HOST_MAPPING_FILE, HOST_MAPPING_JSON_FILE};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn generate_sorted_mapping_deterministically() -> Result<(), anyhow::Error> {
+        let out_dir = "_sorted-mapping";
+        let mapping_path = Path::new(out_dir).join(HOST_MAPPING_FILE);
+
+        generate(
+            "testdata/generate/config",
+            out_dir,
+            &GenerateOptions::default(),
+        )?;
+        let first = fs::read(&mapping_path)?;
+        generate(
+            "testdata/generate/config",
+            out_dir,
+            &GenerateOptions::default(),
+        )?;
+        assert_eq!(fs::read(&mapping_path)?, first);
+
+        let hosts: Vec<Host> = serde_yaml::from_slice(&first)?;
+        assert!(hosts.windows(2).all(|w| w[0].hostname < w[1].hostname));
+        for host in &hosts {
+            assert!(host
+                .interfaces
+                .windows(2)
+                .all(|w| w[0].logical_name <= w[1].logical_name));
+        }
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_replaces_existing_mapping() -> Result<(), anyhow::Error> {
+        let out_dir = "_replaced-mapping";
+        let mapping_path = Path::new(out_dir).join(HOST_MAPPING_FILE);
+
+        for _ in 0..2 {
+            generate(
+                "testdata/generate/config",
+                out_dir,
+                &GenerateOptions::default(),
+            )?;
+        }
+
+        let hosts: Vec<Host> = serde_yaml::from_str(&fs::read_to_string(&mapping_path)?)?;
+        assert_eq!(
+            hosts
+                .iter()
+                .map(|h| h.hostname.as_str())
+                .collect::<Vec<_>>(),
+            vec!["node1"]
+        );
+
+        // No temporary files are left behind.
+        assert!(fs::read_dir(out_dir)?
+            .filter_map(Result::ok)
+            .all(|entry| !entry.file_name().to_string_lossy().starts_with('.')));
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_json_mapping_round_trips() -> Result<(), anyhow::Error> {
+        let out_dir = "_json-mapping";
+
+        generate(
+            "testdata/generate/config",
+            out_dir,
+            &GenerateOptions::default(),
+        )?;
+        let yaml_hosts = read_mapping(&mapping_file_path(Path::new(out_dir)))?;
+
+        let options = GenerateOptions {
+            mapping_format: MappingFormat::Json,
+            ..Default::default()
+        };
+        generate("testdata/generate/config", out_dir, &options)?;
+
+        // The previous YAML mapping is replaced.
+        let path = mapping_file_path(Path::new(out_dir));
+        assert_eq!(path, Path::new(out_dir).join(HOST_MAPPING_JSON_FILE));
+        assert!(!Path::new(out_dir).join(HOST_MAPPING_FILE).exists());
+        assert_eq!(read_mapping(&path)?, yaml_hosts);
+
+        assert_eq!(parse_mapping_format("json"), Ok(MappingFormat::Json));
+        assert!(parse_mapping_format("toml").is_err());
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_successfully() -> Result<(), anyhow::Error> {
+        let config_dir = "testdata/generate/config";
+        let exp_output_path = Path::new("testdata/generate/expected");
+        let out_dir = "_out";
+        let output_path = Path::new("_out").join("node1");
+
+        generate(config_dir, out_dir, &GenerateOptions::default())?;
+
+        // verify contents of lo.nmconnection files
+        let exp_lo_conn = fs::read_to_string(exp_output_path.join("lo.nmconnection"))?;
+        let lo_conn = fs::read_to_string(output_path.join("lo.nmconnection"))?;
+
+        assert_eq!(exp_lo_conn, lo_conn);
+
+        // verify contents of the host mapping file
+        let mut exp_hosts: Vec<Host> = serde_yaml::from_str(
+            fs::read_to_string(exp_output_path.join(HOST_MAPPING_FILE))?.as_str(),
+        )?;
+        let mut hosts: Vec<Host> = serde_yaml::from_str(
+            fs::read_to_string(Path::new(out_dir).join(HOST_MAPPING_FILE))?.as_str(),
+        )?;
+
+        assert_eq!(exp_hosts.len(), hosts.len());
+
+        exp_hosts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        hosts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+
+        for (h1, h2) in exp_hosts.iter_mut().zip(hosts.iter_mut()) {
+            h1.interfaces
+                .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+            h2.interfaces
+                .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+        }
+
+        assert_eq!(exp_hosts, hosts);
+
+        // verify contents of *.nmconnection files based on interface.connection_ids
+        hosts
+            .iter_mut()
+            .flat_map(|h| h.interfaces.iter())
+            .flat_map(|interface| &interface.connection_ids)
+            .for_each(|conn_id| {
+                let exp_conn =
+                    fs::read_to_string(exp_output_path.join(format!("{conn_id}.nmconnection")))
+                        .unwrap();
+                let conn = fs::read_to_string(output_path.join(format!("{conn_id}.nmconnection")))
+                    .unwrap();
+                assert_eq!(exp_conn, conn);
+            });
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_emits_hostname_dispatcher() -> Result<(), anyhow::Error> {
+        let out_dir = "_dispatcher-gen-out";
+        let options = GenerateOptions {
+            emit_hostname_dispatcher: true,
+            ..Default::default()
+        };
+
+        generate("testdata/generate/config", out_dir, &options)?;
+
+        let path = Path::new(out_dir).join(HOSTNAME_DISPATCHER_FILE);
+        assert!(fs::read_to_string(&path)?.starts_with("#!/bin/sh\n"));
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o755);
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_selected_hosts_only() -> Result<(), anyhow::Error> {
+        let config_dir = "_hosts-config";
+        let out_dir = "_hosts-out";
+
+        fs::create_dir_all(config_dir)?;
+        for hostname in ["node1", "node2", "node3"] {
+            fs::copy(
+                "testdata/generate/config/node1.yaml",
+                Path::new(config_dir).join(format!("{hostname}.yaml")),
+            )?;
+        }
+
+        generate(config_dir, out_dir, &GenerateOptions::default())?;
+
+        let marker = Path::new(out_dir).join("node2").join("marker");
+        fs::write(&marker, "")?;
+        fs::remove_dir_all(Path::new(out_dir).join("node1"))?;
+
+        let options = GenerateOptions {
+            hosts: vec!["node1".to_string()],
+            ..Default::default()
+        };
+        generate(config_dir, out_dir, &options)?;
+
+        assert!(marker.exists());
+        assert!(Path::new(out_dir)
+            .join("node1")
+            .join("eth0.nmconnection")
+            .exists());
+
+        let mut hostnames: Vec<String> = serde_yaml::from_str::<Vec<Host>>(&fs::read_to_string(
+            Path::new(out_dir).join(HOST_MAPPING_FILE),
+        )?)?
+        .into_iter()
+        .map(|h| h.hostname)
+        .collect();
+        hostnames.sort();
+        assert_eq!(hostnames, vec!["node1", "node2", "node3"]);
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_archive_unpacks_to_output_tree() -> Result<(), anyhow::Error> {
+        let mut buffer = Vec::new();
+        generate_archive(
+            "testdata/generate/config",
+            &mut buffer,
+            &GenerateOptions::default(),
+        )?;
+
+        let dir = archive::extract(buffer.as_slice())?;
+        let mut files = find_config_files(dir.path())?
+            .into_iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let out_dir = "_archive-out";
+        generate(
+            "testdata/generate/config",
+            out_dir,
+            &GenerateOptions::default(),
+        )?;
+        let expected = find_config_files(Path::new(out_dir))?
+            .into_iter()
+            .map(|path| path.strip_prefix(out_dir).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(files, expected);
+
+        for file in &files {
+            assert_eq!(
+                fs::read_to_string(dir.path().join(file))?,
+                fs::read_to_string(Path::new(out_dir).join(file))?
+            );
+        }
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_with_output_mode() -> Result<(), anyhow::Error> {
+        let out_dir = "_mode-out";
+        let options = GenerateOptions {
+            output_mode: Some(0o600),
+            ..Default::default()
+        };
+
+        // Existing files are restricted as well.
+        let existing = Path::new(out_dir).join("node1").join("eth0.nmconnection");
+        fs::create_dir_all(existing.parent().unwrap())?;
+        fs::write(&existing, "")?;
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o644))?;
+
+        generate("testdata/generate/config", out_dir, &options)?;
+
+        for entry in fs::read_dir(Path::new(out_dir).join("node1"))? {
+            assert_eq!(entry?.metadata()?.permissions().mode() & 0o7777, 0o600);
+        }
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_emits_fragments() -> Result<(), anyhow::Error> {
+        let out_dir = "_fragments-out";
+        let options = GenerateOptions {
+            emit_fragments: true,
+            ..Default::default()
+        };
+
+        generate("testdata/generate/config", out_dir, &options)?;
+
+        let fragments_dir = Path::new(out_dir).join("node1").join(FRAGMENTS_DIR);
+        let mut fragments = fs::read_dir(&fragments_dir)?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        fragments.sort();
+
+        assert_eq!(
+            fragments,
+            vec![
+                "br1.ovs-bridge.yaml",
+                "bridge0.linux-bridge.yaml",
+                "eth0.ethernet.yaml",
+                "eth1.ethernet.yaml",
+                "ovs0.ovs-interface.yaml",
+            ]
+        );
+
+        let fragment: nmstate::NetworkState = serde_yaml::from_str(&fs::read_to_string(
+            fragments_dir.join("eth0.ethernet.yaml"),
+        )?)?;
+        assert_eq!(fragment.interfaces.iter().count(), 1);
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_octal_mode() {
+        assert_eq!(parse_mode("600"), Ok(0o600));
+        assert_eq!(parse_mode("0o640"), Ok(0o640));
+        assert!(parse_mode("800").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn generate_in_memory_successfully() -> Result<(), anyhow::Error> {
+        let exp_output_path = Path::new("testdata/generate/expected");
+
+        let mut timings = Timings::default();
+        let mut hosts = generate_in_memory(
+            "testdata/generate/config",
+            &GenerateOptions::default(),
+            &mut timings,
+        )?;
+        assert_eq!(hosts.len(), 1);
+
+        let summary = timings.summary();
+        assert!(summary.contains("read node1: "));
+        assert!(summary.contains("gen_conf node1: "));
+
+        let (host, config) = &mut hosts[0];
+        host.interfaces
+            .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+
+        let mut exp_hosts: Vec<Host> = serde_yaml::from_str(
+            fs::read_to_string(exp_output_path.join(HOST_MAPPING_FILE))?.as_str(),
+        )?;
+        exp_hosts[0]
+            .interfaces
+            .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+
+        assert_eq!(exp_hosts[0], *host);
+
+        for (filename, content) in config {
+            assert_eq!(
+                fs::read_to_string(exp_output_path.join(filename))?,
+                *content
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_in_memory_through_library() -> Result<(), anyhow::Error> {
+        let archive_path = Path::new("_library-config.tar.gz");
+        let entries = vec![(
+            "node1.yaml".to_string(),
+            fs::read_to_string("testdata/generate/config/node1.yaml")?,
+            0o644,
+        )];
+        archive::write_archive(fs::File::create(archive_path)?, &entries)?;
+
+        for config_dir in ["testdata/generate/config", archive_path.to_str().unwrap()] {
+            let hosts: Vec<(Host, crate::NetworkConfig)> =
+                crate::generate_in_memory(config_dir, &GenerateOptions::default())?;
+            assert_eq!(hosts.len(), 1);
+
+            let (host, config) = &hosts[0];
+            assert_eq!(host.hostname, "node1");
+            assert!(config
+                .iter()
+                .any(|(filename, _)| filename == "eth0.nmconnection"));
+        }
+
+        let error =
+            crate::generate_in_memory("_missing-config", &GenerateOptions::default()).unwrap_err();
+        assert!(matches!(error, ConfigError::Other(_)));
+
+        // cleanup
+        fs::remove_file(archive_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_in_memory_detects_unified_config() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_unified-config");
+        fs::create_dir_all(config_dir)?;
+        let generate = || {
+            generate_in_memory(
+                config_dir.to_str().unwrap(),
+                &GenerateOptions::default(),
+                &mut Timings::default(),
+            )
+        };
+
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            config_dir.join("node1.yaml"),
+        )?;
+        let hosts = generate()?;
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].0.hostname, "node1");
+
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            config_dir.join("_all.yaml"),
+        )?;
+        assert_eq!(
+            generate().unwrap_err().to_string(),
+            "Unified config \"_unified-config/_all.yaml\" cannot be combined with per-host configs: \
+             _unified-config/node1.yaml"
+        );
+
+        fs::remove_file(config_dir.join("node1.yaml"))?;
+        let hosts = generate()?;
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].0.hostname, "_all");
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_in_memory_from_nested_dirs() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_nested-config");
+        fs::create_dir_all(config_dir.join("rack1"))?;
+        fs::create_dir_all(config_dir.join("rack2/row1"))?;
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            config_dir.join("rack1/node5.yaml"),
+        )?;
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            config_dir.join("rack2/row1/node6.yaml"),
+        )?;
+
+        let hosts = generate_in_memory(
+            config_dir.to_str().unwrap(),
+            &GenerateOptions::default(),
+            &mut Timings::default(),
+        )?;
+        let hostnames: Vec<&str> = hosts.iter().map(|(h, _)| h.hostname.as_str()).collect();
+        assert_eq!(hostnames, vec!["node5", "node6"]);
+
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            config_dir.join("rack2/node5.yaml"),
+        )?;
+        let error = generate_in_memory(
+            config_dir.to_str().unwrap(),
+            &GenerateOptions::default(),
+            &mut Timings::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Duplicate hostname 'node5': \"_nested-config/rack1/node5.yaml\" and \"_nested-config/rack2/node5.yaml\""
+        );
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_mapping_with_mac_set_hash() -> Result<(), anyhow::Error> {
+        let output_dir = "_mac-set-hash-out";
+        let options = GenerateOptions {
+            mac_set_hash_salt: Some("s3cr3t".to_string()),
+            ..Default::default()
+        };
+        generate("testdata/generate/config", output_dir, &options)?;
+
+        let mapping = read_mapping(&Path::new(output_dir).join(HOST_MAPPING_FILE))?;
+        let host = &mapping[0];
+        assert_eq!(
+            host.mac_set_hash,
+            Some(mac_set_hash(
+                "s3cr3t",
+                &[
+                    "FE:C4:05:42:8B:AA",
+                    "0E:4D:C6:B8:C4:72",
+                    "00:00:00:00:00:00",
+                    "5c:c7:c9:5e:fb:ec"
+                ]
+            ))
+        );
+        assert!(host.interfaces.iter().all(|i| i.mac_address.is_none()));
+
+        let contents = fs::read_to_string(Path::new(output_dir).join(HOST_MAPPING_FILE))?;
+        assert!(!contents.to_lowercase().contains("fe:c4:05:42:8b:aa"));
+
+        // cleanup
+        fs::remove_dir_all(output_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn emit_apply_order_by_priority() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_apply-order-config");
+        let output_dir = "_apply-order-out";
+        fs::create_dir_all(config_dir)?;
+
+        let desired_state = fs::read_to_string("testdata/generate/config/node1.yaml")?;
+        for (hostname, rollout) in [
+            ("cp1", "rollout:\n  group: control-plane\n  priority: 10\n"),
+            ("worker2", "rollout:\n  group: workers\n"),
+            ("cp2", "rollout:\n  group: control-plane\n  priority: 10\n"),
+            ("worker1", ""),
+            ("canary", "rollout:\n  priority: -1\n"),
+        ] {
+            fs::write(
+                config_dir.join(format!("{hostname}.yaml")),
+                format!("{rollout}{desired_state}"),
+            )?;
+        }
+
+        let options = GenerateOptions {
+            emit_apply_order: true,
+            ..Default::default()
+        };
+        generate(config_dir.to_str().unwrap(), output_dir, &options)?;
+
+        assert_eq!(
+            fs::read_to_string(Path::new(output_dir).join(APPLY_ORDER_FILE))?,
+            "canary\nworker1\nworker2\ncp1\ncp2\n"
+        );
+
+        // Groups with interleaved priorities are kept together, ordered by their lowest priority.
+        let host = |hostname: &str, group: &str, priority: i32| Host {
+            hostname: hostname.to_string(),
+            mac_set_hash: None,
+            serial: None,
+            group: Some(group.to_string()),
+            priority: Some(priority),
+            interfaces: vec![],
+        };
+        assert_eq!(
+            apply_order(&[
+                host("w3", "workers", 3),
+                host("cp2", "control-plane", 2),
+                host("w1", "workers", 1),
+                host("cp0", "control-plane", 0),
+            ]),
+            "cp0\ncp2\nw1\nw3\n"
+        );
+        assert_eq!(
+            apply_order(&[
+                host("w3", "workers", 3),
+                host("cp2", "control-plane", 2),
+                host("w1", "workers", 1),
+            ]),
+            "w1\nw3\ncp2\n"
+        );
+
+        let mapping = read_mapping(&Path::new(output_dir).join(HOST_MAPPING_FILE))?;
+        let cp1 = mapping.iter().find(|h| h.hostname == "cp1").unwrap();
+        assert_eq!(cp1.group.as_deref(), Some("control-plane"));
+        assert_eq!(cp1.priority, Some(10));
+
+        fs::write(
+            config_dir.join("worker1.yaml"),
+            format!("rollout:\n  order: 1\n{desired_state}"),
+        )?;
+        let error = generate(config_dir.to_str().unwrap(), output_dir, &options).unwrap_err();
+        assert_eq!(error.to_string(), "Parsing rollout section");
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+        fs::remove_dir_all(output_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_hosts_in_parallel_preserves_order() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_parallel-config");
+        fs::create_dir_all(config_dir)?;
+        for hostname in ["node3", "node1", "node4", "node2"] {
+            fs::copy(
+                "testdata/generate/config/node1.yaml",
+                config_dir.join(format!("{hostname}.yaml")),
+            )?;
+        }
+
+        let generate = |jobs| {
+            generate_in_memory(
+                config_dir.to_str().unwrap(),
+                &GenerateOptions {
+                    jobs: Some(jobs),
+                    ..Default::default()
+                },
+                &mut Timings::default(),
+            )
+        };
+
+        let sequential = generate(1)?;
+        let parallel = generate(4)?;
+
+        let hostnames: Vec<&str> = parallel.iter().map(|(h, _)| h.hostname.as_str()).collect();
+        assert_eq!(hostnames, vec!["node1", "node2", "node3", "node4"]);
+        assert_eq!(sequential, parallel);
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_from_stdin_requires_hostname() {
+        let error = generate_in_memory(
+            STDIN_CONFIG_DIR,
+            &GenerateOptions::default(),
+            &mut Timings::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Reading config from stdin requires a hostname"
+        );
+    }
+
+    #[test]
+    fn generate_fails_due_to_empty_dir() {
+        fs::create_dir_all("empty").unwrap();
+
+        let error = generate("empty", "_out", &GenerateOptions::default()).unwrap_err();
+        assert!(matches!(error, ConfigError::EmptyConfigDir));
+
+        fs::remove_dir_all("empty").unwrap();
+    }
+
+    #[test]
+    fn generate_fails_due_to_missing_path() {
+        let error = generate("<missing>", "_out", &GenerateOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("No such file or directory"))
+    }
+
+    #[test]
+    fn generate_config_fails_due_to_invalid_data() {
+        let err = generate_config(
+            "test.yaml",
+            "<invalid>".to_string(),
+            false,
+            &GenerateOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Failed generating config for test.yaml");
+        assert!(format!("{err:#}").contains("Invalid YAML string"))
+    }
+
+    #[test]
+    fn generate_config_fails_due_to_invalid_bond_option() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: bond0
+            type: bond
+            state: up
+            link-aggregation:
+              mode: active-backup
+              options:
+                miimon: fast
+                lacp_rate: fast
+              port:
+                - eth0
+        "#;
+
+        let err = generate_config(
+            "node3.yaml",
+            data.to_string(),
+            false,
+            &GenerateOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed generating config for node3.yaml (interface bond0)"
+        );
+        assert!(format!("{err:#}").contains("invalid digit found in string"));
+    }
+
+    #[test]
+    fn check_customized_loopback() {
+        let loopback = |addresses: &str| {
+            let data = format!(
+                "interfaces:\n  - name: lo\n    type: loopback\n    state: up\n    \
+                 ipv4:\n      enabled: true\n      address:\n{addresses}"
+            );
+            let network_state = nmstate::NetworkState::new_from_yaml(&data).unwrap();
+            let result = check_loopback(network_state.interfaces.iter().next().unwrap());
+            result
+        };
+
+        assert_eq!(
+            loopback("        - ip: 127.0.0.1\n          prefix-length: 8\n").unwrap(),
+            None
+        );
+        assert_eq!(
+            loopback("        - ip: 127.0.0.1\n          prefix-length: 8\n        - ip: 127.0.0.2\n          prefix-length: 8\n")
+                .unwrap(),
+            Some("Loopback interface lo has custom addresses which are not applied: 127.0.0.2/8".to_string())
+        );
+        assert_eq!(
+            loopback("        - ip: 10.0.0.1\n          prefix-length: 32\n")
+                .unwrap_err()
+                .to_string(),
+            "Loopback interface lo is assigned routable addresses which would not be applied: 10.0.0.1/32"
+        );
+    }
+
+    #[test]
+    fn extract_interfaces_skips_loopback() -> Result<(), serde_yaml::Error> {
+        let net_state: nmstate::NetworkState = serde_yaml::from_str(
+            r#"---
+        interfaces:
+          - name: eth1
+            type: ethernet
+            mac-address: FE:C4:05:42:8B:AA
+          - name: bridge0
+            type: linux-bridge
+            mac-address: FE:C4:05:42:8B:AB
+          - name: lo
+            type: loopback
+            mac-address: 00:00:00:00:00:00
+        "#,
+        )?;
+
+        let config_files = vec![
+            generate_config_file("eth1".to_string(), "eth1".to_string()),
+            generate_config_file("bridge0".to_string(), "bridge0".to_string()),
+        ];
+
+        let mut interfaces = extract_interfaces(&net_state).expect("extract interfaces");
+        populate_connection_ids(&mut interfaces, &config_files).expect("populate ids");
+        interfaces.sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+
+        assert_eq!(
+            interfaces,
+            vec![
+                Interface {
+                    logical_name: "bridge0".to_string(),
+                    mac_address: Option::from("FE:C4:05:42:8B:AB".to_string()),
+                    interface_type: InterfaceType::LinuxBridge,
+                    connection_ids: vec!["bridge0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth1".to_string(),
+                    mac_address: Option::from("FE:C4:05:42:8B:AA".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth1".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn activation_script_orders_bridge_over_bond() {
+        let data = r#"---
+        interfaces:
+          - name: br0
+            type: linux-bridge
+            state: up
+            bridge:
+              port:
+                - name: bond0
+          - name: bond0
+            type: bond
+            state: up
+            link-aggregation:
+              mode: active-backup
+              port:
+                - eth1
+                - eth0
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+          - name: eth1
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AB
+        "#;
+
+        let (interfaces, _) = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            activation_script(&interfaces),
+            "#!/bin/sh\n# Generated by nmc\nset -e\n\n\
+             nmcli connection up 'eth0'\n\
+             nmcli connection up 'eth1'\n\
+             nmcli connection up 'bond0'\n\
+             nmcli connection up 'br0'\n"
+        );
+    }
+
+    #[test]
+    fn generate_config_sets_stable_id() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth0.1365
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth0
+              id: 1365
+        "#;
+        let options = GenerateOptions {
+            stable_id: Some("${CONNECTION}/${BOOT}".to_string()),
+            ..Default::default()
+        };
+
+        let (_, config) = generate_config("test.yaml", data.to_string(), false, &options).unwrap();
+
+        assert_eq!(config.len(), 2);
+        for (_, content) in config {
+            let mut keyfile = configparser::ini::Ini::new();
+            keyfile.read(content).unwrap();
+            assert_eq!(
+                keyfile.get("connection", "stable-id"),
+                Some("${CONNECTION}/${BOOT}".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn generate_config_sets_autoconnect() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth0.1365
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth0
+              id: 1365
+        "#;
+        let options = GenerateOptions {
+            autoconnect: Some(false),
+            ..Default::default()
+        };
+
+        let (_, config) = generate_config("test.yaml", data.to_string(), false, &options).unwrap();
+
+        assert_eq!(config.len(), 2);
+        for (_, content) in config {
+            let mut keyfile = configparser::ini::Ini::new();
+            keyfile.read(content).unwrap();
+            assert!(keyfile.get("connection", "autoconnect").is_some());
+        }
+
+        // Only connections not specifying it are affected.
+        let mut config = vec![
+            (
+                "eth1.nmconnection".to_string(),
+                "[connection]\nid=eth1\n".to_string(),
+            ),
+            (
+                "eth2.nmconnection".to_string(),
+                "[connection]\nid=eth2\nautoconnect=true\n".to_string(),
+            ),
+        ];
+        post_process_config(&mut config, &options).unwrap();
+        assert!(config[0].1.contains("autoconnect=false\n"));
+        assert!(config[1].1.contains("autoconnect=true\n"));
+    }
+
+    #[test]
+    fn generate_config_sets_ipv6_privacy() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            ipv6:
+              enabled: true
+              dhcp: true
+              autoconf: true
+          - name: eth1
+            type: ethernet
+            state: up
+            ipv6:
+              enabled: false
+        "#;
+        let options = GenerateOptions {
+            ipv6_privacy: Some(2),
+            ..Default::default()
+        };
+
+        let (_, config) = generate_config("test.yaml", data.to_string(), false, &options).unwrap();
+
+        let ip6_privacy = |filename: &str| {
+            let (_, content) = config.iter().find(|(f, _)| f == filename).unwrap();
+            let mut keyfile = configparser::ini::Ini::new();
+            keyfile.read(content.clone()).unwrap();
+            keyfile.get("ipv6", "ip6-privacy")
+        };
+        assert_eq!(ip6_privacy("eth0.nmconnection"), Some("2".to_string()));
+        assert_eq!(ip6_privacy("eth1.nmconnection"), None);
+
+        // Connections specifying it are not affected.
+        let mut config = vec![(
+            "eth2.nmconnection".to_string(),
+            "[connection]\nid=eth2\n\n[ipv6]\nmethod=auto\nip6-privacy=0\n".to_string(),
+        )];
+        post_process_config(&mut config, &options).unwrap();
+        assert!(config[0].1.contains("ip6-privacy=0\n"));
+        assert!(!config[0].1.contains("ip6-privacy=2"));
+    }
+
+    #[test]
+    fn generate_config_applies_overrides() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth1
+            type: ethernet
+            state: up
+        "#;
+        let options = GenerateOptions {
+            overrides: vec![
+                parse_override("connection.zone=trusted").unwrap(),
+                parse_connection_override("eth1:connection.zone=public").unwrap(),
+                parse_connection_override("eth1:connection.metered=1").unwrap(),
+            ],
+            ..Default::default()
+        };
+
+        let (_, config) = generate_config("test.yaml", data.to_string(), false, &options).unwrap();
+
+        for (filename, content) in config {
+            let mut keyfile = configparser::ini::Ini::new();
+            keyfile.read(content).unwrap();
+
+            let (zone, metered) = match filename.as_str() {
+                "eth0.nmconnection" => ("trusted", None),
+                "eth1.nmconnection" => ("public", Some("1".to_string())),
+                _ => panic!("unexpected connection file {filename}"),
+            };
+            assert_eq!(keyfile.get("connection", "zone"), Some(zone.to_string()));
+            assert_eq!(keyfile.get("connection", "metered"), metered);
+        }
+    }
+
+    #[test]
+    fn parse_keyfile_overrides() {
+        assert_eq!(
+            parse_override("wireguard-peer.abc.allowed-ips=10.0.0.0/8"),
+            Ok(KeyfileOverride {
+                connection_id: None,
+                section: "wireguard-peer.abc".to_string(),
+                key: "allowed-ips".to_string(),
+                value: "10.0.0.0/8".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_connection_override("bond0:ipv4.dns=1.1.1.1;").unwrap(),
+            KeyfileOverride {
+                connection_id: Some("bond0".to_string()),
+                section: "ipv4".to_string(),
+                key: "dns".to_string(),
+                value: "1.1.1.1;".to_string(),
+            }
+        );
+        assert!(parse_override("zone=trusted").is_err());
+        assert!(parse_connection_override("connection.zone=trusted").is_err());
+    }
+
+    #[test]
+    fn generate_config_substitutes_env_vars() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            ipv4:
+              enabled: true
+              address:
+                - ip: ${NMC_TEST_IP}
+                  prefix-length: 24
+        "#;
+        let lookup = |name: &str| (name == "NMC_TEST_IP").then(|| "10.0.0.5".to_string());
+
+        let substituted = substitute_env_vars(data, false, lookup).unwrap();
+        assert!(substituted.contains("- ip: 10.0.0.5"));
+
+        let data = data.replace("NMC_TEST_IP", "NMC_TEST_UNDEFINED");
+        assert_eq!(
+            substitute_env_vars(&data, false, lookup)
+                .unwrap_err()
+                .to_string(),
+            "Detected undefined environment variables: NMC_TEST_UNDEFINED"
+        );
+        assert_eq!(substitute_env_vars(&data, true, lookup).unwrap(), data);
+
+        let options = GenerateOptions {
+            env_substitute: true,
+            ..Default::default()
+        };
+        let error = generate_config("test.yaml", data, false, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Detected undefined environment variables: NMC_TEST_UNDEFINED"
+        );
+    }
+
+    #[test]
+    fn prepare_document_substitutes_rollout() -> Result<(), anyhow::Error> {
+        let data = "rollout:\n  group: ${NMC_TEST_GROUP}\n  priority: ${NMC_TEST_PRIORITY}\n\
+                    interfaces: []\n";
+        let lookup = |name: &str| match name {
+            "NMC_TEST_GROUP" => Some("workers".to_string()),
+            "NMC_TEST_PRIORITY" => Some("5".to_string()),
+            _ => None,
+        };
+
+        let options = GenerateOptions {
+            env_substitute: true,
+            ..Default::default()
+        };
+        let (data_without_rollout, rollout) = prepare_document(data.to_string(), &options, lookup)?;
+        assert_eq!(
+            rollout,
+            Rollout {
+                group: Some("workers".to_string()),
+                priority: Some(5),
+            }
+        );
+        assert_eq!(data_without_rollout, "interfaces: []\n");
+
+        // References are only resolved if requested.
+        let error =
+            prepare_document(data.to_string(), &GenerateOptions::default(), lookup).unwrap_err();
+        assert_eq!(error.to_string(), "Parsing rollout section");
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_inconsistent_flow_control() {
+        let data = r#"---
+        interfaces:
+          - name: bond0
+            type: bond
+            state: up
+            link-aggregation:
+              mode: active-backup
+              port:
+                - eth0
+                - eth1
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+            ethtool:
+              pause:
+                autoneg: false
+                rx: true
+                tx: true
+          - name: eth1
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AB
+            ethtool:
+              pause:
+                autoneg: false
+                rx: false
+                tx: true
+        "#;
+
+        let (_, config) = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            validate_flow_control(&config).unwrap(),
+            vec!["Connection file eth1.nmconnection disables 'pause-rx' while other ports of bond0 enable it: eth0.nmconnection"]
+        );
+
+        let options = GenerateOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error = generate_config("test.yaml", data.to_string(), true, &options).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Detected advisory findings in strict mode"));
+
+        // Controller ids containing ';' must not be cut off as if it started a comment.
+        let port = |filename: &str, controller: &str, pause_rx: bool| {
+            (
+                filename.to_string(),
+                format!(
+                    "[connection]\ncontroller={controller}\n\n[ethtool]\npause-rx={pause_rx}\n"
+                ),
+            )
+        };
+        let config = vec![
+            port("eth0.nmconnection", "uplink;a", true),
+            port("eth1.nmconnection", "uplink;b", false),
+        ];
+        assert!(validate_flow_control(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extract_interfaces_records_bond_member_macs() -> Result<(), serde_yaml::Error> {
+        let net_state: nmstate::NetworkState = serde_yaml::from_str(
+            r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            mac-address: FE:C4:05:42:8B:AA
+          - name: eth1
+            type: ethernet
+            mac-address: FE:C4:05:42:8B:AB
+          - name: bond0
+            type: bond
+            link-aggregation:
+              mode: active-backup
+              port:
+                - eth0
+                - eth1
+        "#,
+        )?;
+
+        let interfaces = extract_interfaces(&net_state).expect("extract interfaces");
+        let bond = interfaces
+            .iter()
+            .find(|i| i.logical_name == "bond0")
+            .unwrap();
+        assert_eq!(
+            bond.member_mac_addresses,
+            vec!["FE:C4:05:42:8B:AA", "FE:C4:05:42:8B:AB"]
+        );
+        assert!(interfaces
+            .iter()
+            .filter(|i| i.logical_name != "bond0")
+            .all(|i| i.member_mac_addresses.is_empty()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_cloud_init_config() -> Result<(), anyhow::Error> {
+        let out_dir = "_cloud-init-out";
+        let options = GenerateOptions {
+            backend: Backend::CloudInit,
+            ..Default::default()
+        };
+
+        generate("testdata/cloud-init/config", out_dir, &options)?;
+
+        assert_eq!(
+            fs::read_to_string(Path::new(out_dir).join("node1").join("network-config.yaml"))?,
+            fs::read_to_string("testdata/cloud-init/expected/node1/network-config.yaml")?
+        );
+        assert_eq!(fs::read_dir(Path::new(out_dir).join("node1"))?.count(), 1);
+        assert!(!Path::new(out_dir).join("host_config.yaml").exists());
+
+        assert_eq!(parse_backend("cloud-init"), Ok(Backend::CloudInit));
+        assert!(parse_backend("netplan").is_err());
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_config_with_nmstate_strictness() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth1
+            state: up
+        "#;
+
+        let options = GenerateOptions {
+            nmstate_strictness: NmstateStrictness::Permissive,
+            ..Default::default()
+        };
+        assert!(generate_config("test.yaml", data.to_string(), false, &options).is_ok());
+
+        let options = GenerateOptions {
+            nmstate_strictness: NmstateStrictness::Strict,
+            ..Default::default()
+        };
+        let error = generate_config("test.yaml", data.to_string(), false, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Detected interfaces of unknown type: eth1"
+        );
+
+        assert_eq!(
+            parse_nmstate_strictness("strict"),
+            Ok(NmstateStrictness::Strict)
+        );
+        assert!(parse_nmstate_strictness("lenient").is_err());
+    }
+
+    #[test]
+    fn generate_topology_diagram() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+          - name: eth1
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AB
+          - name: bond0
+            type: bond
+            state: up
+            link-aggregation:
+              mode: active-backup
+              port:
+                - eth0
+                - eth1
+          - name: bond0.10
+            type: vlan
+            state: up
+            vlan:
+              base-iface: bond0
+              id: 10
+          - name: br0
+            type: linux-bridge
+            state: up
+            bridge:
+              port:
+                - name: bond0.10
+        "#;
+
+        let (interfaces, config) = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+        let dot = topology_dot("node1", &interfaces, &config).unwrap();
+
+        assert!(dot.starts_with("digraph \"node1\" {\n"));
+        for (name, interface_type) in [
+            ("eth0", "ethernet"),
+            ("eth1", "ethernet"),
+            ("bond0", "bond"),
+            ("bond0.10", "vlan"),
+            ("br0", "linux-bridge"),
+        ] {
+            assert!(dot.contains(&format!(
+                "    \"{name}\" [label=\"{name}\\n({interface_type})\"];\n"
+            )));
+        }
+        for edge in [
+            "\"eth0\" -> \"bond0\"",
+            "\"eth1\" -> \"bond0\"",
+            "\"bond0\" -> \"bond0.10\"",
+            "\"bond0.10\" -> \"br0\"",
+        ] {
+            assert!(
+                dot.contains(&format!("    {edge};\n")),
+                "missing {edge}: {dot}"
+            );
+        }
+
+        // Connection ids may contain ';' which does not start a comment in keyfiles.
+        let config = vec![(
+            "port.nmconnection".to_string(),
+            "[connection]\nid=uplink;primary\ncontroller=bond0\n".to_string(),
+        )];
+        let dot = topology_dot("node1", &[], &config).unwrap();
+        assert!(dot.contains("    \"uplink;primary\" -> \"bond0\";\n"));
+    }
+
+    #[test]
+    fn generate_config_binds_by_name() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+        "#;
+        let options = GenerateOptions {
+            bind_by: Some(BindBy::Name),
+            ..Default::default()
+        };
+
+        let (_, mut config) =
+            generate_config("test.yaml", data.to_string(), true, &options).unwrap();
+        config[0].1 = keyfile::set(&config[0].1, "ethernet", "mac-address", "FE:C4:05:42:8B:AA");
+        bind_connections(&mut config, &[], BindBy::Name).unwrap();
+
+        assert!(config[0].1.contains("interface-name=eth0\n"));
+        assert!(!config[0].1.contains("\nmac-address="));
+    }
+
+    #[test]
+    fn generate_config_binds_by_mac() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+          - name: eth1
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AB
+          - name: eth2
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AC
+        "#;
+
+        let (mut interfaces, mut config) = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+        // Shared MAC addresses are rejected by `generate_config` but ambiguous nonetheless.
+        interfaces
+            .iter_mut()
+            .filter(|i| i.logical_name == "eth2")
+            .for_each(|i| i.mac_address = Some("FE:C4:05:42:8B:AB".to_string()));
+        bind_connections(&mut config, &interfaces, BindBy::Mac).unwrap();
+
+        let keyfile = |name: &str| {
+            let filename = format!("{name}.nmconnection");
+            config
+                .iter()
+                .find(|(f, _)| *f == filename)
+                .map(|(_, content)| content.as_str())
+                .unwrap()
+        };
+
+        assert!(keyfile("eth0").contains("\nmac-address=FE:C4:05:42:8B:AA\n"));
+        assert!(!keyfile("eth0").contains("interface-name="));
+
+        for name in ["eth1", "eth2"] {
+            assert!(keyfile(name).contains(&format!("interface-name={name}\n")));
+            assert!(!keyfile(name).contains("\nmac-address="));
+        }
+    }
+
+    #[test]
+    fn generate_config_rejects_duplicate_mac_addresses() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: 00:11:22:33:44:55
+          - name: eth1
+            type: ethernet
+            state: up
+            mac-address: 00:11:22:33:44:55
+        "#;
+
+        let error = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Detected interfaces sharing MAC addresses: 00:11:22:33:44:55 (eth0, eth1)"
+        );
+    }
+
+    #[test]
+    fn validate_vlan_id_out_of_range() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth0.5000
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth0
+              id: 5000
+        "#;
+
+        let error = generate_config(
+            "test.yaml",
+            data.to_string(),
+            false,
+            &GenerateOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Detected VLAN ids outside of 1-4094: eth0.5000 (5000)"
+        );
+    }
+
+    #[test]
+    fn validate_vlan_id_reserved() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: eth0.5
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth0
+              id: 5
+          - name: eth0.1365
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth0
+              id: 1365
+        "#;
+        let network_state = nmstate::NetworkState::new_from_yaml(data).unwrap();
+        let reserved = vec![parse_vlan_range("1-10").unwrap()];
+
+        assert_eq!(
+            validate_vlan_ids(&network_state, &reserved).unwrap(),
+            vec!["VLAN interface eth0.5 uses id 5 within the reserved range 1-10"]
+        );
+
+        let options = GenerateOptions {
+            strict: true,
+            reserved_vlans: reserved,
+            ..Default::default()
+        };
+        assert!(generate_config("test.yaml", data.to_string(), false, &options).is_err());
+    }
 
-    fs::create_dir_all(&path).context("Creating output dir")?;
+    #[test]
+    fn find_isolated_bridge() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+          - name: br0
+            type: linux-bridge
+            state: up
+            bridge:
+              port:
+                - name: eth0
+          - name: br1
+            type: linux-bridge
+            state: up
+            bridge:
+              port: []
+          - name: br1.10
+            type: vlan
+            state: up
+            vlan:
+              base-iface: br1
+              id: 10
+          - name: eth1.20
+            type: vlan
+            state: up
+            vlan:
+              base-iface: eth1
+              id: 20
+        "#;
+        let network_state = nmstate::NetworkState::new_from_yaml(data).unwrap();
 
-    config.iter().try_for_each(|(filename, content)| {
-        let path = path.join(filename);
+        // eth1 is not part of the config and therefore assumed to exist on the host.
+        assert_eq!(
+            find_isolated_interfaces(&network_state, &interface_dependencies(&network_state)),
+            vec![
+                "Interface br1 is not connected to any Ethernet interface",
+                "Interface br1.10 is not connected to any Ethernet interface"
+            ]
+        );
 
-        fs::write(path, content).context("Writing config file")
-    })
-}
+        let options = GenerateOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(generate_config("test.yaml", data.to_string(), false, &options).is_err());
+    }
 
-fn store_network_mapping(
-    output_dir: &str,
-    hostname: String,
-    interfaces: Vec<Interface>,
-) -> anyhow::Result<()> {
-    let path = Path::new(output_dir);
+    #[test]
+    fn parse_vlan_ranges() {
+        assert_eq!(parse_vlan_range("1-10"), Ok(1..=10));
+        assert_eq!(parse_vlan_range("100"), Ok(100..=100));
+        assert!(parse_vlan_range("10-1").is_err());
+        assert!(parse_vlan_range("a-b").is_err());
+    }
 
-    let mapping_file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path.join(HOST_MAPPING_FILE))?;
+    #[test]
+    fn validate_dns_servers_duplicates() {
+        let config = vec![(
+            "eth0.nmconnection".to_string(),
+            "[ipv4]\ndns=8.8.8.8;1.1.1.1;8.8.8.8;\n\n[ipv6]\ndns=2001:4860:4860::8888;\n"
+                .to_string(),
+        )];
 
-    let hosts = [Host {
-        hostname,
-        interfaces,
-    }];
+        assert_eq!(
+            validate_dns_servers(&config).unwrap(),
+            vec!["Connection file eth0.nmconnection lists DNS server 8.8.8.8 more than once in [ipv4]"]
+        );
+    }
 
-    serde_yaml::to_writer(mapping_file, &hosts).context("Writing mapping file")
-}
+    #[test]
+    fn validate_dns_servers_malformed() {
+        let config = vec![(
+            "eth0.nmconnection".to_string(),
+            "[ipv4]\ndns=8.8.8.8;8.8.8;\n".to_string(),
+        )];
 
-#[cfg(test)]
-mod tests {
-    use crate::generate_conf::{
-        extract_hostname, extract_interfaces, generate, generate_config, populate_connection_ids,
-        validate_connection_ids, validate_interfaces,
-    };
-    use crate::types::{Host, Interface};
-    use crate::HOST_MAPPING_FILE;
-    use std::fs;
-    use std::path::Path;
+        assert_eq!(
+            validate_dns_servers(&config).unwrap_err().to_string(),
+            "Connection file eth0.nmconnection contains malformed DNS server '8.8.8' in [ipv4]"
+        );
+
+        let config = vec![(
+            "eth0.nmconnection".to_string(),
+            "[ipv6]\ndns=8.8.8.8;\n".to_string(),
+        )];
+        assert!(validate_dns_servers(&config).is_err());
+    }
 
     #[test]
-    fn generate_successfully() -> Result<(), anyhow::Error> {
-        let config_dir = "testdata/generate";
-        let exp_output_path = Path::new("testdata/generate/expected");
-        let out_dir = "_out";
-        let output_path = Path::new("_out").join("node1");
+    fn find_network_configs_skips_auxiliary_files() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_network-configs");
+        fs::create_dir_all(config_dir.join("rack1"))?;
+        for file in ["node1.yaml", "node2", "rack1/node3.yml", "rack1/ca.pem"] {
+            fs::write(config_dir.join(file), "")?;
+        }
 
-        generate(config_dir, out_dir)?;
+        assert_eq!(
+            find_network_configs(config_dir)?,
+            vec![
+                config_dir.join("node1.yaml"),
+                config_dir.join("node2"),
+                config_dir.join("rack1/node3.yml")
+            ]
+        );
 
-        // verify contents of lo.nmconnection files
-        let exp_lo_conn = fs::read_to_string(exp_output_path.join("lo.nmconnection"))?;
-        let lo_conn = fs::read_to_string(output_path.join("lo.nmconnection"))?;
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
 
-        assert_eq!(exp_lo_conn, lo_conn);
+        Ok(())
+    }
 
-        // verify contents of the host mapping file
-        let mut exp_hosts: Vec<Host> = serde_yaml::from_str(
-            fs::read_to_string(exp_output_path.join(HOST_MAPPING_FILE))?.as_str(),
+    #[test]
+    fn override_hostname_of_file() {
+        assert_eq!(
+            override_hostname(Path::new("rack1/node1.override.yaml")),
+            Some("node1")
+        );
+        assert_eq!(override_hostname(Path::new("node1.yaml")), None);
+        assert_eq!(override_hostname(Path::new(".override.yaml")), None);
+    }
+
+    #[test]
+    fn generate_unified_config_with_overrides() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_override-config");
+        fs::create_dir_all(config_dir)?;
+        fs::write(
+            config_dir.join("_all.yaml"),
+            "interfaces:\n- name: eth0\n  type: ethernet\n  state: up\n  ipv4:\n    enabled: true\n    dhcp: true\n",
         )?;
-        let mut hosts: Vec<Host> = serde_yaml::from_str(
-            fs::read_to_string(Path::new(out_dir).join(HOST_MAPPING_FILE))?.as_str(),
+        fs::write(
+            config_dir.join("node1.override.yaml"),
+            "interfaces:\n- name: eth0\n  ipv4:\n    dhcp: false\n    address:\n    - ip: 192.168.0.5\n      prefix-length: 24\n",
         )?;
 
-        assert_eq!(exp_hosts.len(), hosts.len());
-
-        exp_hosts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
-        hosts.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        // The override redefines the DHCP setting of eth0.
+        let error = generate_in_memory(
+            config_dir.to_str().unwrap(),
+            &GenerateOptions::default(),
+            &mut Timings::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{error:#}").contains("Conflicting definitions of interface 'eth0'"));
 
-        for (h1, h2) in exp_hosts.iter_mut().zip(hosts.iter_mut()) {
-            h1.interfaces
-                .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
-            h2.interfaces
-                .sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
-        }
+        let options = GenerateOptions {
+            merge_policy: MergePolicy::DeepMerge,
+            ..Default::default()
+        };
+        let hosts = generate_in_memory(
+            config_dir.to_str().unwrap(),
+            &options,
+            &mut Timings::default(),
+        )?;
+        assert_eq!(
+            hosts
+                .iter()
+                .map(|(h, _)| h.hostname.as_str())
+                .collect::<Vec<_>>(),
+            vec!["_all", "node1"]
+        );
 
-        assert_eq!(exp_hosts, hosts);
+        let keyfile = |index: usize| {
+            let (_, config) = &hosts[index];
+            config
+                .iter()
+                .find(|(filename, _)| filename == "eth0.nmconnection")
+                .map(|(_, content)| content.clone())
+                .unwrap()
+        };
+        assert!(keyfile(0).contains("method=auto"));
+        assert!(keyfile(1).contains("address0=192.168.0.5/24"));
 
-        // verify contents of *.nmconnection files based on interface.connection_ids
-        hosts
-            .iter_mut()
-            .flat_map(|h| h.interfaces.iter())
-            .flat_map(|interface| &interface.connection_ids)
-            .for_each(|conn_id| {
-                let exp_conn =
-                    fs::read_to_string(exp_output_path.join(format!("{conn_id}.nmconnection")))
-                        .unwrap();
-                let conn = fs::read_to_string(output_path.join(format!("{conn_id}.nmconnection")))
-                    .unwrap();
-                assert_eq!(exp_conn, conn);
-            });
+        // Overrides cannot be used without a unified config.
+        fs::remove_file(config_dir.join("_all.yaml"))?;
+        let error = generate_in_memory(
+            config_dir.to_str().unwrap(),
+            &GenerateOptions::default(),
+            &mut Timings::default(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("requires a unified config"));
 
         // cleanup
-        fs::remove_dir_all(out_dir)?;
+        fs::remove_dir_all(config_dir)?;
 
         Ok(())
     }
 
     #[test]
-    fn generate_fails_due_to_empty_dir() {
-        fs::create_dir_all("empty").unwrap();
+    fn validate_parent_refs_of_keyfiles() {
+        let eth0 = (
+            "eth0.nmconnection".to_string(),
+            "[connection]\nid=eth0\ntype=ethernet\ninterface-name=eth0\n".to_string(),
+        );
+        let vlan = |parent: &str| {
+            (
+                "eth0.1365.nmconnection".to_string(),
+                format!(
+                    "[connection]\nid=eth0.1365\ntype=vlan\n\n[vlan]\nid=1365\nparent={parent}\n"
+                ),
+            )
+        };
 
-        let error = generate("empty", "_out").unwrap_err();
-        assert_eq!(error.to_string(), "Empty config directory");
+        assert!(validate_parent_refs(&vec![eth0.clone(), vlan("eth0")]).is_ok());
 
-        fs::remove_dir_all("empty").unwrap();
+        let error = validate_parent_refs(&vec![eth0, vlan("eth1")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Connection files reference parent connections which are not part of the config: \
+             eth0.1365.nmconnection (vlan.parent=eth1)"
+        );
     }
 
     #[test]
-    fn generate_fails_due_to_missing_path() {
-        let error = generate("<missing>", "_out").unwrap_err();
-        assert!(error.to_string().contains("No such file or directory"))
-    }
+    fn plaintext_secrets_of_keyfiles() {
+        let wifi = |psk: &str| {
+            (
+                "wlan0.nmconnection".to_string(),
+                format!(
+                    "[connection]\nid=wlan0\ntype=wifi\n\n[wifi-security]\nkey-mgmt=wpa-psk\npsk={psk}\n"
+                ),
+            )
+        };
+        let eap = (
+            "eth0.nmconnection".to_string(),
+            "[connection]\nid=eth0\ntype=ethernet\n\n[802-1x]\neap=tls;\nprivate-key=/etc/NetworkManager/certs/eth0.key\n"
+                .to_string(),
+        );
 
-    #[test]
-    fn generate_config_fails_due_to_invalid_data() {
-        let err = generate_config("<invalid>".to_string(), false).unwrap_err();
-        assert!(err.to_string().contains("Invalid YAML string"))
+        assert_eq!(
+            find_plaintext_secrets(&vec![eap.clone(), wifi("secret123")])
+                .unwrap()
+                .unwrap(),
+            "Connection files contain plaintext secrets: wlan0.nmconnection (wifi-security.psk); \
+             keep the output out of version control or provide the secrets separately (e.g. via an NM secret agent)"
+        );
+        assert_eq!(find_plaintext_secrets(&vec![eap, wifi("")]).unwrap(), None);
     }
 
     #[test]
-    fn extract_interfaces_skips_loopback() -> Result<(), serde_yaml::Error> {
-        let net_state: nmstate::NetworkState = serde_yaml::from_str(
-            r#"---
+    fn connection_count_exceeding_limit() {
+        let data = r#"---
         interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
           - name: eth1
             type: ethernet
-            mac-address: FE:C4:05:42:8B:AA
-          - name: bridge0
-            type: linux-bridge
-            mac-address: FE:C4:05:42:8B:AB
-          - name: lo
-            type: loopback
-            mac-address: 00:00:00:00:00:00
-        "#,
-        )?;
+            state: up
+          - name: eth2
+            type: ethernet
+            state: up
+        "#;
 
-        let config_files = vec![
-            generate_config_file("eth1".to_string(), "eth1".to_string()),
-            generate_config_file("bridge0".to_string(), "bridge0".to_string()),
-        ];
+        let mut options = GenerateOptions {
+            max_connections: Some(2),
+            ..Default::default()
+        };
+        let (_, config) = generate_config("test.yaml", data.to_string(), false, &options).unwrap();
+        assert_eq!(
+            check_connection_count(&config, 2).unwrap(),
+            "Configuration contains 3 connections exceeding the limit of 2"
+        );
+        assert_eq!(check_connection_count(&config, 3), None);
 
-        let mut interfaces = extract_interfaces(&net_state);
-        populate_connection_ids(&mut interfaces, &config_files).expect("populate ids");
-        interfaces.sort_by(|a, b| a.logical_name.cmp(&b.logical_name));
+        options.strict = true;
+        let error = generate_config("test.yaml", data.to_string(), false, &options).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Configuration contains 3 connections exceeding the limit of 2"));
+    }
+
+    #[test]
+    fn validate_referenced_files_in_config_dir() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_referenced-files");
+        fs::create_dir_all(config_dir)?;
+        fs::write(config_dir.join("ca.pem"), "")?;
+
+        let keyfile = |client_cert: &str| {
+            (
+                "eth0.nmconnection".to_string(),
+                format!(
+                    "[802-1x]\neap=tls;\nca-cert=ca.pem\nclient-cert={client_cert}\n\
+                     private-key=/etc/pki/client.key\n"
+                ),
+            )
+        };
 
         assert_eq!(
-            interfaces,
+            referenced_files(&keyfile("file://client.pem").1)?,
             vec![
-                Interface {
-                    logical_name: "bridge0".to_string(),
-                    mac_address: Option::from("FE:C4:05:42:8B:AB".to_string()),
-                    interface_type: "linux-bridge".to_string(),
-                    connection_ids: vec!["bridge0".to_string()],
-                },
-                Interface {
-                    logical_name: "eth1".to_string(),
-                    mac_address: Option::from("FE:C4:05:42:8B:AA".to_string()),
-                    interface_type: "ethernet".to_string(),
-                    connection_ids: vec!["eth1".to_string()],
-                },
+                ("802-1x", "ca-cert", "ca.pem".to_string()),
+                ("802-1x", "client-cert", "client.pem".to_string()),
+                ("802-1x", "private-key", "/etc/pki/client.key".to_string())
             ]
         );
 
+        let error =
+            validate_referenced_files(&vec![keyfile("client.pem")], config_dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Referenced files are missing from the config dir: _referenced-files/client.pem"
+        );
+
+        fs::write(config_dir.join("client.pem"), "")?;
+        validate_referenced_files(&vec![keyfile("client.pem")], config_dir)?;
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_referenced_vpn_secret_in_config_dir() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_referenced-vpn");
+        fs::create_dir_all(config_dir)?;
+
+        let config = vec![(
+            "vpn0.nmconnection".to_string(),
+            "[vpn]\nservice-type=org.freedesktop.NetworkManager.openvpn\nsecret=static.key\n"
+                .to_string(),
+        )];
+        assert_eq!(
+            referenced_files(&config[0].1)?,
+            vec![("vpn", "secret", "static.key".to_string())]
+        );
+
+        let error = validate_referenced_files(&config, config_dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Referenced files are missing from the config dir: _referenced-vpn/static.key"
+        );
+
+        fs::write(config_dir.join("static.key"), "")?;
+        validate_referenced_files(&config, config_dir)?;
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_referenced_wireguard_private_key_in_config_dir() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_referenced-wireguard");
+        fs::create_dir_all(config_dir)?;
+
+        let keyfile = |private_key: &str| {
+            vec![(
+                "wg0.nmconnection".to_string(),
+                format!("[wireguard]\nlisten-port=51820\nprivate-key={private_key}\n"),
+            )]
+        };
+
+        // Inline keys may contain slashes and are not mistaken for paths.
+        let inline = keyfile("yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk/");
+        assert!(referenced_files(&inline[0].1)?.is_empty());
+        validate_referenced_files(&inline, config_dir)?;
+
+        let referenced = keyfile("file://wg0.key");
+        assert_eq!(
+            referenced_files(&referenced[0].1)?,
+            vec![("wireguard", "private-key", "wg0.key".to_string())]
+        );
+
+        let error = validate_referenced_files(&referenced, config_dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Referenced files are missing from the config dir: _referenced-wireguard/wg0.key"
+        );
+
+        fs::write(config_dir.join("wg0.key"), "")?;
+        validate_referenced_files(&referenced, config_dir)?;
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
         Ok(())
     }
 
+    #[test]
+    fn populate_connection_ids_of_ovs_ports() {
+        let keyfile = |entries: &[(&str, &str)]| {
+            let mut config = configparser::ini::Ini::new();
+            for (key, value) in entries {
+                config.set("connection", key, Some(value.to_string()));
+            }
+            (format!("{}.nmconnection", entries[0].1), config.writes())
+        };
+        let interface = |name: &str, interface_type: &str| Interface {
+            logical_name: name.to_string(),
+            mac_address: None,
+            interface_type: interface_type.into(),
+            connection_ids: vec![],
+            exclude_from_match: false,
+            unmanaged: false,
+            member_mac_addresses: vec![],
+        };
+
+        let config = vec![
+            keyfile(&[
+                ("id", "br0-br"),
+                ("type", "ovs-bridge"),
+                ("interface-name", "br0"),
+            ]),
+            // Attached to by the Ethernet connection via its UUID.
+            keyfile(&[
+                ("id", "eth1-port"),
+                ("type", "ovs-port"),
+                ("uuid", "5e4ac41f-d8dd-5cbd-98b3-122629af91e0"),
+                ("master", "br0"),
+                ("slave-type", "ovs-bridge"),
+            ]),
+            keyfile(&[
+                ("id", "eth1"),
+                ("type", "802-3-ethernet"),
+                ("interface-name", "eth1"),
+                ("master", "5e4ac41f-d8dd-5cbd-98b3-122629af91e0"),
+                ("slave-type", "ovs-port"),
+            ]),
+            // Not attached to by any interface, attributed to its bridge instead.
+            keyfile(&[
+                ("id", "spare-port"),
+                ("type", "ovs-port"),
+                ("controller", "br0-br"),
+                ("slave-type", "ovs-bridge"),
+            ]),
+        ];
+        let mut interfaces = vec![
+            interface("br0", "ovs-bridge"),
+            interface("eth1", "ethernet"),
+        ];
+
+        populate_connection_ids(&mut interfaces, &config).unwrap();
+
+        assert_eq!(interfaces[0].connection_ids, vec!["br0-br", "spare-port"]);
+        assert_eq!(interfaces[1].connection_ids, vec!["eth1-port", "eth1"]);
+
+        // Connection files without any link to an interface are still rejected.
+        let config = vec![keyfile(&[("id", "orphan-port"), ("type", "ovs-port")])];
+        let error = populate_connection_ids(&mut interfaces, &config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No interface-name found in connection file: orphan-port.nmconnection"
+        );
+    }
+
     fn generate_config_file(logical_name: String, connection_id: String) -> (String, String) {
         let filename = format!("{connection_id}.nmconnection");
 
@@ -378,19 +4207,28 @@ mod tests {
             Interface {
                 logical_name: "eth3.1365".to_string(),
                 mac_address: None,
-                interface_type: "vlan".to_string(),
+                interface_type: InterfaceType::Vlan,
                 connection_ids: vec!["eth3.1365".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "bond0".to_string(),
                 mac_address: None,
-                interface_type: "bond".to_string(),
+                interface_type: InterfaceType::Bond,
                 connection_ids: vec!["bond0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
         ];
 
         let error = validate_interfaces(&interfaces, false).unwrap_err();
-        assert_eq!(error.to_string(), "No Ethernet interfaces were provided")
+        assert!(matches!(
+            error.downcast_ref::<ConfigError>(),
+            Some(ConfigError::MissingEthernet)
+        ))
     }
 
     #[test]
@@ -399,38 +4237,56 @@ mod tests {
             Interface {
                 logical_name: "eth0".to_string(),
                 mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth1".to_string(),
                 mac_address: None,
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth1".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth2".to_string(),
                 mac_address: Option::from("00:11:22:33:44:56".to_string()),
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth2".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth3".to_string(),
                 mac_address: None,
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth3".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth3.1365".to_string(),
                 mac_address: None,
-                interface_type: "vlan".to_string(),
+                interface_type: InterfaceType::Vlan,
                 connection_ids: vec!["eth3.1365".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "bond0".to_string(),
                 mac_address: Option::from("00:11:22:33:44:58".to_string()),
-                interface_type: "bond".to_string(),
+                interface_type: InterfaceType::Bond,
                 connection_ids: vec!["bond0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
         ];
 
@@ -450,20 +4306,29 @@ mod tests {
             Interface {
                 logical_name: "eth0".to_string(),
                 mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth0.1365".to_string(),
                 mac_address: None,
-                interface_type: "vlan".to_string(),
+                interface_type: InterfaceType::Vlan,
                 connection_ids: vec!["eth0.1365".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "bond0".to_string(),
                 mac_address: None,
-                interface_type: "bond".to_string(),
+                interface_type: InterfaceType::Bond,
                 connection_ids: Vec::new(),
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
         ];
 
@@ -475,26 +4340,130 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validate_connection_ids_naming_convention() {
+        let interfaces = vec![
+            Interface {
+                logical_name: "eth0".to_string(),
+                mac_address: Option::from("00:11:22:33:44:55".to_string()),
+                interface_type: InterfaceType::Ethernet,
+                connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            },
+            Interface {
+                logical_name: "eth0.1365".to_string(),
+                mac_address: None,
+                interface_type: InterfaceType::Vlan,
+                connection_ids: vec!["eth0.1365".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            },
+            Interface {
+                logical_name: "Bond0".to_string(),
+                mac_address: None,
+                interface_type: InterfaceType::Bond,
+                connection_ids: vec!["Bond0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
+            },
+        ];
+
+        let pattern = regex::Regex::new("^[a-z0-9-]+$").unwrap();
+        assert_eq!(
+            validate_connection_id_pattern(&interfaces, &pattern)
+                .unwrap_err()
+                .to_string(),
+            "Detected connection ids not matching the pattern '^[a-z0-9-]+$': eth0.1365, Bond0"
+        );
+
+        let pattern = regex::Regex::new("^[A-Za-z0-9.-]+$").unwrap();
+        assert!(validate_connection_id_pattern(&interfaces, &pattern).is_ok());
+    }
+
+    #[test]
+    fn generate_config_records_unmanaged_interfaces() {
+        let data = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mac-address: FE:C4:05:42:8B:AA
+          - name: eth1
+            type: ethernet
+            state: ignore
+            mac-address: FE:C4:05:42:8B:AB
+        "#;
+
+        let (interfaces, config) = generate_config(
+            "test.yaml",
+            data.to_string(),
+            true,
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>(),
+            vec!["eth0.nmconnection"]
+        );
+        assert_eq!(
+            interfaces,
+            vec![
+                Interface {
+                    logical_name: "eth0".to_string(),
+                    mac_address: Option::from("FE:C4:05:42:8B:AA".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: vec!["eth0".to_string()],
+                    exclude_from_match: false,
+                    unmanaged: false,
+                    member_mac_addresses: vec![],
+                },
+                Interface {
+                    logical_name: "eth1".to_string(),
+                    mac_address: Option::from("FE:C4:05:42:8B:AB".to_string()),
+                    interface_type: InterfaceType::Ethernet,
+                    connection_ids: Vec::new(),
+                    exclude_from_match: false,
+                    unmanaged: true,
+                    member_mac_addresses: vec![],
+                },
+            ]
+        );
+    }
+
     #[test]
     fn validate_interfaces_successfully() {
         let interfaces = vec![
             Interface {
                 logical_name: "eth0".to_string(),
                 mac_address: Option::from("00:11:22:33:44:55".to_string()),
-                interface_type: "ethernet".to_string(),
+                interface_type: InterfaceType::Ethernet,
                 connection_ids: vec!["eth0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "eth0.1365".to_string(),
                 mac_address: None,
-                interface_type: "vlan".to_string(),
+                interface_type: InterfaceType::Vlan,
                 connection_ids: vec!["eth0.1365".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
             Interface {
                 logical_name: "bond0".to_string(),
                 mac_address: None,
-                interface_type: "bond".to_string(),
+                interface_type: InterfaceType::Bond,
                 connection_ids: vec!["bond0".to_string()],
+                exclude_from_match: false,
+                unmanaged: false,
+                member_mac_addresses: vec![],
             },
         ];
 
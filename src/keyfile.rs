@@ -0,0 +1,129 @@
+//! Minimal line based editing of NetworkManager keyfiles.
+//!
+//! Unlike a full INI round-trip this preserves comments as well as the ordering of sections and keys.
+
+/// Set `key` in `section` to `value`, replacing an existing value in place.
+/// The section is appended to the keyfile if it does not exist yet.
+pub(crate) fn set(contents: &str, section: &str, key: &str, value: &str) -> String {
+    upsert(contents, section, key, value, true)
+}
+
+/// Set `key` in `section` to `value` unless the key is already present.
+/// The section is appended to the keyfile if it does not exist yet.
+pub(crate) fn set_default(contents: &str, section: &str, key: &str, value: &str) -> String {
+    upsert(contents, section, key, value, false)
+}
+
+/// Remove `key` from `section`, leaving the keyfile untouched if it is not present.
+pub(crate) fn remove(contents: &str, section: &str, key: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+
+    if let Some((start, end)) = section_range(&lines, section) {
+        if let Some(i) = (start + 1..end).find(|&i| key_of(&lines[i]) == Some(key)) {
+            lines.remove(i);
+        }
+    }
+
+    join(lines)
+}
+
+fn upsert(contents: &str, section: &str, key: &str, value: &str, overwrite: bool) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+    let entry = format!("{key}={value}");
+
+    let Some((start, end)) = section_range(&lines, section) else {
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(format!("[{section}]"));
+        lines.push(entry);
+        return join(lines);
+    };
+
+    if let Some(i) = (start + 1..end).find(|&i| key_of(&lines[i]) == Some(key)) {
+        if overwrite {
+            lines[i] = entry;
+        }
+        return join(lines);
+    }
+
+    // Insert after the last non-empty line of the section.
+    let position = (start + 1..end)
+        .rev()
+        .find(|&i| !lines[i].trim().is_empty())
+        .map_or(start + 1, |i| i + 1);
+    lines.insert(position, entry);
+
+    join(lines)
+}
+
+/// Return the line index of the section header and the (exclusive) end of its body.
+fn section_range(lines: &[String], section: &str) -> Option<(usize, usize)> {
+    let header = format!("[{section}]");
+    let start = lines.iter().position(|line| line.trim() == header)?;
+    let end = lines
+        .iter()
+        .skip(start + 1)
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+
+    Some((start, end))
+}
+
+fn key_of(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with(';') {
+        return None;
+    }
+
+    line.split_once('=').map(|(key, _)| key.trim())
+}
+
+fn join(lines: Vec<String>) -> String {
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyfile::{remove, set, set_default};
+
+    const KEYFILE: &str =
+        "# managed by nmc\n[connection]\nid=eth0\ntype=ethernet\n\n[ipv4]\nmethod=auto\n";
+
+    #[test]
+    fn set_default_keeps_existing_values() {
+        assert_eq!(set_default(KEYFILE, "connection", "id", "other"), KEYFILE);
+    }
+
+    #[test]
+    fn set_replaces_existing_values() {
+        assert_eq!(
+            set(KEYFILE, "connection", "id", "ens1f0"),
+            "# managed by nmc\n[connection]\nid=ens1f0\ntype=ethernet\n\n[ipv4]\nmethod=auto\n"
+        );
+    }
+
+    #[test]
+    fn remove_existing_keys_only() {
+        assert_eq!(
+            remove(KEYFILE, "connection", "type"),
+            "# managed by nmc\n[connection]\nid=eth0\n\n[ipv4]\nmethod=auto\n"
+        );
+        assert_eq!(remove(KEYFILE, "ipv4", "type"), KEYFILE);
+        assert_eq!(remove(KEYFILE, "ethernet", "mac-address"), KEYFILE);
+    }
+
+    #[test]
+    fn set_default_appends_keys_and_sections() {
+        let contents = set_default(KEYFILE, "connection", "stable-id", "${CONNECTION}");
+        let contents = set_default(&contents, "ethtool", "pause-rx", "true");
+
+        assert_eq!(
+            contents,
+            "# managed by nmc\n[connection]\nid=eth0\ntype=ethernet\nstable-id=${CONNECTION}\n\n\
+             [ipv4]\nmethod=auto\n\n[ethtool]\npause-rx=true\n"
+        );
+    }
+}
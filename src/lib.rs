@@ -0,0 +1,111 @@
+//! Generate NetworkManager configurations from nmstate desired states and apply them to a host.
+//!
+//! The `nmc` command line is a thin wrapper around the functions exposed here.
+
+mod apply_conf;
+mod archive;
+mod clean_conf;
+mod cloud_init;
+mod error;
+mod ethtool;
+mod generate_conf;
+mod keyfile;
+mod merge_conf;
+#[cfg(feature = "remote")]
+mod remote;
+mod state;
+mod timing;
+mod types;
+mod validate_conf;
+
+pub use apply_conf::{
+    parse_keyfile_owner, parse_mac_change_policy, parse_mac_match_mask, parse_nic_provider,
+    ApplyOptions, MacChangePolicy, NicProvider, UnifiedLayout, CERTS_DIR, CONFIG_DIR,
+    STATIC_SYSTEM_CONNECTIONS_DIR,
+};
+pub use clean_conf::CleanOptions;
+pub use error::ConfigError;
+pub use generate_conf::{
+    parse_backend, parse_bind_by, parse_connection_override, parse_mapping_format, parse_mode,
+    parse_nmstate_strictness, parse_override, parse_vlan_range, Backend, BindBy, GenerateOptions,
+    KeyfileOverride, MappingFormat, NetworkConfig, NmstateStrictness, STDIN_CONFIG_DIR,
+};
+pub use merge_conf::{parse_merge_policy, MergePolicy};
+pub use state::STATE_FILE;
+pub use types::{ApplyReport, Host, Interface, InterfaceReport, InterfaceType};
+
+/// File storing a mapping between host identifier (usually hostname) and its preconfigured network interfaces.
+const HOST_MAPPING_FILE: &str = "host_config.yaml";
+/// Same as `HOST_MAPPING_FILE` serialized as JSON.
+const HOST_MAPPING_JSON_FILE: &str = "host_config.json";
+/// File storing input configurations applicable for all hosts.
+const ALL_HOSTS_FILE: &str = "_all.yaml";
+/// Directory storing output configurations applicable for all hosts.
+const ALL_HOSTS_DIR: &str = "_all";
+/// NetworkManager dispatcher script keeping `/etc/hostname` in sync with the DHCP provided hostname.
+const HOSTNAME_DISPATCHER_FILE: &str = "90-nmc-hostname";
+
+/// Generate network configurations from all YAML files in the `config_dir`
+/// and store the result *.nmconnection files and host mapping (if applicable) under `output_dir`.
+pub fn generate(config_dir: &str, output_dir: &str) -> Result<(), ConfigError> {
+    generate_with_options(config_dir, output_dir, &GenerateOptions::default())
+}
+
+/// Same as [`generate`] with non-default behaviour.
+pub fn generate_with_options(
+    config_dir: &str,
+    output_dir: &str,
+    options: &GenerateOptions,
+) -> Result<(), ConfigError> {
+    generate_conf::generate(config_dir, output_dir, options)
+}
+
+/// Same as [`generate_with_options`] but writes the output as a gzipped tarball to `writer`
+/// (e.g. stdout to be piped into a remote [`apply`]) instead of storing it.
+pub fn generate_archive(
+    config_dir: &str,
+    writer: impl std::io::Write,
+    options: &GenerateOptions,
+) -> Result<(), ConfigError> {
+    generate_conf::generate_archive(config_dir, writer, options)
+}
+
+/// Same as [`generate_with_options`] but returns the configurations per host instead of storing them.
+///
+/// Unified configurations are returned as a single host named `_all`.
+pub fn generate_in_memory(
+    config_dir: &str,
+    options: &GenerateOptions,
+) -> Result<Vec<(Host, NetworkConfig)>, ConfigError> {
+    generate_conf::generate_network_configs(config_dir, options)
+}
+
+/// Validate all YAML files in the `config_dir` without writing any output.
+pub fn validate(config_dir: &str, options: &GenerateOptions) -> Result<(), ConfigError> {
+    validate_conf::validate(config_dir, options)
+}
+
+/// Check the output of [`generate`] stored in `output_dir` for keyfiles and host mapping entries without counterpart.
+pub fn validate_mapping_consistency(output_dir: &str) -> Result<(), ConfigError> {
+    validate_conf::validate_mapping_consistency(output_dir)
+}
+
+/// Identify the current host and apply the matching configurations previously stored by [`generate`].
+pub fn apply(config_dir: &str) -> Result<(), ConfigError> {
+    apply_with_options(config_dir, &ApplyOptions::default())
+}
+
+/// Same as [`apply`] with non-default behaviour.
+pub fn apply_with_options(config_dir: &str, options: &ApplyOptions) -> Result<(), ConfigError> {
+    apply_conf::apply(config_dir, options)
+}
+
+/// Merge the nmstate documents stored in `paths`, given in order of increasing priority, into a single one.
+pub fn merge(paths: &[std::path::PathBuf], policy: MergePolicy) -> Result<String, ConfigError> {
+    merge_conf::merge(paths, policy)
+}
+
+/// Remove the files written by [`apply`], only listing them unless `options.confirm` is set.
+pub fn clean(options: &CleanOptions) -> Result<(), ConfigError> {
+    clean_conf::clean(options)
+}
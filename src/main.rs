@@ -1,43 +1,367 @@
-use log::{error, info};
+use std::fs;
+use std::io;
 
-use apply_conf::apply;
-use generate_conf::generate;
+use clap_complete::Shell;
+use log::{error, info, warn};
 
-mod apply_conf;
-mod generate_conf;
-mod types;
+use nmc::{
+    apply_with_options, clean, generate_archive, generate_with_options, merge, parse_backend,
+    parse_bind_by, parse_connection_override, parse_keyfile_owner, parse_mac_change_policy,
+    parse_mac_match_mask, parse_mapping_format, parse_merge_policy, parse_mode, parse_nic_provider,
+    parse_nmstate_strictness, parse_override, parse_vlan_range, validate,
+    validate_mapping_consistency, ApplyOptions, Backend, BindBy, CleanOptions, ConfigError,
+    GenerateOptions, KeyfileOverride, MacChangePolicy, MappingFormat, MergePolicy, NicProvider,
+    NmstateStrictness, UnifiedLayout, CERTS_DIR, CONFIG_DIR, STATE_FILE,
+    STATIC_SYSTEM_CONNECTIONS_DIR, STDIN_CONFIG_DIR,
+};
 
 const APP_NAME: &str = "nmc";
 
 const SUB_CMD_GENERATE: &str = "generate";
 const SUB_CMD_APPLY: &str = "apply";
+const SUB_CMD_VALIDATE: &str = "validate";
+const SUB_CMD_CLEAN: &str = "clean";
+const SUB_CMD_MERGE: &str = "merge";
+const SUB_CMD_COMPLETIONS: &str = "completions";
 
-/// File storing a mapping between host identifier (usually hostname) and its preconfigured network interfaces.
-const HOST_MAPPING_FILE: &str = "host_config.yaml";
-/// File storing input configurations applicable for all hosts.
-const ALL_HOSTS_FILE: &str = "_all.yaml";
-/// Directory storing output configurations applicable for all hosts.
-const ALL_HOSTS_DIR: &str = "_all";
+/// Environment variable providing the config dir when `--config-dir` is absent.
+const CONFIG_DIR_ENV: &str = "NMC_CONFIG_DIR";
+const DESIRED_STATES_DIR: &str = "desired-states";
 
-fn main() {
-    let app = clap::Command::new(APP_NAME)
+/// Exit code of unexpected failures.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code of invalid input (e.g. unparsable desired states or host mappings).
+const EXIT_INVALID_CONFIG: i32 = 2;
+/// Exit code of `apply` when none or several of the preconfigured hosts match.
+const EXIT_NO_MATCHING_HOST: i32 = 3;
+/// Exit code of I/O failures (e.g. missing files or insufficient permissions).
+const EXIT_IO_ERROR: i32 = 4;
+/// Exit code of failures reported by nmstate.
+const EXIT_NMSTATE_ERROR: i32 = 5;
+
+fn build_cli() -> clap::Command {
+    clap::Command::new(APP_NAME)
         .version(clap::crate_version!())
         .about("Command line of NM configurator")
         .subcommand_required(true)
+        .arg(
+            clap::Arg::new("LOG-FILE")
+                .long("log-file")
+                .value_name("PATH")
+                .global(true)
+                .help("Appends the logs to the given file instead of writing them to stderr"),
+        )
+        .arg(
+            clap::Arg::new("LOG-STDERR")
+                .long("log-stderr")
+                .action(clap::ArgAction::SetTrue)
+                .global(true)
+                .requires("LOG-FILE")
+                .help("Keeps writing the logs to stderr in addition to the log file"),
+        )
         .subcommand(
             clap::Command::new(SUB_CMD_GENERATE)
                 .about("Generate network configuration using nmstate")
                 .arg(
                     clap::Arg::new("CONFIG-DIR")
-                        .required(true)
                         .long("config-dir")
-                        .help("Config dir containing network configurations for different hosts in YAML format"),
+                        .env(CONFIG_DIR_ENV)
+                        .default_value(DESIRED_STATES_DIR)
+                        .help("Config dir containing network configurations for different hosts in YAML format \
+                         ('-' reads the configuration of a single host from stdin)"),
+                )
+                .arg(
+                    clap::Arg::new("HOSTNAME")
+                        .long("hostname")
+                        .required_if_eq("CONFIG-DIR", STDIN_CONFIG_DIR)
+                        .help("Host the configuration read from stdin belongs to ('_all' for unified configurations)"),
                 )
                 .arg(
                     clap::Arg::new("OUTPUT-DIR")
                         .default_value("_out")
                         .long("output-dir")
                         .help("Destination dir storing the output configurations"),
+                )
+                .arg(
+                    clap::Arg::new("TAR-STDOUT")
+                        .long("tar-stdout")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("OUTPUT-DIR")
+                        .help("Writes the output configurations as a gzipped tarball to stdout instead of the output dir"),
+                )
+                .arg(
+                    clap::Arg::new("HOST")
+                        .long("host")
+                        .value_name("HOSTNAME")
+                        .action(clap::ArgAction::Append)
+                        .help("Only generates the configuration of the given host (can be repeated), \
+                         preserving the existing output of all others"),
+                )
+                .arg(
+                    clap::Arg::new("OUTPUT-MODE")
+                        .long("output-mode")
+                        .value_name("OCTAL")
+                        .value_parser(parse_mode)
+                        .help("Sets the mode of the generated connection files (e.g. '600' as they may contain secrets)"),
+                )
+                .arg(
+                    clap::Arg::new("BIND-BY")
+                        .long("bind-by")
+                        .value_name("name|mac")
+                        .value_parser(parse_bind_by)
+                        .help("Binds the Ethernet connections either by interface name or by MAC address only \
+                         (connections bound by MAC address are not renamed by 'apply')"),
+                )
+                .arg(
+                    clap::Arg::new("EMIT-ACTIVATION-SCRIPT")
+                        .long("emit-activation-script")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Emits an 'activate.sh' script per host which brings up the connections in dependency order"),
+                )
+                .arg(
+                    clap::Arg::new("EMIT-HOSTNAME-DISPATCHER")
+                        .long("emit-hostname-dispatcher")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Emits a NetworkManager dispatcher script syncing '/etc/hostname' with the DHCP provided hostname \
+                         (installed by 'apply')"),
+                )
+                .arg(
+                    clap::Arg::new("EMIT-TOPOLOGY")
+                        .long("emit-topology")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Emits a Graphviz diagram of the interface hierarchy as '<host>.dot' per host"),
+                )
+                .arg(
+                    clap::Arg::new("EMIT-APPLY-ORDER")
+                        .long("emit-apply-order")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Emits the hostnames ordered by the 'rollout' group and priority of the hosts as 'apply_order.txt'"),
+                )
+                .arg(
+                    clap::Arg::new("MAC-SET-HASH")
+                        .long("mac-set-hash")
+                        .value_name("SALT")
+                        .help("Stores a hash of every host's MAC addresses salted with the given value in the host mapping \
+                         instead of the addresses themselves"),
+                )
+                .arg(
+                    clap::Arg::new("WARN-ON-SECRETS")
+                        .long("warn-on-secrets")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Warns about secrets (e.g. Wi-Fi PSKs) stored in plaintext in the generated connection files"),
+                )
+                .arg(
+                    clap::Arg::new("EMIT-FRAGMENTS")
+                        .long("emit-fragments")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Emits the nmstate definition of each interface as a separate YAML file \
+                         under 'fragments' per host"),
+                )
+                .arg(
+                    clap::Arg::new("SET")
+                        .long("set")
+                        .value_name("SECTION.KEY=VALUE")
+                        .value_parser(parse_override)
+                        .action(clap::ArgAction::Append)
+                        .help("Sets the key on all generated connection files (can be repeated)"),
+                )
+                .arg(
+                    clap::Arg::new("SET-FOR")
+                        .long("set-for")
+                        .value_name("ID:SECTION.KEY=VALUE")
+                        .value_parser(parse_connection_override)
+                        .action(clap::ArgAction::Append)
+                        .help("Sets the key on the generated connection file with the given id, \
+                         taking precedence over '--set' (can be repeated)"),
+                )
+                .arg(
+                    clap::Arg::new("STABLE-ID")
+                        .long("stable-id")
+                        .value_name("TEMPLATE")
+                        .help("Sets 'connection.stable-id' on connections not specifying one \
+                         (supports NetworkManager tokens e.g. '${CONNECTION}', '${DEVICE}', '${BOOT}')"),
+                )
+                .arg(
+                    clap::Arg::new("BACKEND")
+                        .long("backend")
+                        .value_name("networkmanager|cloud-init")
+                        .value_parser(parse_backend)
+                        .help("Format of the generated configuration, 'cloud-init' emits a network-config (version 2) \
+                         file per host supporting Ethernet, VLAN and bond interfaces only"),
+                )
+                .arg(
+                    clap::Arg::new("MAPPING-FORMAT")
+                        .long("mapping-format")
+                        .value_name("yaml|json")
+                        .value_parser(parse_mapping_format)
+                        .help("Format of the host mapping, stored as 'host_config.yaml' or 'host_config.json'"),
+                )
+                .arg(
+                    clap::Arg::new("NMSTATE-STRICTNESS")
+                        .long("nmstate-strictness")
+                        .value_name("strict|permissive")
+                        .value_parser(parse_nmstate_strictness)
+                        .help("Additionally rejects interfaces of unknown type in strict mode \
+                         instead of accepting everything nmstate parses"),
+                )
+                .arg(
+                    clap::Arg::new("AUTOCONNECT")
+                        .long("autoconnect")
+                        .value_name("true|false")
+                        .value_parser(clap::value_parser!(bool))
+                        .help("Sets 'connection.autoconnect' on connections not specifying one \
+                         rather than relying on the NetworkManager default"),
+                )
+                .arg(
+                    clap::Arg::new("IPV6-PRIVACY")
+                        .long("ipv6-privacy")
+                        .value_name("0|1|2")
+                        .value_parser(clap::value_parser!(u8).range(0..=2))
+                        .help("Sets 'ipv6.ip6-privacy' on IPv6 enabled connections not specifying one \
+                         rather than relying on the NetworkManager default"),
+                )
+                .arg(
+                    clap::Arg::new("STRICT")
+                        .long("strict")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Fails on advisory findings (e.g. inconsistent flow control) instead of logging warnings"),
+                )
+                .arg(
+                    clap::Arg::new("ID-PATTERN")
+                        .long("id-pattern")
+                        .value_name("REGEX")
+                        .value_parser(regex::Regex::new)
+                        .help("Fails if any of the generated connection ids does not match the pattern"),
+                )
+                .arg(
+                    clap::Arg::new("MAX-CONNECTIONS")
+                        .long("max-connections")
+                        .value_name("COUNT")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Warns (or fails with --strict) if a host has more connections than the given count"),
+                )
+                .arg(
+                    clap::Arg::new("JOBS")
+                        .long("jobs")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Maximum number of hosts generated in parallel (defaults to the number of CPUs)"),
+                )
+                .arg(
+                    clap::Arg::new("MERGE-POLICY")
+                        .long("merge-policy")
+                        .value_name("error|override|deep-merge")
+                        .value_parser(parse_merge_policy)
+                        .help("Resolution of interfaces defined by both '_all.yaml' and a per-host override \
+                         (defaults to 'error')"),
+                )
+                .arg(
+                    clap::Arg::new("RESERVED-VLANS")
+                        .long("reserved-vlans")
+                        .value_name("RANGE")
+                        .value_parser(parse_vlan_range)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Warns about VLAN ids within the given reserved ranges (e.g. '1-10,4000-4094')"),
+                )
+                .arg(
+                    clap::Arg::new("ENV-SUBSTITUTE")
+                        .long("env-substitute")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Expands '${VAR}' references in the input configurations from the environment"),
+                )
+                .arg(
+                    clap::Arg::new("ALLOW-UNDEFINED")
+                        .long("allow-undefined")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("ENV-SUBSTITUTE")
+                        .help("Keeps references to undefined environment variables instead of failing"),
+                )
+                .arg(
+                    clap::Arg::new("TIMING")
+                        .long("timing")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Logs the duration of the individual phases (read, gen_conf, store) per host"),
+                )
+                .arg(
+                    clap::Arg::new("VERBOSE")
+                        .long("verbose")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Enables DEBUG log level"),
+                ))
+        .subcommand(
+            clap::Command::new(SUB_CMD_VALIDATE)
+                .about("Validate network configurations without generating any output")
+                .arg(
+                    clap::Arg::new("CONFIG-DIR")
+                        .long("config-dir")
+                        .env(CONFIG_DIR_ENV)
+                        .default_value(DESIRED_STATES_DIR)
+                        .help("Config dir containing network configurations for different hosts in YAML format"),
+                )
+                .arg(
+                    clap::Arg::new("STRICT")
+                        .long("strict")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Fails on advisory findings (e.g. inconsistent flow control) instead of logging warnings"),
+                )
+                .arg(
+                    clap::Arg::new("ID-PATTERN")
+                        .long("id-pattern")
+                        .value_name("REGEX")
+                        .value_parser(regex::Regex::new)
+                        .help("Fails if any of the connection ids does not match the pattern"),
+                )
+                .arg(
+                    clap::Arg::new("MAX-CONNECTIONS")
+                        .long("max-connections")
+                        .value_name("COUNT")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Warns (or fails with --strict) if a host has more connections than the given count"),
+                )
+                .arg(
+                    clap::Arg::new("JOBS")
+                        .long("jobs")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Maximum number of hosts generated in parallel (defaults to the number of CPUs)"),
+                )
+                .arg(
+                    clap::Arg::new("MERGE-POLICY")
+                        .long("merge-policy")
+                        .value_name("error|override|deep-merge")
+                        .value_parser(parse_merge_policy)
+                        .help("Resolution of interfaces defined by both '_all.yaml' and a per-host override \
+                         (defaults to 'error')"),
+                )
+                .arg(
+                    clap::Arg::new("MAPPING-CONSISTENCY")
+                        .long("mapping-consistency")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Treats the config dir as output of 'generate' and checks that the host mapping \
+                         and the keyfiles of every host match exactly"),
+                )
+                .arg(
+                    clap::Arg::new("RESERVED-VLANS")
+                        .long("reserved-vlans")
+                        .value_name("RANGE")
+                        .value_parser(parse_vlan_range)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Warns about VLAN ids within the given reserved ranges (e.g. '1-10,4000-4094')"),
+                )
+                .arg(
+                    clap::Arg::new("ENV-SUBSTITUTE")
+                        .long("env-substitute")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Expands '${VAR}' references in the input configurations from the environment"),
+                )
+                .arg(
+                    clap::Arg::new("ALLOW-UNDEFINED")
+                        .long("allow-undefined")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("ENV-SUBSTITUTE")
+                        .help("Keeps references to undefined environment variables instead of failing"),
                 ))
         .subcommand(
             clap::Command::new(SUB_CMD_APPLY)
@@ -45,9 +369,12 @@ fn main() {
                 .arg(
                     clap::Arg::new("CONFIG-DIR")
                         .long("config-dir")
+                        .env(CONFIG_DIR_ENV)
                         .default_value("config")
                         .help("Config dir containing host mapping ('host_config.yaml') \
-                         and subdirectories containing *.nmconnection files per host")
+                         and subdirectories containing *.nmconnection files per host \
+                         (or an HTTP(S) URL to a gzipped tarball of it with the 'remote' feature, \
+                         '-' reads the gzipped tarball from stdin)")
                 )
                 .arg(
                     clap::Arg::new("VERBOSE")
@@ -55,9 +382,332 @@ fn main() {
                         .action(clap::ArgAction::SetTrue)
                         .help("Enables DEBUG log level")
                 )
-        );
+                .arg(
+                    clap::Arg::new("ALLOW-NO-MATCH")
+                        .long("allow-no-match")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Skips applying config instead of failing when none of the preconfigured hosts match")
+                )
+                .arg(
+                    clap::Arg::new("WARN-UNMATCHED")
+                        .long("warn-unmatched")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Warns about other preconfigured hosts sharing MAC addresses with the identified one")
+                )
+                .arg(
+                    clap::Arg::new("DRY-RUN")
+                        .long("dry-run")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Logs the intended changes without writing any files")
+                )
+                .arg(
+                    clap::Arg::new("TIMING")
+                        .long("timing")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Logs the duration of the individual phases")
+                )
+                .arg(
+                    clap::Arg::new("NM-CONNECTIONS-DIR")
+                        .long("nm-connections-dir")
+                        .default_value(STATIC_SYSTEM_CONNECTIONS_DIR)
+                        .help("Destination dir of the NetworkManager connection files")
+                )
+                .arg(
+                    clap::Arg::new("NM-CONFIG-DIR")
+                        .long("nm-config-dir")
+                        .default_value(CONFIG_DIR)
+                        .help("NetworkManager configuration dir used to disable the default wired connections")
+                )
+                .arg(
+                    clap::Arg::new("NM-CERTS-DIR")
+                        .long("nm-certs-dir")
+                        .default_value(CERTS_DIR)
+                        .help("Destination dir of the certificates and keys referenced by the connection files")
+                )
+                .arg(
+                    clap::Arg::new("REPORT")
+                        .long("report")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("Stores a JSON report of the identified host and the applied interface renames")
+                )
+                .arg(
+                    clap::Arg::new("CONFIG-SHA256")
+                        .long("config-sha256")
+                        .value_name("CHECKSUM")
+                        .help("Verifies the SHA-256 checksum of a config dir fetched from an HTTP(S) URL \
+                         (requires the 'remote' feature)")
+                )
+                .arg(
+                    clap::Arg::new("BACKUP-ARCHIVE")
+                        .long("backup-archive")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("Stores the current NetworkManager connections and configuration \
+                         as well as '/etc/hostname' in a gzipped tarball before making any changes")
+                )
+                .arg(
+                    clap::Arg::new("NIC-PROVIDER")
+                        .long("nic-provider")
+                        .value_name("auto|native|ip")
+                        .value_parser(parse_nic_provider)
+                        .help("Source of the local network interfaces, 'auto' falls back to 'ip -j link show' \
+                         if the native listing lacks MAC addresses")
+                )
+                .arg(
+                    clap::Arg::new("SELECT")
+                        .long("select")
+                        .value_name("ID")
+                        .action(clap::ArgAction::Append)
+                        .help("Only applies the connection with the given id from a unified config, \
+                         can be repeated")
+                )
+                .arg(
+                    clap::Arg::new("ONLY")
+                        .long("only")
+                        .value_name("NAMES")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Only applies the connections of the host interfaces with the given comma separated \
+                         logical or detected local names, leaving the others untouched")
+                )
+                .arg(
+                    clap::Arg::new("LIVE-STATE")
+                        .long("live-state")
+                        .value_name("FILE")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .requires("DRY-RUN")
+                        .conflicts_with_all(["NIC-PROVIDER", "PERSIST-NAMES"])
+                        .help("Plans the apply against the interfaces of a live state captured by 'nmstatectl show' \
+                         (e.g. of a remote host) instead of the local NICs, requires --dry-run")
+                )
+                .arg(
+                    clap::Arg::new("HOSTNAME")
+                        .long("hostname")
+                        .value_name("NAME")
+                        .help("Selects the host by its hostname instead of identifying it by its NICs")
+                )
+                .arg(
+                    clap::Arg::new("EXPLAIN-RENAMES")
+                        .long("explain-renames")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Lists every keyfile line mentioning a renamed interface and whether it gets rewritten")
+                )
+                .arg(
+                    clap::Arg::new("STATE-FILE")
+                        .long("state-file")
+                        .value_name("PATH")
+                        .default_value(STATE_FILE)
+                        .help("File recording the hostname and hash of the applied config after a successful apply")
+                )
+                .arg(
+                    clap::Arg::new("IF-CHANGED")
+                        .long("if-changed")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Skips applying the config if its hash matches the one recorded in the state file")
+                )
+                .arg(
+                    clap::Arg::new("ON-MAC-CHANGE")
+                        .long("on-mac-change")
+                        .value_name("fail|rename|warn")
+                        .value_parser(parse_mac_change_policy)
+                        .help("Handling of preconfigured interfaces whose MAC address is not present locally \
+                         (e.g. replaced NICs), 'rename' adopts the MAC address of the NIC with the preconfigured name")
+                )
+                .arg(
+                    clap::Arg::new("MAC-MATCH-MASK")
+                        .long("mac-match-mask")
+                        .value_name("MASK")
+                        .value_parser(parse_mac_match_mask)
+                        .help("Matches interfaces without an exact MAC address match only on the bits set in the mask \
+                         (e.g. 'fd:ff:ff:ff:ff:ff' ignoring the locally administered bit)"),
+                )
+                .arg(
+                    clap::Arg::new("KEEP-WIRED-DEFAULTS")
+                        .long("keep-wired-defaults")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Keeps the default wired connections of NetworkManager instead of disabling them \
+                         via 'no-auto-default=*' and removing the runtime connections")
+                )
+                .arg(
+                    clap::Arg::new("PERSIST-NAMES")
+                        .long("persist-names")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Pins the preconfigured interface names via systemd .link files and waits for udev \
+                         to rename the NICs before applying the connections")
+                )
+                .arg(
+                    clap::Arg::new("PROBE")
+                        .long("probe")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Checks the live state of the local NICs for likely misconfigurations \
+                         (e.g. bond ports with differing link speeds)")
+                )
+                .arg(
+                    clap::Arg::new("RELOAD")
+                        .long("reload")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Asks NetworkManager to reload the connection files once applied")
+                )
+                .arg(
+                    clap::Arg::new("RELOAD-REQUIRED")
+                        .long("reload-required")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("RELOAD")
+                        .help("Fails if reloading the connection files fails instead of only logging it")
+                )
+                .arg(
+                    clap::Arg::new("HEALTH-CHECK")
+                        .long("health-check")
+                        .value_name("COMMAND")
+                        .requires("RELOAD")
+                        .help("Shell command verifying the network once reloaded (e.g. pinging the gateway), \
+                         retried until it exits successfully")
+                )
+                .arg(
+                    clap::Arg::new("HEALTH-TIMEOUT")
+                        .long("health-timeout")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("60")
+                        .requires("HEALTH-CHECK")
+                        .help("Fails if the health check does not pass within the given time")
+                )
+                .arg(
+                    clap::Arg::new("HEALTH-ROLLBACK")
+                        .long("health-rollback")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("HEALTH-CHECK")
+                        .help("Restores and reloads the previous connection files if the health check fails")
+                )
+                .arg(
+                    clap::Arg::new("FLATTEN")
+                        .long("flatten")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("NESTED")
+                        .help("Copies the keyfiles of subdirectories within unified configurations \
+                         into the NetworkManager connections dir itself")
+                )
+                .arg(
+                    clap::Arg::new("NESTED")
+                        .long("nested")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Preserves subdirectories within unified configurations \
+                         in the NetworkManager connections dir")
+                )
+                .arg(
+                    clap::Arg::new("KEYFILE-OWNER")
+                        .long("keyfile-owner")
+                        .value_name("UID:GID")
+                        .value_parser(parse_keyfile_owner)
+                        .help("Changes the owner of the written connection files (requires sufficient privileges)")
+                )
+        )
+        .subcommand(
+            clap::Command::new(SUB_CMD_CLEAN)
+                .about("Remove network configurations previously applied to host")
+                .arg(
+                    clap::Arg::new("REPORT")
+                        .long("report")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("JSON report stored by 'apply --report' listing the connection files to remove")
+                )
+                .arg(
+                    clap::Arg::new("ALL")
+                        .long("all")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Removes all connection files within the NetworkManager connections dir")
+                )
+                .group(
+                    clap::ArgGroup::new("CONNECTION-FILES")
+                        .args(["REPORT", "ALL"])
+                        .required(true)
+                )
+                .arg(
+                    clap::Arg::new("RESET-HOSTNAME")
+                        .long("reset-hostname")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Removes '/etc/hostname' as well")
+                )
+                .arg(
+                    clap::Arg::new("CONFIRM")
+                        .long("confirm")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Removes the files instead of only listing them")
+                )
+                .arg(
+                    clap::Arg::new("NM-CONNECTIONS-DIR")
+                        .long("nm-connections-dir")
+                        .default_value(STATIC_SYSTEM_CONNECTIONS_DIR)
+                        .help("Dir of the NetworkManager connection files")
+                )
+                .arg(
+                    clap::Arg::new("NM-CONFIG-DIR")
+                        .long("nm-config-dir")
+                        .default_value(CONFIG_DIR)
+                        .help("NetworkManager configuration dir containing 'no-auto-default.conf'")
+                )
+                .arg(
+                    clap::Arg::new("STATE-FILE")
+                        .long("state-file")
+                        .value_name("PATH")
+                        .default_value(STATE_FILE)
+                        .help("File recording the applied config")
+                )
+                .arg(
+                    clap::Arg::new("VERBOSE")
+                        .long("verbose")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Enables DEBUG log level")
+                )
+        )
+        .subcommand(
+            clap::Command::new(SUB_CMD_MERGE)
+                .about("Merge nmstate documents (e.g. a base config and role specific overlays) into a single one")
+                .arg(
+                    clap::Arg::new("FILES")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("FILE")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("nmstate documents in order of increasing priority")
+                )
+                .arg(
+                    clap::Arg::new("MERGE-POLICY")
+                        .long("merge-policy")
+                        .value_name("error|override|deep-merge")
+                        .value_parser(parse_merge_policy)
+                        .help("Resolution of interfaces defined by several documents (defaults to 'error')")
+                )
+                .arg(
+                    clap::Arg::new("OUTPUT")
+                        .long("output")
+                        .value_name("PATH")
+                        .help("File storing the merged document (defaults to stdout)")
+                )
+                .arg(
+                    clap::Arg::new("VERBOSE")
+                        .long("verbose")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Enables DEBUG log level")
+                )
+        )
+        .subcommand(
+            clap::Command::new(SUB_CMD_COMPLETIONS)
+                .about("Print the shell completion script to stdout")
+                .hide(true)
+                .arg(
+                    clap::Arg::new("SHELL")
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish", "powershell"])
+                        .help("Shell to generate the completion script for")
+                )
+        )
+}
 
-    let matches = app.get_matches();
+fn main() {
+    let matches = build_cli().get_matches();
 
     match matches.subcommand() {
         Some((SUB_CMD_GENERATE, cmd)) => {
@@ -67,16 +717,111 @@ fn main() {
             let output_dir = cmd
                 .get_one::<String>("OUTPUT-DIR")
                 .expect("--output-dir is required");
+            let mut options = GenerateOptions::default();
+            options.emit_activation_script = cmd.get_flag("EMIT-ACTIVATION-SCRIPT");
+            options.emit_hostname_dispatcher = cmd.get_flag("EMIT-HOSTNAME-DISPATCHER");
+            options.stable_id = cmd.get_one::<String>("STABLE-ID").cloned();
+            options.autoconnect = cmd.get_one::<bool>("AUTOCONNECT").copied();
+            options.ipv6_privacy = cmd.get_one::<u8>("IPV6-PRIVACY").copied();
+            options.backend = cmd
+                .get_one::<Backend>("BACKEND")
+                .copied()
+                .unwrap_or_default();
+            options.mapping_format = cmd
+                .get_one::<MappingFormat>("MAPPING-FORMAT")
+                .copied()
+                .unwrap_or_default();
+            options.nmstate_strictness = cmd
+                .get_one::<NmstateStrictness>("NMSTATE-STRICTNESS")
+                .copied()
+                .unwrap_or_default();
+            options.strict = cmd.get_flag("STRICT");
+            options.id_pattern = cmd.get_one::<regex::Regex>("ID-PATTERN").cloned();
+            options.max_connections = cmd.get_one::<usize>("MAX-CONNECTIONS").copied();
+            options.jobs = cmd.get_one::<u16>("JOBS").copied().map(usize::from);
+            options.merge_policy = cmd
+                .get_one::<MergePolicy>("MERGE-POLICY")
+                .copied()
+                .unwrap_or_default();
+            options.env_substitute = cmd.get_flag("ENV-SUBSTITUTE");
+            options.allow_undefined = cmd.get_flag("ALLOW-UNDEFINED");
+            options.timing = cmd.get_flag("TIMING");
+            options.hosts = cmd
+                .get_many::<String>("HOST")
+                .map(|hosts| hosts.cloned().collect())
+                .unwrap_or_default();
+            options.output_mode = cmd.get_one::<u32>("OUTPUT-MODE").copied();
+            options.emit_fragments = cmd.get_flag("EMIT-FRAGMENTS");
+            options.emit_topology = cmd.get_flag("EMIT-TOPOLOGY");
+            options.warn_on_secrets = cmd.get_flag("WARN-ON-SECRETS");
+            options.emit_apply_order = cmd.get_flag("EMIT-APPLY-ORDER");
+            options.mac_set_hash_salt = cmd.get_one::<String>("MAC-SET-HASH").cloned();
+            options.bind_by = cmd.get_one::<BindBy>("BIND-BY").copied();
+            options.hostname = cmd.get_one::<String>("HOSTNAME").cloned();
+            options.overrides = ["SET", "SET-FOR"]
+                .into_iter()
+                .filter_map(|arg| cmd.get_many::<KeyfileOverride>(arg))
+                .flatten()
+                .cloned()
+                .collect();
+            options.reserved_vlans = cmd
+                .get_many::<std::ops::RangeInclusive<u16>>("RESERVED-VLANS")
+                .map(|ranges| ranges.cloned().collect())
+                .unwrap_or_default();
 
             setup_logger(cmd);
 
-            match generate(config_dir, output_dir) {
+            let result = if cmd.get_flag("TAR-STDOUT") {
+                generate_archive(config_dir, std::io::stdout().lock(), &options)
+            } else {
+                generate_with_options(config_dir, output_dir, &options)
+            };
+
+            match result {
                 Ok(..) => {
                     info!("Successfully generated and stored network config");
                 }
                 Err(err) => {
                     error!("Generating config failed: {err:#}");
-                    std::process::exit(1)
+                    std::process::exit(exit_code(&err))
+                }
+            }
+        }
+        Some((SUB_CMD_VALIDATE, cmd)) => {
+            let config_dir = cmd
+                .get_one::<String>("CONFIG-DIR")
+                .expect("--config-dir is required");
+            let mut options = GenerateOptions::default();
+            options.strict = cmd.get_flag("STRICT");
+            options.id_pattern = cmd.get_one::<regex::Regex>("ID-PATTERN").cloned();
+            options.max_connections = cmd.get_one::<usize>("MAX-CONNECTIONS").copied();
+            options.jobs = cmd.get_one::<u16>("JOBS").copied().map(usize::from);
+            options.merge_policy = cmd
+                .get_one::<MergePolicy>("MERGE-POLICY")
+                .copied()
+                .unwrap_or_default();
+            options.env_substitute = cmd.get_flag("ENV-SUBSTITUTE");
+            options.allow_undefined = cmd.get_flag("ALLOW-UNDEFINED");
+            options.reserved_vlans = cmd
+                .get_many::<std::ops::RangeInclusive<u16>>("RESERVED-VLANS")
+                .map(|ranges| ranges.cloned().collect())
+                .unwrap_or_default();
+
+            setup_logger(cmd);
+
+            let result = if cmd.get_flag("MAPPING-CONSISTENCY") {
+                validate_mapping_consistency(config_dir)
+            } else {
+                validate(config_dir, &options)
+            };
+
+            match result {
+                Ok(..) => {
+                    info!("Successfully validated network config");
+                }
+                Err(err) => {
+                    error!("Validating config failed: {err:#}");
+                    std::process::exit(exit_code(&err))
                 }
             }
         }
@@ -84,23 +829,192 @@ fn main() {
             let config_dir = cmd
                 .get_one::<String>("CONFIG-DIR")
                 .expect("--config-dir is required");
+            let mut options = ApplyOptions::default();
+            options.allow_no_match = cmd.get_flag("ALLOW-NO-MATCH");
+            options.warn_unmatched = cmd.get_flag("WARN-UNMATCHED");
+            options.dry_run = cmd.get_flag("DRY-RUN");
+            options.timing = cmd.get_flag("TIMING");
+            options.keyfile_owner = cmd.get_one::<(u32, u32)>("KEYFILE-OWNER").copied();
+            options.nm_connections_dir = cmd
+                .get_one::<String>("NM-CONNECTIONS-DIR")
+                .expect("--nm-connections-dir has a default value")
+                .to_owned();
+            options.nm_config_dir = cmd
+                .get_one::<String>("NM-CONFIG-DIR")
+                .expect("--nm-config-dir has a default value")
+                .to_owned();
+            options.nm_certs_dir = cmd
+                .get_one::<String>("NM-CERTS-DIR")
+                .expect("--nm-certs-dir has a default value")
+                .to_owned();
+            options.report = cmd.get_one::<std::path::PathBuf>("REPORT").cloned();
+            options.config_sha256 = cmd.get_one::<String>("CONFIG-SHA256").cloned();
+            options.backup_archive = cmd.get_one::<std::path::PathBuf>("BACKUP-ARCHIVE").cloned();
+            options.unified_layout = if cmd.get_flag("NESTED") {
+                UnifiedLayout::Nested
+            } else if cmd.get_flag("FLATTEN") {
+                UnifiedLayout::Flatten
+            } else {
+                UnifiedLayout::Ignore
+            };
+            options.reload = cmd.get_flag("RELOAD");
+            options.reload_required = cmd.get_flag("RELOAD-REQUIRED");
+            options.health_check = cmd.get_one::<String>("HEALTH-CHECK").cloned();
+            options.health_timeout = std::time::Duration::from_secs(
+                *cmd.get_one::<u64>("HEALTH-TIMEOUT")
+                    .expect("--health-timeout has a default value"),
+            );
+            options.health_rollback = cmd.get_flag("HEALTH-ROLLBACK");
+            options.probe = cmd.get_flag("PROBE");
+            options.explain_renames = cmd.get_flag("EXPLAIN-RENAMES");
+            options.hostname = cmd.get_one::<String>("HOSTNAME").cloned();
+            options.persist_names = cmd.get_flag("PERSIST-NAMES");
+            options.mac_match_mask = cmd.get_one::<[u8; 6]>("MAC-MATCH-MASK").copied();
+            options.state_file = cmd
+                .get_one::<String>("STATE-FILE")
+                .expect("--state-file has a default value")
+                .to_owned();
+            options.if_changed = cmd.get_flag("IF-CHANGED");
+            options.keep_wired_defaults = cmd.get_flag("KEEP-WIRED-DEFAULTS");
+            options.on_mac_change = cmd
+                .get_one::<MacChangePolicy>("ON-MAC-CHANGE")
+                .copied()
+                .unwrap_or_default();
+            options.nic_provider = cmd
+                .get_one::<NicProvider>("NIC-PROVIDER")
+                .copied()
+                .unwrap_or_default();
+            options.live_state = cmd.get_one::<std::path::PathBuf>("LIVE-STATE").cloned();
+            options.select = cmd
+                .get_many::<String>("SELECT")
+                .map(|ids| ids.cloned().collect())
+                .unwrap_or_default();
+            options.only = cmd
+                .get_many::<String>("ONLY")
+                .map(|names| names.cloned().collect())
+                .unwrap_or_default();
 
             setup_logger(cmd);
 
-            match apply(config_dir) {
+            match apply_with_options(config_dir, &options) {
                 Ok(..) => {
                     info!("Successfully applied config");
                 }
                 Err(err) => {
                     error!("Applying config failed: {err:#}");
-                    std::process::exit(1)
+                    std::process::exit(exit_code(&err))
                 }
             }
         }
+        Some((SUB_CMD_CLEAN, cmd)) => {
+            let mut options = CleanOptions::default();
+            options.report = cmd.get_one::<std::path::PathBuf>("REPORT").cloned();
+            options.all = cmd.get_flag("ALL");
+            options.reset_hostname = cmd.get_flag("RESET-HOSTNAME");
+            options.confirm = cmd.get_flag("CONFIRM");
+            options.nm_connections_dir = cmd
+                .get_one::<String>("NM-CONNECTIONS-DIR")
+                .expect("--nm-connections-dir has a default value")
+                .to_owned();
+            options.nm_config_dir = cmd
+                .get_one::<String>("NM-CONFIG-DIR")
+                .expect("--nm-config-dir has a default value")
+                .to_owned();
+            options.state_file = cmd
+                .get_one::<String>("STATE-FILE")
+                .expect("--state-file has a default value")
+                .to_owned();
+
+            setup_logger(cmd);
+
+            match clean(&options) {
+                Ok(..) => {
+                    info!("Successfully cleaned config");
+                }
+                Err(err) => {
+                    error!("Cleaning config failed: {err:#}");
+                    std::process::exit(exit_code(&err))
+                }
+            }
+        }
+        Some((SUB_CMD_MERGE, cmd)) => {
+            let paths: Vec<std::path::PathBuf> = cmd
+                .get_many::<std::path::PathBuf>("FILES")
+                .expect("FILES are required")
+                .cloned()
+                .collect();
+            let policy = cmd
+                .get_one::<MergePolicy>("MERGE-POLICY")
+                .copied()
+                .unwrap_or_default();
+
+            setup_logger(cmd);
+
+            match merge(&paths, policy) {
+                Ok(merged) => match cmd.get_one::<String>("OUTPUT") {
+                    Some(output) => {
+                        if let Err(err) = fs::write(output, merged) {
+                            error!("Writing merged config failed: {err}");
+                            std::process::exit(EXIT_IO_ERROR)
+                        }
+                    }
+                    None => print!("{merged}"),
+                },
+                Err(err) => {
+                    error!("Merging config failed: {err:#}");
+                    std::process::exit(exit_code(&err))
+                }
+            }
+        }
+        Some((SUB_CMD_COMPLETIONS, cmd)) => {
+            let shell = match cmd.get_one::<String>("SHELL").map(String::as_str) {
+                Some("bash") => Shell::Bash,
+                Some("zsh") => Shell::Zsh,
+                Some("fish") => Shell::Fish,
+                Some("powershell") => Shell::PowerShell,
+                _ => unreachable!("Unsupported shell"),
+            };
+
+            clap_complete::generate(shell, &mut build_cli(), APP_NAME, &mut io::stdout());
+        }
         _ => unreachable!("Unrecognized subcommand"),
     }
 }
 
+/// Map the failure to the exit code of its class so that automation is able to tell them apart.
+fn exit_code(err: &ConfigError) -> i32 {
+    let err = match err {
+        ConfigError::NoMatchingHost | ConfigError::AmbiguousHost(..) => {
+            return EXIT_NO_MATCHING_HOST
+        }
+        ConfigError::EmptyConfigDir
+        | ConfigError::MissingEthernet
+        | ConfigError::UnmatchedConnectionFile(..) => return EXIT_INVALID_CONFIG,
+        ConfigError::Other(err) => err,
+    };
+
+    // Typed failures remain recognizable after context was attached to them.
+    if let Some(typed) = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ConfigError>())
+    {
+        return exit_code(typed);
+    }
+
+    if err.chain().any(|cause| cause.is::<nmstate::NmstateError>()) {
+        EXIT_NMSTATE_ERROR
+    } else if err
+        .chain()
+        .any(|cause| cause.is::<serde_yaml::Error>() || cause.is::<serde_json::Error>())
+    {
+        EXIT_INVALID_CONFIG
+    } else if err.chain().any(|cause| cause.is::<io::Error>()) {
+        EXIT_IO_ERROR
+    } else {
+        EXIT_FAILURE
+    }
+}
+
 fn setup_logger(matches: &clap::ArgMatches) {
     let verbose_arg = "VERBOSE";
 
@@ -113,5 +1027,131 @@ fn setup_logger(matches: &clap::ArgMatches) {
     } else {
         log_builder.filter(None, log::LevelFilter::Info);
     }
+
+    let log_file = matches.try_get_one::<String>("LOG-FILE").ok().flatten();
+    let mut open_error = None;
+    if let Some(path) = log_file {
+        match fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                let stderr = matches
+                    .try_get_one::<bool>("LOG-STDERR")
+                    .is_ok_and(|arg| arg.is_some_and(|&value| value));
+                log_builder.target(env_logger::Target::Pipe(Box::new(LogWriter {
+                    file,
+                    stderr,
+                })));
+            }
+            Err(err) => open_error = Some(err),
+        }
+    }
     log_builder.init();
+
+    if let (Some(path), Some(err)) = (log_file, open_error) {
+        warn!("Opening log file {path} failed, logging to stderr instead: {err}");
+    }
+}
+
+/// Writes the logs to a file, optionally duplicating them to stderr.
+struct LogWriter {
+    file: fs::File,
+    stderr: bool,
+}
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stderr {
+            io::stderr().write_all(buf)?;
+        }
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use nmc::{apply_with_options, generate, ApplyOptions};
+
+    use crate::{
+        exit_code, EXIT_INVALID_CONFIG, EXIT_IO_ERROR, EXIT_NMSTATE_ERROR, EXIT_NO_MATCHING_HOST,
+    };
+
+    fn generate_exit_code(config_dir: &Path, output_dir: &str) -> i32 {
+        let err = generate(config_dir.to_str().unwrap(), output_dir).unwrap_err();
+        exit_code(&err)
+    }
+
+    #[test]
+    fn exit_code_of_generate_failures() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_exit-code-config");
+        let output_dir = "_exit-code-out";
+        fs::create_dir_all(config_dir)?;
+
+        assert_eq!(
+            generate_exit_code(config_dir, output_dir),
+            EXIT_INVALID_CONFIG
+        );
+
+        fs::write(config_dir.join("node1.yaml"), "interfaces: [\n")?;
+        assert_eq!(
+            generate_exit_code(config_dir, output_dir),
+            EXIT_INVALID_CONFIG
+        );
+
+        // Valid YAML rejected by nmstate.
+        fs::write(
+            config_dir.join("node1.yaml"),
+            "interfaces:\n- name: eth0\n  type: ethernet\n  state: up\n  \
+             mac-address: 00:00:5E:00:53:01\n  mtu: not-a-number\n",
+        )?;
+        assert_eq!(
+            generate_exit_code(config_dir, output_dir),
+            EXIT_NMSTATE_ERROR
+        );
+
+        fs::remove_dir_all(config_dir)?;
+        assert_eq!(generate_exit_code(config_dir, output_dir), EXIT_IO_ERROR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn exit_code_of_apply_failures() -> Result<(), anyhow::Error> {
+        let dir = Path::new("_exit-code-apply");
+        let config_dir = dir.join("config");
+        fs::create_dir_all(&config_dir)?;
+        fs::write(
+            config_dir.join("host_config.yaml"),
+            "- hostname: nmc-exit-code-host\n  interfaces:\n  - logical_name: eth0\n    \
+             mac_address: 00:00:5e:00:53:01\n    interface_type: ethernet\n    connection_ids: [eth0]\n",
+        )?;
+        let live_state = dir.join("live-state.yaml");
+        fs::write(&live_state, "interfaces: []\n")?;
+
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        let mut options = ApplyOptions::default();
+        options.live_state = Some(live_state);
+        options.nm_connections_dir = path("system-connections");
+        options.nm_config_dir = path("conf.d");
+        options.nm_certs_dir = path("certs");
+        options.state_file = path("state.json");
+
+        let err = apply_with_options(config_dir.to_str().unwrap(), &options).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_NO_MATCHING_HOST);
+
+        fs::write(config_dir.join("host_config.yaml"), "- hostname: [\n")?;
+        let err = apply_with_options(config_dir.to_str().unwrap(), &options).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_CONFIG);
+
+        // cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,347 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use log::info;
+
+use crate::error::ConfigError;
+
+/// Resolution of interfaces defined by several nmstate documents,
+/// the later document always taking priority over the earlier one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MergePolicy {
+    /// Fail if the later document defines any setting of an interface differently.
+    #[default]
+    Error,
+    /// Replace the whole interface definition by the one of the later document.
+    Override,
+    /// Merge the interface settings recursively with the later document winning on conflicts.
+    DeepMerge,
+}
+
+/// Parse the `--merge-policy` value.
+pub fn parse_merge_policy(value: &str) -> Result<MergePolicy, String> {
+    match value {
+        "error" => Ok(MergePolicy::Error),
+        "override" => Ok(MergePolicy::Override),
+        "deep-merge" => Ok(MergePolicy::DeepMerge),
+        _ => Err(format!(
+            "invalid merge policy '{value}', expected 'error', 'override' or 'deep-merge'"
+        )),
+    }
+}
+
+/// Merge the nmstate documents stored in `paths` into a single one.
+///
+/// The documents are given in order of increasing priority, e.g. a base config followed by role specific overlays.
+pub(crate) fn merge(paths: &[PathBuf], policy: MergePolicy) -> Result<String, ConfigError> {
+    let (first, others) = paths
+        .split_first()
+        .ok_or_else(|| anyhow!("No config files to merge"))?;
+
+    let mut merged =
+        fs::read_to_string(first).with_context(|| format!("Reading config {first:?}"))?;
+
+    for path in others {
+        info!("Merging {path:?}...");
+
+        let data = fs::read_to_string(path).with_context(|| format!("Reading config {path:?}"))?;
+        merged =
+            merge_documents(&merged, &data, policy).with_context(|| format!("Merging {path:?}"))?;
+    }
+
+    Ok(merged)
+}
+
+/// Merge the nmstate document `overlay` on top of `base`.
+///
+/// Interfaces are matched by name: matching ones are resolved according to the `policy`, while new ones are appended.
+/// Any other mapping is merged recursively and all other values (including lists such as `routes.config`)
+/// are replaced by the overlay.
+pub(crate) fn merge_documents(
+    base: &str,
+    overlay: &str,
+    policy: MergePolicy,
+) -> anyhow::Result<String> {
+    let mut base: serde_yaml::Value = serde_yaml::from_str(base).context("Parsing base config")?;
+    let overlay: serde_yaml::Value =
+        serde_yaml::from_str(overlay).context("Parsing overlay config")?;
+
+    merge_values(&mut base, overlay, true, policy)?;
+
+    serde_yaml::to_string(&base).context("Serializing merged config")
+}
+
+fn merge_values(
+    base: &mut serde_yaml::Value,
+    overlay: serde_yaml::Value,
+    top_level: bool,
+    policy: MergePolicy,
+) -> anyhow::Result<()> {
+    use serde_yaml::Value;
+
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                let is_interfaces = top_level && key.as_str() == Some("interfaces");
+                match base.get_mut(&key) {
+                    Some(Value::Sequence(existing)) if is_interfaces => {
+                        merge_interfaces(existing, value, policy)?
+                    }
+                    Some(existing) => merge_values(existing, value, false, policy)?,
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+
+    Ok(())
+}
+
+fn merge_interfaces(
+    base: &mut Vec<serde_yaml::Value>,
+    overlay: serde_yaml::Value,
+    policy: MergePolicy,
+) -> anyhow::Result<()> {
+    let serde_yaml::Value::Sequence(overlay) = overlay else {
+        *base = vec![overlay];
+        return Ok(());
+    };
+
+    for interface in overlay {
+        let existing = interface.get("name").and_then(|name| {
+            base.iter_mut()
+                .find(|candidate| candidate.get("name") == Some(name))
+        });
+        let Some(existing) = existing else {
+            base.push(interface);
+            continue;
+        };
+
+        match policy {
+            MergePolicy::Error => {
+                if let Some(setting) = conflicting_setting(existing, &interface) {
+                    return Err(anyhow!(
+                        "Conflicting definitions of interface '{}' ({setting}), \
+                         choose a merge policy of 'override' or 'deep-merge' to resolve them",
+                        interface["name"].as_str().unwrap_or_default()
+                    ));
+                }
+                merge_values(existing, interface, false, policy)?
+            }
+            MergePolicy::Override => *existing = interface,
+            MergePolicy::DeepMerge => merge_values(existing, interface, false, policy)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the dotted path of the first setting `overlay` defines differently from `base`.
+///
+/// Settings only present in the overlay are additions rather than conflicts.
+fn conflicting_setting(base: &serde_yaml::Value, overlay: &serde_yaml::Value) -> Option<String> {
+    use serde_yaml::Value;
+
+    let (Value::Mapping(base), Value::Mapping(overlay)) = (base, overlay) else {
+        return (base != overlay).then(String::new);
+    };
+
+    overlay.iter().find_map(|(key, value)| {
+        let existing = base.get(key)?;
+        let key = key.as_str().unwrap_or_default();
+        conflicting_setting(existing, value).map(|path| {
+            if path.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{key}.{path}")
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::merge_conf::{merge, merge_documents, parse_merge_policy, MergePolicy};
+
+    const MERGE_BASE: &str = r#"---
+    interfaces:
+      - name: eth0
+        type: ethernet
+        state: up
+        mtu: 1500
+        ipv4:
+          enabled: true
+          dhcp: true
+    "#;
+
+    #[test]
+    fn merge_documents_deep_merges_by_interface_name() -> Result<(), anyhow::Error> {
+        let base = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            ipv4:
+              enabled: true
+              dhcp: true
+          - name: eth1
+            type: ethernet
+            state: up
+        dns-resolver:
+          config:
+            server:
+              - 10.0.0.1
+        "#;
+        let overlay = r#"---
+        interfaces:
+          - name: eth0
+            ipv4:
+              dhcp: false
+              address:
+                - ip: 192.168.0.5
+                  prefix-length: 24
+          - name: eth2
+            type: ethernet
+            state: up
+        dns-resolver:
+          config:
+            server:
+              - 10.0.0.2
+        "#;
+
+        let merged: serde_yaml::Value =
+            serde_yaml::from_str(&merge_documents(base, overlay, MergePolicy::DeepMerge)?)?;
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r#"---
+            interfaces:
+              - name: eth0
+                type: ethernet
+                state: up
+                ipv4:
+                  enabled: true
+                  dhcp: false
+                  address:
+                    - ip: 192.168.0.5
+                      prefix-length: 24
+              - name: eth1
+                type: ethernet
+                state: up
+              - name: eth2
+                type: ethernet
+                state: up
+            dns-resolver:
+              config:
+                server:
+                  - 10.0.0.2
+            "#,
+        )?;
+        assert_eq!(merged, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_documents_fails_on_conflicting_interface() -> Result<(), anyhow::Error> {
+        let overlay = r#"---
+        interfaces:
+          - name: eth0
+            mtu: 9000
+        "#;
+
+        let error = merge_documents(MERGE_BASE, overlay, MergePolicy::Error).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting definitions of interface 'eth0' (mtu), \
+             choose a merge policy of 'override' or 'deep-merge' to resolve them"
+        );
+
+        let overlay = r#"---
+        interfaces:
+          - name: eth0
+            ipv4:
+              dhcp: false
+        "#;
+        let error = merge_documents(MERGE_BASE, overlay, MergePolicy::Error).unwrap_err();
+        assert!(error.to_string().contains("interface 'eth0' (ipv4.dhcp)"));
+
+        // Identical or additional settings do not conflict.
+        let overlay = r#"---
+        interfaces:
+          - name: eth0
+            mtu: 1500
+            ipv6:
+              enabled: false
+        "#;
+        let merged: serde_yaml::Value =
+            serde_yaml::from_str(&merge_documents(MERGE_BASE, overlay, MergePolicy::Error)?)?;
+        assert_eq!(merged["interfaces"][0]["ipv4"]["dhcp"], true);
+        assert_eq!(merged["interfaces"][0]["ipv6"]["enabled"], false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_documents_replaces_conflicting_interface() -> Result<(), anyhow::Error> {
+        let overlay = r#"---
+        interfaces:
+          - name: eth0
+            type: ethernet
+            state: up
+            mtu: 9000
+        "#;
+
+        let merged: serde_yaml::Value = serde_yaml::from_str(&merge_documents(
+            MERGE_BASE,
+            overlay,
+            MergePolicy::Override,
+        )?)?;
+        let expected: serde_yaml::Value = serde_yaml::from_str(overlay)?;
+        assert_eq!(merged, expected);
+
+        assert_eq!(parse_merge_policy("override"), Ok(MergePolicy::Override));
+        assert_eq!(parse_merge_policy("deep-merge"), Ok(MergePolicy::DeepMerge));
+        assert!(parse_merge_policy("last-write-wins").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_files_in_order_of_priority() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_merge-config");
+        fs::create_dir_all(config_dir)?;
+        let paths: Vec<PathBuf> = ["base.yaml", "role.yaml", "site.yaml"]
+            .iter()
+            .map(|file| config_dir.join(file))
+            .collect();
+        fs::write(&paths[0], MERGE_BASE)?;
+        fs::write(&paths[1], "interfaces:\n- name: eth0\n  mtu: 9000\n")?;
+        fs::write(&paths[2], "interfaces:\n- name: eth0\n  mtu: 9100\n")?;
+
+        let error = merge(&paths, MergePolicy::Error).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Merging \"_merge-config/role.yaml\": Conflicting definitions of interface 'eth0' (mtu), \
+             choose a merge policy of 'override' or 'deep-merge' to resolve them"
+        );
+
+        // The last document takes priority over all earlier ones.
+        let merged: serde_yaml::Value =
+            serde_yaml::from_str(&merge(&paths, MergePolicy::DeepMerge)?)?;
+        assert_eq!(merged["interfaces"][0]["mtu"], 9100);
+        assert_eq!(merged["interfaces"][0]["ipv4"]["dhcp"], true);
+
+        assert!(merge(&[], MergePolicy::Error).is_err());
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Read;
+
+use anyhow::{anyhow, Context};
+use log::info;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+/// Whether the config dir refers to a remote tarball rather than a local directory.
+pub(crate) fn is_remote(config_dir: &str) -> bool {
+    config_dir.starts_with("http://") || config_dir.starts_with("https://")
+}
+
+/// Download the gzipped tarball at `url` and extract it into a temporary dir
+/// which is removed once the returned handle is dropped.
+///
+/// The contents are verified against the hex encoded `sha256` checksum if given.
+pub(crate) fn fetch_config(url: &str, sha256: Option<&str>) -> anyhow::Result<TempDir> {
+    info!("Fetching config from {url}...");
+
+    let response = match ureq::get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => {
+            return Err(anyhow!(
+                "Fetching config from {url} failed with HTTP status {status}"
+            ))
+        }
+        Err(err) => return Err(anyhow!(err).context(format!("Fetching config from {url}"))),
+    };
+
+    let mut contents = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut contents)
+        .context("Downloading config")?;
+
+    if let Some(expected) = sha256 {
+        let actual = Sha256::digest(&contents)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(anyhow!(
+                "Checksum mismatch for config from {url}: expected {expected}, got {actual}"
+            ));
+        }
+    }
+
+    crate::archive::extract(contents.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use sha2::{Digest, Sha256};
+
+    use crate::archive::write_archive;
+    use crate::remote::{fetch_config, is_remote};
+
+    /// Serve a single HTTP response on a local port, returning the URL to request.
+    fn serve(status: &str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/config.tar.gz", listener.local_addr().unwrap());
+        let status = status.to_owned();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+
+            let header = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        url
+    }
+
+    fn tarball() -> Vec<u8> {
+        let mut tarball = Vec::new();
+        write_archive(
+            &mut tarball,
+            &[(
+                "_all/eth0.nmconnection".to_string(),
+                "[connection]\nid=eth0\ntype=ethernet\n".to_string(),
+                0o600,
+            )],
+        )
+        .unwrap();
+
+        tarball
+    }
+
+    #[test]
+    fn detect_remote_config_dir() {
+        assert!(is_remote("https://example.com/config.tar.gz"));
+        assert!(is_remote("http://10.0.0.1/config.tar.gz"));
+        assert!(!is_remote("config"));
+    }
+
+    #[test]
+    fn fetch_config_successfully() {
+        let body = tarball();
+        let checksum = Sha256::digest(&body)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        let dir = fetch_config(&serve("200 OK", body), Some(&checksum)).unwrap();
+        assert!(dir.path().join("_all").join("eth0.nmconnection").exists());
+    }
+
+    #[test]
+    fn fetch_config_fails_due_to_checksum_mismatch() {
+        let url = serve("200 OK", tarball());
+
+        let error = fetch_config(&url, Some("abc")).unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "Checksum mismatch for config from {url}: expected abc"
+        )));
+    }
+
+    #[test]
+    fn fetch_config_fails_due_to_http_status() {
+        let url = serve("404 Not Found", Vec::new());
+
+        let error = fetch_config(&url, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Fetching config from {url} failed with HTTP status 404")
+        );
+    }
+}
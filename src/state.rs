@@ -0,0 +1,137 @@
+//! Persistent record of the last config bundle applied by `apply`, used for drift detection.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::generate_conf::find_config_files;
+
+/// Default location of the file recording the last applied config.
+pub const STATE_FILE: &str = "/var/lib/nmc/state.json";
+
+/// Outcome of the last successful `apply`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct ApplyState {
+    /// Identified host, not set for unified configurations.
+    pub hostname: Option<String>,
+    /// SHA-256 checksum (hex encoded) of the applied config bundle.
+    pub config_hash: String,
+    /// Time of the apply in seconds since the Unix epoch.
+    pub applied_at: u64,
+}
+
+impl ApplyState {
+    pub(crate) fn new(hostname: Option<String>, config_hash: String) -> Self {
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            hostname,
+            config_hash,
+            applied_at,
+        }
+    }
+}
+
+/// Hash the relative paths and contents of all files within the config dir.
+pub(crate) fn config_hash(config_dir: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+
+    for path in find_config_files(config_dir).context("Reading config dir")? {
+        let contents = fs::read(&path).context(format!("Reading {path:?}"))?;
+        let relative = path.strip_prefix(config_dir).unwrap_or(&path);
+
+        hasher.update(relative.as_os_str().as_encoded_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Read the recorded state, returning `None` if nothing was applied yet.
+pub(crate) fn read_state(path: &Path) -> anyhow::Result<Option<ApplyState>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Reading state file {path:?}")),
+    };
+
+    serde_json::from_str(&contents)
+        .map(Some)
+        .context(format!("Parsing state file {path:?}"))
+}
+
+/// Record the state, creating its parent dir if necessary.
+pub(crate) fn write_state(path: &Path, state: &ApplyState) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(format!("Creating {dir:?} directory"))?;
+    }
+
+    let contents = serde_json::to_string_pretty(state).context("Serializing state")?;
+    fs::write(path, contents).context(format!("Writing state file {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::state::{config_hash, read_state, write_state, ApplyState};
+
+    #[test]
+    fn write_and_read_state() -> Result<(), anyhow::Error> {
+        let path = Path::new("_state").join("state.json");
+        assert_eq!(read_state(&path)?, None);
+
+        let state = ApplyState::new(Some("node1".to_string()), "abc".to_string());
+        write_state(&path, &state)?;
+        assert_eq!(read_state(&path)?, Some(state));
+
+        fs::write(&path, "<invalid>")?;
+        assert!(read_state(&path).is_err());
+
+        // cleanup
+        fs::remove_dir_all("_state")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_hash_covers_paths_and_contents() -> Result<(), anyhow::Error> {
+        let config_dir = Path::new("_state-config");
+        fs::create_dir_all(config_dir.join("node1"))?;
+        fs::write(config_dir.join("node1/eth0.nmconnection"), "[connection]\n")?;
+
+        let hash = config_hash(config_dir)?;
+        assert_eq!(hash.len(), 64);
+        assert_eq!(config_hash(config_dir)?, hash);
+
+        fs::write(
+            config_dir.join("node1/eth0.nmconnection"),
+            "[connection]\nid=eth0\n",
+        )?;
+        let modified = config_hash(config_dir)?;
+        assert_ne!(modified, hash);
+
+        fs::rename(config_dir.join("node1"), config_dir.join("node2"))?;
+        assert_ne!(config_hash(config_dir)?, modified);
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Wall-clock instrumentation of the major phases of a command.
+
+use std::time::{Duration, Instant};
+
+/// Durations of the measured phases in order of completion.
+#[derive(Default)]
+pub(crate) struct Timings {
+    phases: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    /// Run `f` and record its wall-clock duration under `phase`.
+    pub(crate) fn measure<T>(&mut self, phase: impl Into<String>, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((phase.into(), start.elapsed()));
+        result
+    }
+
+    /// Append the phases measured by `other`, e.g. on a worker thread.
+    pub(crate) fn extend(&mut self, other: Timings) {
+        self.phases.extend(other.phases);
+    }
+
+    /// Render a human readable breakdown of all measured phases.
+    pub(crate) fn summary(&self) -> String {
+        let total: Duration = self.phases.iter().map(|(_, duration)| *duration).sum();
+
+        let mut summary = format!("Timing summary (total {total:.2?}):");
+        for (phase, duration) in &self.phases {
+            summary.push_str(&format!("\n  {phase}: {duration:.2?}"));
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timing::Timings;
+
+    #[test]
+    fn summary_lists_measured_phases() {
+        let mut timings = Timings::default();
+
+        assert_eq!(timings.measure("parse", || 42), 42);
+        timings.measure("store node1", || {});
+
+        let summary = timings.summary();
+        let lines: Vec<&str> = summary.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Timing summary (total "));
+        assert!(lines[1].starts_with("  parse: "));
+        assert!(lines[2].starts_with("  store node1: "));
+    }
+}
@@ -1,20 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::info;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct Host {
-    pub(crate) hostname: String,
-    pub(crate) interfaces: Vec<Interface>,
+    pub hostname: String,
+    /// Identifies the host by all of its MAC addresses without storing them
+    /// in the `<salt>:<hex digest>` format (see `apply_conf::mac_set_hash`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub mac_set_hash: Option<String>,
+    /// Identifies the host by its DMI product serial, taking precedence over the MAC addresses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Rollout group of the host (e.g. `workers`), hosts of a group are kept together in the apply order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Rollout priority of the host, lower priorities are applied first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub priority: Option<i32>,
+    pub interfaces: Vec<Interface>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct Interface {
-    pub(crate) logical_name: String,
+    pub logical_name: String,
     #[serde(default)]
-    pub(crate) connection_ids: Vec<String>,
+    pub connection_ids: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub(crate) mac_address: Option<String>,
-    pub(crate) interface_type: String,
+    pub mac_address: Option<String>,
+    #[serde(default = "default_interface_type")]
+    pub interface_type: InterfaceType,
+    /// Ignore the interface when identifying the host (e.g. transient USB NICs).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[serde(default)]
+    pub exclude_from_match: bool,
+    /// Interface deliberately left unmanaged by NetworkManager (e.g. bound to DPDK).
+    /// It is only recorded for documentation purposes and has no connection files.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[serde(default)]
+    pub unmanaged: bool,
+    /// MAC addresses of the ports of a bond, any of which identifies the host.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub member_mac_addresses: Vec<String>,
+}
+
+/// Type of a preconfigured interface, (de)serialized as the respective nmstate type name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum InterfaceType {
+    Ethernet,
+    Vlan,
+    Bond,
+    LinuxBridge,
+    OvsBridge,
+    /// Any other nmstate type (e.g. `ovs-interface`, `mac-vlan`) which requires no special handling.
+    Other(String),
+}
+
+impl InterfaceType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            InterfaceType::Ethernet => "ethernet",
+            InterfaceType::Vlan => "vlan",
+            InterfaceType::Bond => "bond",
+            InterfaceType::LinuxBridge => "linux-bridge",
+            InterfaceType::OvsBridge => "ovs-bridge",
+            InterfaceType::Other(interface_type) => interface_type,
+        }
+    }
+}
+
+impl From<String> for InterfaceType {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "ethernet" => InterfaceType::Ethernet,
+            "vlan" => InterfaceType::Vlan,
+            "bond" => InterfaceType::Bond,
+            "linux-bridge" => InterfaceType::LinuxBridge,
+            "ovs-bridge" => InterfaceType::OvsBridge,
+            _ => InterfaceType::Other(value),
+        }
+    }
+}
+
+impl From<&str> for InterfaceType {
+    fn from(value: &str) -> Self {
+        value.to_owned().into()
+    }
+}
+
+impl From<nmstate::InterfaceType> for InterfaceType {
+    fn from(value: nmstate::InterfaceType) -> Self {
+        value.to_string().into()
+    }
+}
+
+impl From<InterfaceType> for String {
+    fn from(value: InterfaceType) -> Self {
+        value.as_str().to_owned()
+    }
+}
+
+impl fmt::Display for InterfaceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reject Ethernet interfaces sharing a MAC address as they could not be told apart when renaming them.
+///
+/// Virtual interfaces (e.g. bonds) are not taken into account since they commonly inherit the address of a port.
+pub(crate) fn validate_unique_mac_addresses(interfaces: &[Interface]) -> anyhow::Result<()> {
+    let mut interfaces_by_mac: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for interface in interfaces
+        .iter()
+        .filter(|i| i.interface_type == InterfaceType::Ethernet)
+    {
+        if let Some(mac) = &interface.mac_address {
+            interfaces_by_mac
+                .entry(mac.trim().replace('-', ":").to_lowercase())
+                .or_default()
+                .push(&interface.logical_name);
+        }
+    }
+
+    let duplicates: Vec<String> = interfaces_by_mac
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(mac, names)| format!("{mac} ({})", names.join(", ")))
+        .collect();
+
+    if !duplicates.is_empty() {
+        return Err(anyhow!(
+            "Detected interfaces sharing MAC addresses: {}",
+            duplicates.join("; ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Interface type assumed for hand-written mappings omitting it.
+fn default_interface_type() -> InterfaceType {
+    let interface_type = InterfaceType::Ethernet;
+    info!("Interface type not specified, defaulting to '{interface_type}'");
+    interface_type
+}
+
+/// Machine readable summary of the changes made by `apply`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ApplyReport {
+    /// Identified host, not set for unified configurations or if none of the hosts match.
+    pub hostname: Option<String>,
+    pub interfaces: Vec<InterfaceReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct InterfaceReport {
+    pub logical_name: String,
+    pub local_name: String,
+    /// Source keyfiles relative to the config dir.
+    pub source_keyfiles: Vec<String>,
+    pub destination_keyfiles: Vec<PathBuf>,
+    pub renamed: bool,
+}
+
+impl fmt::Display for InterfaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} (from {})",
+            self.logical_name,
+            self.local_name,
+            self.source_keyfiles.join(", ")
+        )
+    }
 }
@@ -0,0 +1,238 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use configparser::ini::Ini;
+use log::{error, info};
+use rayon::prelude::*;
+
+use crate::error::ConfigError;
+use crate::generate_conf::{
+    find_network_configs, generate_config, mapping_file_path, override_hostname, read_mapping,
+    unified_config_file, validate_referenced_files, GenerateOptions,
+};
+use crate::merge_conf::merge_documents;
+
+/// Run the `generate` pipeline for all YAML files in the `config_dir` without writing any output.
+///
+/// Contrary to `generate` all files are validated even if some of them fail,
+/// the outcome is logged per file and an error is returned if any of them is invalid.
+pub(crate) fn validate(config_dir: &str, options: &GenerateOptions) -> Result<(), ConfigError> {
+    let paths = find_network_configs(Path::new(config_dir)).context("Reading config dir")?;
+
+    if paths.is_empty() {
+        return Err(ConfigError::EmptyConfigDir);
+    }
+
+    // Unified configurations (and the overrides layered on them) are not bound to specific NICs.
+    let unified = unified_config_file(config_dir, &paths)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .context("Creating validation thread pool")?;
+
+    // Outcomes are logged in input order once all files have been validated.
+    let results: Vec<_> = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| validate_file(path, unified.as_deref(), options))
+            .collect()
+    });
+
+    let mut failed = Vec::new();
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(..) => info!("{path:?}: OK"),
+            Err(err) => {
+                error!("{path:?}: {err:#}");
+                failed.push(path);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(ConfigError::Other(anyhow!(
+            "Validation failed for {} of {} files: {}",
+            failed.len(),
+            paths.len(),
+            failed
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Cross-check the output of `generate` stored in `output_dir`: every connection id in the host mapping
+/// must have a keyfile in the respective host dir and every keyfile must be referenced by the mapping.
+///
+/// Loopback keyfiles are not part of the mapping and therefore ignored.
+pub(crate) fn validate_mapping_consistency(output_dir: &str) -> Result<(), ConfigError> {
+    let mapping = mapping_file_path(Path::new(output_dir));
+    let hosts = read_mapping(&mapping).context(format!("Reading {mapping:?}"))?;
+
+    let mut inconsistencies = Vec::new();
+    for host in &hosts {
+        let host_dir = Path::new(output_dir).join(&host.hostname);
+
+        let mut keyfiles = BTreeSet::new();
+        for entry in fs::read_dir(&host_dir).context(format!("Reading {host_dir:?}"))? {
+            let path = entry.context("Reading host dir")?.path();
+            if path.extension() != Some(OsStr::new("nmconnection")) {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path).context(format!("Reading {path:?}"))?;
+            let mut c = Ini::new_cs();
+            c.set_comment_symbols(&['#']);
+            c.read(contents).map_err(|e| anyhow!(e))?;
+            if c.get("connection", "type").is_some_and(|t| t == "loopback") {
+                continue;
+            }
+
+            if let Some(stem) = path.file_stem().and_then(OsStr::to_str) {
+                keyfiles.insert(stem.to_owned());
+            }
+        }
+
+        let connection_ids: BTreeSet<String> = host
+            .interfaces
+            .iter()
+            .flat_map(|i| i.connection_ids.iter().cloned())
+            .collect();
+
+        inconsistencies.extend(
+            connection_ids
+                .difference(&keyfiles)
+                .map(|id| format!("{}: missing keyfile for connection '{id}'", host.hostname)),
+        );
+        inconsistencies.extend(
+            keyfiles
+                .difference(&connection_ids)
+                .map(|id| format!("{}: orphaned keyfile '{id}.nmconnection'", host.hostname)),
+        );
+    }
+
+    if !inconsistencies.is_empty() {
+        return Err(ConfigError::Other(anyhow!(
+            "Detected inconsistencies between host mapping and keyfiles: {}",
+            inconsistencies.join("; ")
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_file(
+    path: &Path,
+    unified: Option<&Path>,
+    options: &GenerateOptions,
+) -> anyhow::Result<()> {
+    let mut data = fs::read_to_string(path).context("Reading network config")?;
+    match (unified, override_hostname(path)) {
+        (Some(unified), Some(..)) => {
+            let base = fs::read_to_string(unified).context("Reading unified config")?;
+            data = merge_documents(&base, &data, options.merge_policy)
+                .context(format!("Merging into {unified:?}"))?;
+        }
+        (None, Some(..)) => {
+            return Err(anyhow!(
+                "Override config requires a unified config to be layered on"
+            ))
+        }
+        _ => {}
+    }
+
+    let source = path.file_name().and_then(OsStr::to_str);
+    let (_, config) =
+        generate_config(source.unwrap_or_default(), data, unified.is_none(), options)?;
+    validate_referenced_files(&config, path.parent().unwrap_or(Path::new(".")))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::generate_conf::{generate, GenerateOptions};
+    use crate::validate_conf::{validate, validate_mapping_consistency};
+
+    #[test]
+    fn validate_successfully() {
+        assert!(validate("testdata/generate/config", &GenerateOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn validate_reports_all_invalid_files() -> Result<(), anyhow::Error> {
+        let config_dir = "_validate-config";
+        fs::create_dir_all(config_dir)?;
+        fs::copy(
+            "testdata/generate/config/node1.yaml",
+            Path::new(config_dir).join("node1.yaml"),
+        )?;
+        fs::write(Path::new(config_dir).join("node2.yaml"), "<invalid>")?;
+        fs::write(
+            Path::new(config_dir).join("node3.yaml"),
+            "interfaces:\n  - name: eth0\n    type: ethernet\n    state: up\n",
+        )?;
+
+        let error = validate(config_dir, &GenerateOptions::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Validation failed for 2 of 3 files: _validate-config/node2.yaml, _validate-config/node3.yaml"
+        );
+
+        // The failures are reported in input order regardless of the number of jobs.
+        let options = GenerateOptions {
+            jobs: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate(config_dir, &options).unwrap_err().to_string(),
+            error.to_string()
+        );
+
+        // cleanup
+        fs::remove_dir_all(config_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_mapping_consistency_detects_orphans() -> Result<(), anyhow::Error> {
+        let out_dir = "_consistency-out";
+        generate(
+            "testdata/generate/config",
+            out_dir,
+            &GenerateOptions::default(),
+        )?;
+        validate_mapping_consistency(out_dir)?;
+
+        let host_dir = Path::new(out_dir).join("node1");
+        fs::write(
+            host_dir.join("eth9.nmconnection"),
+            "[connection]\nid=eth9\ntype=ethernet\n",
+        )?;
+        fs::remove_file(host_dir.join("eth0.nmconnection"))?;
+
+        let error = validate_mapping_consistency(out_dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Detected inconsistencies between host mapping and keyfiles: \
+             node1: missing keyfile for connection 'eth0'; node1: orphaned keyfile 'eth9.nmconnection'"
+        );
+
+        // cleanup
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+}